@@ -0,0 +1,357 @@
+use bevy::prelude::{Quat, Vec3};
+use ndarray::ArrayBase;
+use rand::RngCore;
+use rusty_gym::{Action, EpisodeData, GymEnvironment, InfoValue, ReplayableGymEnvironment, Reward, StepInfo};
+use xp_tools::rng::from_seed;
+use crate::{
+  env::LineRider3DEnv, simulator::LineRiderSimulationResult,
+  util::{consts::*, degree_to_radians, range::Range3D, types::ActionType}
+};
+
+/** Samples of the rider trajectory per simulation when planning with simulate and trajectory_stride is 0 */
+pub const PLANNING_TRAJECTORY_STRIDE: usize = 10;
+/** Score of reaching a target, higher than any distance within the build range so that progress always wins */
+pub const TARGET_REACHED_SCORE: f32 = 100.0;
+
+/**
+ * Parameters of plan_track. Every step each beam is extended by yaw_offsets * pitch_offsets * track_types candidate pieces,
+ * turned away from the straight line to the next target by the given degrees, and the best beam_width tracks are kept.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamSearchParams {
+  pub beam_width: usize,
+  pub yaw_offsets: Vec<f32>,
+  pub pitch_offsets: Vec<f32>,
+  /** Only used for ActionType::FreePointsWithTpRelative */
+  pub track_types: Vec<u8>,
+  pub piece_length: f32,
+  /** Scores candidates by simulating the rider on the partial track instead of through the cheap surrogate */
+  pub simulate: bool,
+  /** Steps of every simulation while planning, 0 uses simulation_steps of the config */
+  pub simulation_steps: usize,
+  /** Height the surrogate assumes the rider can gain per booster piece */
+  pub surrogate_boost_height: f32
+}
+
+impl Default for BeamSearchParams {
+  fn default() -> Self {
+    BeamSearchParams {
+      beam_width: 4,
+      yaw_offsets: vec![-30.0, 0.0, 30.0],
+      pitch_offsets: vec![-30.0, 0.0, 30.0],
+      track_types: vec![TP_NORMAL, TP_ACCELERATE],
+      piece_length: 1.0,
+      simulate: true,
+      simulation_steps: 0,
+      surrogate_boost_height: 1.0
+    }
+  }
+}
+
+impl BeamSearchParams {
+  /** Candidate pieces per beam and step, K */
+  pub fn candidate_count(&self, action_type: ActionType) -> usize {
+    let track_types = if action_type == ActionType::FreePointsWithTpRelative {self.track_types.len()} else {1};
+    self.yaw_offsets.len() * self.pitch_offsets.len() * track_types
+  }
+}
+
+#[derive(Debug, Clone)]
+struct Beam {
+  actions: Vec<Action>,
+  /** Targets the track reached so far */
+  progress: usize,
+  score: f32
+}
+
+/** Checkpoints the track has to reach in the order of the env followed by the goal */
+fn track_targets(env: &LineRider3DEnv) -> Vec<(Vec3, Range3D<f32>)> {
+  let config = &env.sim.config;
+  let mut targets: Vec<(Vec3, Range3D<f32>)> = if config.with_checkpoint_sequence() {
+    config.checkpoint_positions.iter().cloned().zip(config.checkpoint_ranges.iter().cloned()).collect()
+  } else if config.with_checkpoint() {
+    vec![(config.checkpoint_pos, config.checkpoint_range.clone())]
+  } else {
+    Vec::new()
+  };
+  targets.push((config.goal_pos, config.goal_position.clone()));
+  targets
+}
+
+/** Places the piece of a relative action the same way LineRider3DEnv::step does */
+fn place(env: &mut LineRider3DEnv, action: &Action) -> bool {
+  let track_type = if env.sim.config.action_type == ActionType::FreePointsWithTpRelative {action[3] as u8} else {TP_NORMAL};
+  let last_point = env.lines[env.lines.len()-1].0;
+  env.add_line_for_point((last_point + Vec3::new(action[0] as f32, action[1] as f32, action[2] as f32), track_type))
+}
+
+fn rewind(env: &mut LineRider3DEnv, root_history_len: usize) {
+  while env.undo_history.len() > root_history_len && env.undo_last_piece() {}
+}
+
+fn candidates(env: &LineRider3DEnv, params: &BeamSearchParams, target: Vec3) -> Vec<Action> {
+  let last_point = env.lines[env.lines.len()-1].0;
+  let to_target = target - last_point;
+  let base = if to_target.length_squared() > f32::EPSILON {to_target.normalize()} else {env.heading()};
+  let side = base.cross(Vec3::Y);
+  let side = if side.length_squared() > f32::EPSILON {side.normalize()} else {Vec3::Z};
+  let with_track_type = env.sim.config.action_type == ActionType::FreePointsWithTpRelative;
+  let track_types = if with_track_type {params.track_types.clone()} else {vec![TP_NORMAL]};
+  let mut actions = Vec::with_capacity(params.candidate_count(env.sim.config.action_type));
+  for yaw in &params.yaw_offsets {
+    for pitch in &params.pitch_offsets {
+      let rotation = Quat::from_axis_angle(Vec3::Y, degree_to_radians(*yaw)) * Quat::from_axis_angle(side, degree_to_radians(*pitch));
+      let direction = rotation * base * params.piece_length;
+      for track_type in &track_types {
+        let mut action = vec![direction.x as f64, direction.y as f64, direction.z as f64];
+        if with_track_type {
+          action.push(*track_type as f64);
+        }
+        actions.push(ArrayBase::from(action).into_dyn());
+      }
+    }
+  }
+  actions
+}
+
+fn simulate(env: &mut LineRider3DEnv, steps: usize) -> LineRiderSimulationResult {
+  env.add_lines_freeroam();
+  let sim_res = env.sim.simulate_till_end(steps);
+  env.reset_simulation_only();
+  sim_res
+}
+
+/**
+ * Higher is better. Reached targets count most, then how close the rider (or for the surrogate the track) gets to the next one.
+ * The surrogate punishes points the rider can't climb to with the energy of the start height and the boosters before them.
+ */
+fn score(env: &mut LineRider3DEnv, params: &BeamSearchParams, targets: &[(Vec3, Range3D<f32>)], progress: usize, simulation_steps: usize) -> f32 {
+  let last_point = env.lines[env.lines.len()-1].0;
+  let track_target = targets[progress.min(targets.len()-1)].0;
+  let track_score = TARGET_REACHED_SCORE * progress as f32 - last_point.distance(track_target);
+  if params.simulate {
+    let sim_res = simulate(env, simulation_steps);
+    let rider_progress = if env.sim.config.with_checkpoint_sequence() {
+      sim_res.checkpoints_reached
+    } else {
+      (env.sim.config.with_checkpoint() && sim_res.checkpoint_reached) as usize
+    } + sim_res.goal_reached as usize;
+    let rider_target = targets[rider_progress.min(targets.len()-1)].0;
+    let rider_distance = sim_res.trajectory.iter().map(|sample| Vec3::from_array(sample.position).distance(rider_target)).reduce(f32::min).unwrap_or(sim_res.closest_to_goal);
+    TARGET_REACHED_SCORE * rider_progress as f32 - rider_distance + 0.1 * track_score
+  } else {
+    let start_height = env.lines[0].0.y;
+    let mut boosters = 0;
+    let mut missing_height = 0.0;
+    for (point, track_type) in env.lines.iter().skip(1) {
+      missing_height += (point.y - start_height - boosters as f32 * params.surrogate_boost_height).max(0.0);
+      if *track_type == TP_ACCELERATE {
+        boosters += 1;
+      }
+    }
+    track_score - TARGET_REACHED_SCORE * missing_height
+  }
+}
+
+/**
+ * Plans the remaining pieces of the track of env through beam search and returns the actions of the best plan.
+ * The track the plan ends up with reaches the goal, or else comes closest to it. env is left in the state it was in.
+ * Supports the relative free point action types, the plan ends early once the track reaches the goal.
+ */
+pub fn plan_track(env: &mut LineRider3DEnv, params: &BeamSearchParams) -> Vec<Action> {
+  let action_type = env.sim.config.action_type;
+  if !matches!(action_type, ActionType::FreePointsRelative | ActionType::FreePointsWithTpRelative) {
+    panic!("Beam search can't handle action_type {}", action_type);
+  }
+  let targets = track_targets(env);
+  let state = env.expert_state();
+  let root_history_len = env.undo_history.len();
+  let simulation_steps = if params.simulation_steps > 0 {params.simulation_steps} else {env.sim.config.simulation_steps};
+  let trajectory_stride = env.sim.config.trajectory_stride;
+  if params.simulate && trajectory_stride == 0 {
+    env.sim.config.trajectory_stride = PLANNING_TRAJECTORY_STRIDE;
+  }
+  let root_progress = if state.all_checkpoints_reached() {targets.len()-1} else {state.checkpoints_reached};
+  let mut beams = vec![Beam {actions: Vec::new(), progress: root_progress, score: f32::NEG_INFINITY}];
+  for _ in 0..state.remaining_steps {
+    let mut expanded: Vec<Beam> = Vec::with_capacity(beams.len() * params.candidate_count(action_type));
+    for beam in &beams {
+      if beam.progress >= targets.len() {
+        expanded.push(beam.clone());
+        continue;
+      }
+      rewind(env, root_history_len);
+      for action in &beam.actions {
+        place(env, action);
+      }
+      for action in candidates(env, params, targets[beam.progress].0) {
+        if !place(env, &action) {
+          continue;
+        }
+        let last_point = env.lines[env.lines.len()-1].0;
+        let progress = if targets[beam.progress].1.vec3_in_range(&last_point) {beam.progress + 1} else {beam.progress};
+        let candidate_score = score(env, params, &targets, progress, simulation_steps);
+        env.undo_last_piece();
+        let mut actions = beam.actions.clone();
+        actions.push(action);
+        expanded.push(Beam {actions, progress, score: candidate_score});
+      }
+    }
+    if expanded.is_empty() {
+      break;
+    }
+    expanded.sort_by(|a, b| b.score.total_cmp(&a.score));
+    expanded.truncate(params.beam_width.max(1));
+    let all_finished = expanded.iter().all(|beam| beam.progress >= targets.len());
+    beams = expanded;
+    if all_finished {
+      break;
+    }
+  }
+  rewind(env, root_history_len);
+  env.sim.config.trajectory_stride = trajectory_stride;
+  beams.into_iter().next().map(|beam| beam.actions).unwrap_or_default()
+}
+
+/** Whether the info of the last step of an episode reports that the rider reached the goal */
+pub fn simulation_goal_reached(info: &StepInfo) -> bool {
+  match info.get("simulation_result") {
+    Some(InfoValue::Map(sim_res)) => sim_res.get("goal_reached") == Some(&InfoValue::Bool(true)),
+    _ => false
+  }
+}
+
+/** An episode played by following plan_track */
+#[derive(Debug, Clone)]
+pub struct Demonstration {
+  pub episode: EpisodeData,
+  pub reward: Reward,
+  pub goal_reached: bool
+}
+
+/**
+ * Piece of piece_length along the heading of the track, empty for action types with a track type.
+ * Continues a track whose plan is used up without changing the ride to the targets.
+ */
+fn continue_along_heading(env: &LineRider3DEnv, piece_length: f32) -> Action {
+  let direction = env.heading() * piece_length;
+  let mut action = vec![direction.x as f64, direction.y as f64, direction.z as f64];
+  if env.sim.config.action_type == ActionType::FreePointsWithTpRelative {
+    action.push(TP_EMPTY as f64);
+  }
+  ArrayBase::from(action).into_dyn()
+}
+
+/**
+ * Plays episode_amount episodes with fresh seeds by planning the whole track with plan_track at the start of each.
+ * Once the plan is used up, pieces along the heading fill the remaining steps. The episodes can be replayed like recorded ones.
+ */
+pub fn record_demonstrations(env: &mut LineRider3DEnv, params: &BeamSearchParams, episode_amount: usize, seed: Option<u64>) -> Vec<Demonstration> {
+  let (mut rng, _) = from_seed(seed);
+  (0..episode_amount).map(|_| {
+    let episode_seed = rng.next_u64();
+    env.use_seed(episode_seed);
+    env.reset();
+    let plan = plan_track(env, params);
+    let mut log: Vec<Action> = Vec::with_capacity(plan.len() + 1);
+    let mut step_index = 0;
+    loop {
+      let action = plan.get(step_index).cloned().unwrap_or_else(|| continue_along_heading(env, params.piece_length));
+      let step = env.step(&action);
+      log.push(action);
+      step_index += 1;
+      if step.is_done {
+        let goal_reached = simulation_goal_reached(&step.info);
+        let mut episode = EpisodeData::new(episode_seed, log);
+        episode.env_params = Some(env.get_config());
+        return Demonstration {episode, reward: step.reward, goal_reached};
+      }
+    }
+  }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::simulator::LineRiderSim;
+
+  fn make_env() -> LineRider3DEnv {
+    let mut sim = LineRiderSim::default();
+    sim.config.action_type = ActionType::FreePointsWithTpRelative;
+    sim.config.step_limit = 6;
+    let mut env = LineRider3DEnv::new(sim, None);
+    env.use_seed(42);
+    env.sim.set_max_width(10.0);
+    env.sim.set_goal_position(Vec3::new(3.0, -1.0, 1.0));
+    env
+  }
+
+  #[test]
+  fn surrogate_plan_reaches_goal_and_restores_env() {
+    let mut env = make_env();
+    let params = BeamSearchParams {simulate: false, beam_width: 2, ..Default::default()};
+    assert_eq!(params.candidate_count(ActionType::FreePointsWithTpRelative), 18);
+    assert_eq!(params.candidate_count(ActionType::FreePointsRelative), 9);
+    let lines_before = env.lines.clone();
+    let plan = plan_track(&mut env, &params);
+    assert!(!plan.is_empty() && plan.len() <= 6);
+    assert_eq!(env.lines, lines_before);
+    assert!(env.undo_history.is_empty());
+    for action in &plan {
+      assert!(place(&mut env, action));
+    }
+    assert!(env.last_point_reaches_goal());
+    // the goal lies below the start, so the surrogate sees no reason to climb above it
+    assert!(env.lines.iter().all(|(point, _)| point.y <= env.lines[0].0.y + f32::EPSILON));
+  }
+
+  #[test]
+  fn replaying_the_plan_builds_the_planned_track() {
+    let mut env = make_env();
+    env.sim.config.step_limit = 8;
+    // lies in the way of the straight line to the goal, so that candidates get rejected while planning
+    env.sim.set_obstacles(vec![Range3D::new(1.0, 2.0, -1.5, 0.5, -0.5, 1.5)]);
+    let params = BeamSearchParams {simulate: false, beam_width: 3, ..Default::default()};
+    let plan = plan_track(&mut env, &params);
+    let mut planned_points = vec![env.lines[0].0];
+    for action in &plan {
+      let last_point = planned_points[planned_points.len()-1];
+      planned_points.push(last_point + Vec3::new(action[0] as f32, action[1] as f32, action[2] as f32));
+    }
+    let prev_points = env.prev_points;
+    assert!(!place(&mut env, &ArrayBase::from(vec![1.5, -0.5, 0.0, TP_NORMAL as f64]).into_dyn()));
+    assert_eq!(env.prev_points, prev_points);
+    for action in &plan {
+      let step = env.step(action);
+      assert_eq!(step.info.get("valid_action"), Some(&InfoValue::Bool(true)));
+    }
+    assert_eq!(env.lines.iter().map(|(point, _)| *point).collect::<Vec<Vec3>>(), planned_points);
+  }
+
+  #[test]
+  fn demonstrations_continue_along_the_heading() {
+    let mut env = make_env();
+    env.skip_simulation = true;
+    env.sim.config.step_limit = 12;
+    let params = BeamSearchParams {simulate: false, beam_width: 2, ..Default::default()};
+    let demonstrations = record_demonstrations(&mut env, &params, 1, Some(3));
+    let log = &demonstrations[0].episode.log;
+    assert_eq!(log.len(), 13);
+    assert!(log.iter().all(|action| action.iter().take(3).any(|value| *value != 0.0)));
+    let fillers = log.iter().filter(|action| action[3] == TP_EMPTY as f64).count();
+    assert!(fillers > 0);
+  }
+
+  #[test]
+  fn simulated_plan_scores_with_the_rider() {
+    let mut env = make_env();
+    env.sim.config.step_limit = 1;
+    let params = BeamSearchParams {
+      beam_width: 1, yaw_offsets: vec![0.0], pitch_offsets: vec![0.0], track_types: vec![TP_NORMAL], simulation_steps: 50, ..Default::default()
+    };
+    let plan = plan_track(&mut env, &params);
+    assert_eq!(plan.len(), 1);
+    assert_eq!(env.lines.len(), 1);
+    assert_eq!(env.sim.config.trajectory_stride, 0);
+  }
+}
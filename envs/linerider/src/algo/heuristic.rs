@@ -1,52 +1,32 @@
 use std::ops::Sub;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use bevy::prelude::Vec3;
 use ndarray::ArrayBase;
-use rusty_gym::{Observation, Action};
-
-use crate::util::consts::{ACTION_TYPE_FREE_POINTS_RELATIVE, ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE, TARGET_ABOVE_START, TARGET_SAME_HEIGHT_AS_START, TP_ACCELERATE, TP_NORMAL};
-
-pub const EMPTY_POINT_IN_OBSERVATION: [f64; 4] = [-1.0, -1.0, -1.0, -1.0];
+use serde::{Serialize, Deserialize};
+use rusty_gym::{Observation, Action, Reward, Space, ReplayableGymEnvironment, RlAlgorithm, SelfTrainingAlgo};
 
+use crate::{env::expert::ExpertState, util::{consts::{TP_ACCELERATE, TP_NORMAL}, types::{ActionType, TargetType}}};
 
 /**
- * Heuristic made for Target Type A to B with Action Space Relative
+ * Heuristic made for Target Type A to B with Action Space Relative, heads for the goal and ignores checkpoints
  */
-pub fn straight_line_heuristica(observation: &Observation, config: &HashMap<String, String>, multiplier: Option<f32>) -> Action {
-  let obs = observation.as_slice().expect("");
-  let obs_len = obs.len();
-  let mut current_pos = Vec3::ZERO;
-  let mut found_pos = false;
-  let mut current_index = 0;
-  'LAST_POS_SEARCH: while !found_pos {
-    if current_index+3 >= obs_len {
-      break 'LAST_POS_SEARCH;
-    }
-    let current_point = &obs[current_index..current_index+4];
-    if EMPTY_POINT_IN_OBSERVATION.eq(current_point) {
-      found_pos = true;
-      let previous_point = &obs[current_index-4..current_index];
-      current_pos = Vec3::new(previous_point[0] as f32, previous_point[1] as f32,previous_point[2] as f32);
-    }
-    current_index += 4;
-  }
-  let goal_pos_arr = &obs[obs_len-4..obs_len];
-  let goal_pos = Vec3::new(goal_pos_arr[0] as f32,goal_pos_arr[1] as f32, goal_pos_arr[2] as f32);
-  let mut direction = goal_pos.sub(current_pos).normalize();
+pub fn straight_line_heuristica(state: &ExpertState, config: &HashMap<String, String>, multiplier: Option<f32>) -> Action {
+  let mut direction = state.goal.sub(state.last_point).normalize();
   if let Some(dist_multiplier) = multiplier {
     direction *= dist_multiplier;
   }
   let action_type = config.get("action_type").expect("Action Type must be in Config");
-  let action_type_val = action_type.parse::<u8>().expect("Action Type is of u8 type");
+  let action_type_val = action_type.parse::<ActionType>().expect("Action Type is a known action type");
   match action_type_val {
-    ACTION_TYPE_FREE_POINTS_RELATIVE => {
+    ActionType::FreePointsRelative => {
       ArrayBase::from(vec![direction.x as f64, direction.y as f64, direction.z as f64]).into_dyn()
     },
-    ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE => {
+    ActionType::FreePointsWithTpRelative => {
       let target_type = config.get("target_type").expect("Target Type must be in Config");
-      let target_type_val = target_type.parse::<u8>().expect("Target Type is of u8 type");
+      let target_type_val = target_type.parse::<TargetType>().expect("Target Type is a known target type");
       let track_type = match target_type_val {
-        TARGET_ABOVE_START | TARGET_SAME_HEIGHT_AS_START => {TP_ACCELERATE},
+        TargetType::AboveStart | TargetType::SameHeightAsStart => {TP_ACCELERATE},
         _ => {TP_NORMAL}
       };
       ArrayBase::from(vec![direction.x as f64, direction.y as f64, direction.z as f64, track_type as f64]).into_dyn()
@@ -56,90 +36,218 @@ pub fn straight_line_heuristica(observation: &Observation, config: &HashMap<Stri
 }
 
 /**
- * Heuristic that includes checkopint
+ * Heuristic that includes checkopint, heads for the next checkpoint and then for the goal.
+ * A target closer than 0.9 counts as reached, places nothing once there is no target left.
  */
-pub fn straight_line_heuristic_general(observation: &Observation, config: &HashMap<String, String>, multiplier: Option<f32>) -> Action {
-  let obs = observation.as_slice().expect("");
-  let obs_len = obs.len();
-  let mut current_pos = Vec3::ZERO;
-  let mut goal_pos = Vec3::ZERO;
-  let mut found_pos = false;
-  let mut current_index = 0;
-  'LAST_POS_SEARCH: while !found_pos {
-    if current_index+3 >= obs_len {
-      break 'LAST_POS_SEARCH;
-    }
-    let current_point = &obs[current_index..current_index+4];
-    if EMPTY_POINT_IN_OBSERVATION.eq(current_point) {
-      found_pos = true;
-      let previous_point = &obs[current_index-4..current_index];
-      current_pos = Vec3::new(previous_point[0] as f32, previous_point[1] as f32,previous_point[2] as f32);
-    }
-    current_index += 4;
-  }
-  let mut found_pos = false;
-  'GOAL_POS_SEARCH: while !found_pos {
-    if current_index+3 >= obs_len {
-      break 'GOAL_POS_SEARCH;
-    }
-    let current_point = &obs[current_index..current_index+4];
-    if current_point[3] == 4.0 {
-      let potential_goal_pos = Vec3::new(current_point[0] as f32, current_point[1] as f32,current_point[2] as f32);
-      if potential_goal_pos.distance(current_pos) > 0.9 {
-        found_pos = true;
-        goal_pos = potential_goal_pos;
-        goal_pos.y -= 0.25;
-        goal_pos.x -= 0.25;
+pub fn straight_line_heuristic_general(state: &ExpertState, config: &HashMap<String, String>, multiplier: Option<f32>) -> Action {
+  let targets = if state.all_checkpoints_reached() {vec![state.goal]} else {vec![state.current_target, state.goal]};
+  let target_opt = targets.into_iter().find(|target| target.distance(state.last_point) > 0.9);
+  let action_type = config.get("action_type").expect("Action Type must be in Config");
+  let action_type_val = action_type.parse::<ActionType>().expect("Action Type is a known action type");
+  if let Some(mut goal_pos) = target_opt {
+    goal_pos.y -= 0.25;
+    goal_pos.x -= 0.25;
+    let mut direction = goal_pos.sub(state.last_point).normalize();
+    if let Some(dist_multiplier) = multiplier {
+      direction *= dist_multiplier;
+    }
+    match action_type_val {
+      ActionType::FreePointsRelative => {
+        ArrayBase::from(vec![direction.x as f64, direction.y as f64, direction.z as f64]).into_dyn()
+      },
+      ActionType::FreePointsWithTpRelative => {
+        let track_type = if state.last_point.y < goal_pos.y {TP_ACCELERATE} else {TP_NORMAL};
+        ArrayBase::from(vec![direction.x as f64, direction.y as f64, direction.z as f64, track_type as f64]).into_dyn()
       }
+      _ => {panic!("Heuristic can't handle action_type {}", action_type_val)}
     }
-    current_index += 4;
-  }
-  let action_type = config.get("action_type").expect("Action Type must be in Config");
-  let action_type_val = action_type.parse::<u8>().expect("Action Type is of u8 type");
-  if found_pos == false {
+  } else {
     match action_type_val {
-      ACTION_TYPE_FREE_POINTS_RELATIVE => {
+      ActionType::FreePointsRelative => {
         ArrayBase::from(vec![0.0, 0.0, 0.0]).into_dyn()
       },
-      ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE => {
+      ActionType::FreePointsWithTpRelative => {
         ArrayBase::from(vec![0.0, 0.0, 0.0, 0.0]).into_dyn()
       }
       _ => {panic!("Heuristic can't handle action_type {}", action_type_val)}
     }
-  } else {
+  }
+}
 
-    let mut direction = goal_pos.sub(current_pos).normalize();
-    if let Some(dist_multiplier) = multiplier {
-      direction *= dist_multiplier;
+/**
+ * Straight line towards the current target of the privileged ExpertState, works for every observation type.
+ * Boosts when the target lies above the last point and places nothing once the target is reached.
+ */
+pub fn straight_line_expert(state: &ExpertState, action_type: ActionType, multiplier: Option<f32>) -> Action {
+  let to_target = state.current_target - state.last_point;
+  let mut direction = if to_target.length_squared() > f32::EPSILON {to_target.normalize()} else {Vec3::ZERO};
+  if let Some(dist_multiplier) = multiplier {
+    direction *= dist_multiplier;
+  }
+  match action_type {
+    ActionType::FreePointsRelative => {
+      ArrayBase::from(vec![direction.x as f64, direction.y as f64, direction.z as f64]).into_dyn()
+    },
+    ActionType::FreePointsWithTpRelative => {
+      let track_type = if state.last_point.y < state.current_target.y {TP_ACCELERATE} else {TP_NORMAL};
+      ArrayBase::from(vec![direction.x as f64, direction.y as f64, direction.z as f64, track_type as f64]).into_dyn()
     }
-    match action_type_val {
-      ACTION_TYPE_FREE_POINTS_RELATIVE => {
-        ArrayBase::from(vec![direction.x as f64, direction.y as f64, direction.z as f64]).into_dyn()
-      },
-      ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE => {
-        let track_type = if current_pos.y < goal_pos.y {TP_ACCELERATE} else {TP_NORMAL};
-        ArrayBase::from(vec![direction.x as f64, direction.y as f64, direction.z as f64, track_type as f64]).into_dyn()
+    _ => {panic!("Expert can't handle action_type {}", action_type)}
+  }
+}
+
+/** Signature shared by the heuristics, the multiplier scales the length of the placed piece */
+pub type HeuristicFn = fn(&ExpertState, &HashMap<String, String>, Option<f32>) -> Action;
+
+/**
+ * Runs a heuristic as RlAlgorithm so that it can be evaluated and recorded like a learned agent.
+ * It acts on the expert state of the environment, which act_in_env reads, act alone can't be used.
+ * Training only plays the heuristic, for episodes episodes if set or else until the step budget is used up.
+ * The env config the heuristic needs is taken from the environment it is trained on and saved with it.
+ */
+pub struct HeuristicAlgorithm {
+  pub name: String,
+  pub heuristic: HeuristicFn,
+  pub multiplier: Option<f32>,
+  /** Replaces the track type the heuristic picked for action types with a track type */
+  pub track_type: Option<u8>,
+  pub episodes: Option<usize>,
+  pub env_config: HashMap<String, String>,
+  /** Reward of the last step of every training episode, shared so it can be read after the algorithm was handed over */
+  pub final_rewards: Arc<Mutex<Vec<Reward>>>,
+  /** Called with the reward of the last step of every training episode, e.g. to advance a progress bar */
+  pub on_episode_end: Option<Box<dyn FnMut(Reward)>>
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedHeuristic {
+  hyperparams: HashMap<String, String>,
+  env_config: HashMap<String, String>
+}
+
+impl HeuristicAlgorithm {
+  pub fn new(name: &str, heuristic: HeuristicFn) -> HeuristicAlgorithm {
+    HeuristicAlgorithm {
+      name: name.to_owned(), heuristic, multiplier: None, track_type: None, episodes: None,
+      env_config: HashMap::new(), final_rewards: Arc::new(Mutex::new(Vec::new())), on_episode_end: None
+    }
+  }
+  /** Action of the heuristic in state, with the track type replaced if one is set */
+  pub fn act_on_state(&self, state: &ExpertState) -> Action {
+    let mut action = (self.heuristic)(state, &self.env_config, self.multiplier);
+    if let (Some(track_type), 4) = (self.track_type, action.len()) {
+      action[3] = track_type as f64;
+    }
+    action
+  }
+  /** Amount of training episodes whose last reward is at least success_reward */
+  pub fn success_count(&self, success_reward: Reward) -> usize {
+    self.final_rewards.lock().unwrap().iter().filter(|reward| **reward >= success_reward).count()
+  }
+}
+
+impl RlAlgorithm for HeuristicAlgorithm {
+  fn act(&mut self, _obs: Observation) -> Action {
+    panic!("Heuristic {} acts on the expert state of the environment, use act_in_env", self.name)
+  }
+  fn act_in_env(&mut self, _obs: Observation, env: &mut dyn ReplayableGymEnvironment) -> Action {
+    let state = env.get_expert_state().and_then(|state| ExpertState::from_map(&state));
+    self.act_on_state(&state.unwrap_or_else(|| panic!("Heuristic {} needs an environment with an expert state", self.name)))
+  }
+  fn set_observation_shape(&mut self, _space: Space) {}
+  fn save(&mut self, save_path: &str) {
+    let saved = SavedHeuristic {hyperparams: self.get_hyperparams(), env_config: self.env_config.clone()};
+    std::fs::write(save_path, serde_json::to_string(&saved).expect("heuristic parameters are serializable")).expect("Able to write the heuristic parameters");
+  }
+  fn load(&mut self, load_path: &str) {
+    let content = std::fs::read_to_string(load_path).expect("Able to read the heuristic parameters");
+    let saved: SavedHeuristic = serde_json::from_str(&content).expect("heuristic parameters can be parsed");
+    self.load_hyperparams(&saved.hyperparams);
+    self.env_config = saved.env_config;
+  }
+  fn get_hyperparams(&mut self) -> HashMap<String, String> {
+    let mut hyperparams = HashMap::new();
+    hyperparams.insert("name".to_owned(), self.get_name());
+    if let Some(multiplier) = self.multiplier {
+      hyperparams.insert("multiplier".to_owned(), multiplier.to_string());
+    }
+    if let Some(track_type) = self.track_type {
+      hyperparams.insert("track_type".to_owned(), track_type.to_string());
+    }
+    if let Some(episodes) = self.episodes {
+      hyperparams.insert("episodes".to_owned(), episodes.to_string());
+    }
+    hyperparams
+  }
+  fn load_hyperparams(&mut self, config: &HashMap<String, String>) {
+    if let Some(multiplier) = config.get("multiplier") {
+      self.multiplier = Some(multiplier.parse().expect("multiplier is a number"));
+    }
+    if let Some(track_type) = config.get("track_type") {
+      self.track_type = Some(track_type.parse().expect("track_type is a track piece type"));
+    }
+    if let Some(episodes) = config.get("episodes") {
+      self.episodes = Some(episodes.parse().expect("episodes is a number"));
+    }
+  }
+  fn get_name(&self) -> String {
+    self.name.clone()
+  }
+  fn reset(&mut self) {
+    self.final_rewards.lock().unwrap().clear();
+  }
+}
+
+impl SelfTrainingAlgo for HeuristicAlgorithm {
+  fn train_on_env(&mut self, mut rust_env: Box<dyn ReplayableGymEnvironment>, seed: Option<u64>, steps: usize) {
+    self.env_config = rust_env.get_config();
+    if let Some(seed) = seed {
+      rust_env.use_seed(seed);
+    }
+    let mut obs = rust_env.reset();
+    let mut episode = 0;
+    let mut step_count = 0;
+    loop {
+      let action = self.act_in_env(obs, rust_env.as_mut());
+      let step = rust_env.step(&action);
+      step_count += 1;
+      if step.is_done {
+        self.final_rewards.lock().unwrap().push(step.reward);
+        if let Some(on_episode_end) = &mut self.on_episode_end {
+          on_episode_end(step.reward);
+        }
+        episode += 1;
+        // the recorder stores an episode on reset, so the last one is reset as well
+        obs = rust_env.reset();
+        let finished = match self.episodes {
+          Some(episodes) => episode >= episodes,
+          None => step_count >= steps
+        };
+        if finished {
+          break;
+        }
+      } else {
+        obs = step.obs;
       }
-      _ => {panic!("Heuristic can't handle action_type {}", action_type_val)}
     }
   }
 }
 
 #[cfg(test)]
 mod tests {
-  use crate::{env::LineRider3DEnv, simulator::LineRiderSim, util::consts::*, algo::heuristic::straight_line_heuristica};
-  use rusty_gym::{GymEnvironment, ReplayableGymEnvironment, GymRecorder};
+  use crate::{env::LineRider3DEnv, simulator::LineRiderSim, util::{types::*, consts::*}, algo::heuristic::*};
+  use rusty_gym::{GymEnvironment, ReplayableGymEnvironment, GymRecorder, RlAlgorithm, SelfTrainingAlgo};
   use bevy::prelude::Vec3;
   #[test]
   fn straight_line_heuristic() {
     let mut sim: LineRiderSim = LineRiderSim::default();
     sim.config.reward_type = vec![
-      REWARD_END_BUILD_PHASE_IF_TRACK_REACHES_GOAL, REWARD_DISTANCE_OF_TRACK_TO_GOAL_AT_END,
-      REWARD_TRACK_CLOSER_TO_GOAL_IN_STEP, REWARD_GOAL_REACHED_BY_BOTH_ONLY,
-      REWARD_GOAL_REACHED_BY_TRACK,  REWARD_SCOLD_INVALID_ACTION,
-      REWARD_DISTANCE_TO_GOAL_IN_SIMULATION_IF_TRACK_REACHED_GOAL]; 
-    sim.config.target_type = TARGET_RANDOM_START_AND_END;
-    sim.config.action_type = ACTION_TYPE_FREE_POINTS_RELATIVE;
+      RewardComponent::EndBuildPhaseIfTrackReachesGoal, RewardComponent::DistanceOfTrackToGoalAtEnd,
+      RewardComponent::TrackCloserToGoalInStep, RewardComponent::GoalReachedByBothOnly,
+      RewardComponent::GoalReachedByTrack,  RewardComponent::ScoldInvalidAction,
+      RewardComponent::DistanceToGoalInSimulationIfTrackReachedGoal]; 
+    sim.config.target_type = TargetType::RandomStartAndEnd;
+    sim.config.action_type = ActionType::FreePointsRelative;
     sim.config.step_limit = 50;
     sim.config.skip_collision_check_on_last_x_pieces = 999;
     sim.config.max_piece_length = 2.0;
@@ -152,16 +260,16 @@ mod tests {
     let mut rec = GymRecorder::new(Box::new(env), None);
     // env.use_seed(42);
     for _ in 0..2 {
-      let mut obs = rec.reset();
+      rec.reset();
       let mut done = false;
       // let mut last_reward = -1.0;
       while !done {
         let config = rec.get_config();
-        let action = &straight_line_heuristica(&obs, &config, None);
+        let state = ExpertState::from_map(&rec.get_expert_state().unwrap()).unwrap();
+        let action = &straight_line_heuristica(&state, &config, None);
         let res = rec.step(action);
         done = res.is_done;
         // last_reward = res.reward;
-        obs = res.obs;
       }
       // assert!(last_reward > 1.0);
     }
@@ -174,4 +282,47 @@ mod tests {
     // let mut new_run_data = RunData::new(RUNTYPE_TRAINING, name, "Heuristic".to_owned(), episodes, env_config.clone(), None, 0, Some("heuristic_solution".to_owned()), None);
     // save_cbor_and_flate_to_path("heuristic_solution.tlr", &new_run_data);
   }
+
+  #[test]
+  fn heuristic_algorithm_plays_episodes() {
+    let mut sim: LineRiderSim = LineRiderSim::default();
+    sim.config.target_type = TargetType::RandomStartAndEnd;
+    sim.config.action_type = ActionType::FreePointsWithTpRelative;
+    sim.config.step_limit = 5;
+    let env = LineRider3DEnv::new(sim, None);
+    let mut algo = HeuristicAlgorithm::new("heuristic_test", straight_line_heuristic_general);
+    let mut hyperparams = HashMap::new();
+    hyperparams.insert("episodes".to_owned(), "2".to_owned());
+    hyperparams.insert("track_type".to_owned(), TP_DECELERATE.to_string());
+    algo.load_hyperparams(&hyperparams);
+    let rec = GymRecorder::new(Box::new(env), None);
+    let episode_data = rec.data.clone();
+    let ended_episodes = Arc::new(Mutex::new(0));
+    let counter = ended_episodes.clone();
+    algo.on_episode_end = Some(Box::new(move |_| *counter.lock().unwrap() += 1));
+    algo.train_on_env(Box::new(rec), Some(3), 0);
+    assert_eq!(algo.final_rewards.lock().unwrap().len(), 2);
+    assert_eq!(*ended_episodes.lock().unwrap(), 2);
+    assert_eq!(episode_data.lock().unwrap().len(), 2);
+    let logged_action = episode_data.lock().unwrap().index(0).log[0].clone();
+    assert_eq!(logged_action[3], TP_DECELERATE as f64);
+    assert_eq!(algo.get_hyperparams().get("name"), Some(&"heuristic_test".to_owned()));
+  }
+
+  #[test]
+  fn heuristic_algorithm_acts_for_every_observation_type() {
+    for observation_type in [ObservationType::BuildPoints, ObservationType::SlidingWindow, ObservationType::GoalAndLastPoint, ObservationType::View3D] {
+      let mut sim: LineRiderSim = LineRiderSim::default();
+      sim.config.target_type = TargetType::RandomWithCheckpointBelow;
+      sim.config.action_type = ActionType::FreePointsRelative;
+      sim.config.observation_type = observation_type;
+      let mut env = LineRider3DEnv::new(sim, Some(5));
+      let obs = env.reset();
+      let mut algo = HeuristicAlgorithm::new("heuristic_test", straight_line_heuristic_general);
+      algo.env_config = env.get_config();
+      let expected = straight_line_heuristic_general(&env.expert_state(), &algo.env_config, None);
+      assert!(expected.iter().any(|value| *value != 0.0));
+      assert_eq!(algo.act_in_env(obs, &mut env), expected);
+    }
+  }
 }
@@ -1 +1,2 @@
-pub mod heuristic;
\ No newline at end of file
+pub mod heuristic;
+pub mod beam_search;
\ No newline at end of file
@@ -2,7 +2,7 @@
 
 use bevy::prelude::Vec3;
 use rusty_gym::{gym::GymEnvironment, Observation, ReplayableGymEnvironment};
-use linerider::{*, simulator::*, env::{tracks::{prepare_acc_jump, prepare_curvy_track, prepare_bezier_jump}, *}, algo::heuristic::straight_line_heuristic_general, util::consts::*};
+use linerider::{*, simulator::*, env::{tracks::{prepare_acc_jump, prepare_curvy_track, prepare_bezier_jump}, *}, algo::heuristic::straight_line_heuristic_general, util::{consts::*, types::*}};
 use ndarray::ArrayBase;
 // use simulator::*;
 // use util::{consts::*, track::*};
@@ -20,7 +20,7 @@ fn debug_main() {
     let mut app_config: Mut<LineRiderConfig> = env.sim.app.world.resource_mut();
     app_config.copy_from(&env.sim.config);
   }
-  env.sim.config.action_type = 5;
+  env.sim.config.action_type = ActionType::FreePointsRelative;
   // for i in 0..10 {
   //   env.add_line_for_point((Vec3::new(i as f32, -i as f32, i as f32 * 0.1), 1));
   // }
@@ -57,8 +57,8 @@ fn test_checkpoint() {
   sim.config.use_cylinder_track = true;
   sim.config.smooth_free_points = true;
   sim.config.bezier_resolution = 10;
-  sim.config.action_type = ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE;
-  sim.config.target_type = TARGET_RANDOM_WITH_CHECKPOINT_BELOW;
+  sim.config.action_type = ActionType::FreePointsWithTpRelative;
+  sim.config.target_type = TargetType::RandomWithCheckpointBelow;
   let mut env: LineRider3DEnv = LineRider3DEnv::new(sim, None);
   env.sim.config.step_limit = 10;
   env.skip_simulation = true;
@@ -69,8 +69,8 @@ fn test_checkpoint() {
   let mut current_episode = 0;
   let mut success_counter = 0;
   for _ in 0..10 {
-    println!("ANSWER IS {}", straight_line_heuristic_general(&obs, &config, None));
-    let res = env.step(&straight_line_heuristic_general(&obs, &config, None));
+    println!("ANSWER IS {}", straight_line_heuristic_general(&env.expert_state(), &config, None));
+    let res = env.step(&straight_line_heuristic_general(&env.expert_state(), &config, None));
     obs = res.obs;
   }
   env.add_lines_freeroam();
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+use bevy::prelude::Vec3;
+use rusty_gym::Action;
+use serde::de::DeserializeOwned;
+use crate::{algo::heuristic::straight_line_expert, util::{range::Range3D, types::ActionType}};
+use super::LineRider3DEnv;
+
+/**
+ * The true state of the build phase for heuristics and imitation experts.
+ * Unlike the observation it does not depend on the observation type, the window size or how full the track is.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpertState {
+  pub last_point: Vec3,
+  pub last_track_type: u8,
+  /** Horizontal direction the track continues in, see LineRider3DEnv::heading */
+  pub heading: Vec3,
+  /** The next checkpoint the track has to reach, the goal once there is none left */
+  pub current_target: Vec3,
+  pub goal: Vec3,
+  pub checkpoints_reached: usize,
+  pub checkpoint_count: usize,
+  /** Pieces that can still be placed before the simulation starts */
+  pub remaining_steps: usize,
+  pub build_range: Range3D<f32>
+}
+
+impl ExpertState {
+  pub fn all_checkpoints_reached(&self) -> bool {
+    self.checkpoints_reached >= self.checkpoint_count
+  }
+  pub fn to_map(&self) -> HashMap<String, String> {
+    let vec3_to_string = |v: &Vec3| format!("[{}, {}, {}]", v.x, v.y, v.z);
+    let mut state = HashMap::new();
+    state.insert("last_point".to_owned(), vec3_to_string(&self.last_point));
+    state.insert("last_track_type".to_owned(), self.last_track_type.to_string());
+    state.insert("heading".to_owned(), vec3_to_string(&self.heading));
+    state.insert("current_target".to_owned(), vec3_to_string(&self.current_target));
+    state.insert("goal".to_owned(), vec3_to_string(&self.goal));
+    state.insert("checkpoints_reached".to_owned(), self.checkpoints_reached.to_string());
+    state.insert("checkpoint_count".to_owned(), self.checkpoint_count.to_string());
+    state.insert("remaining_steps".to_owned(), self.remaining_steps.to_string());
+    state.insert("build_range".to_owned(), serde_json::to_string(&self.build_range).expect("build range is serializable"));
+    state
+  }
+  /** Parses a state written by to_map, e.g. one handed through ReplayableGymEnvironment::get_expert_state */
+  pub fn from_map(state: &HashMap<String, String>) -> Option<ExpertState> {
+    fn parse<T: DeserializeOwned>(state: &HashMap<String, String>, key: &str) -> Option<T> {
+      serde_json::from_str(state.get(key)?).ok()
+    }
+    let vec3 = |key: &str| parse::<[f32; 3]>(state, key).map(Vec3::from_array);
+    Some(ExpertState {
+      last_point: vec3("last_point")?,
+      last_track_type: parse(state, "last_track_type")?,
+      heading: vec3("heading")?,
+      current_target: vec3("current_target")?,
+      goal: vec3("goal")?,
+      checkpoints_reached: parse(state, "checkpoints_reached")?,
+      checkpoint_count: parse(state, "checkpoint_count")?,
+      remaining_steps: parse(state, "remaining_steps")?,
+      build_range: parse(state, "build_range")?
+    })
+  }
+}
+
+impl LineRider3DEnv {
+  pub fn expert_state(&self) -> ExpertState {
+    let config = &self.sim.config;
+    let (last_point, last_track_type) = self.lines[self.lines.len()-1];
+    let (checkpoints_reached, checkpoint_count, next_checkpoint) = if config.with_checkpoint_sequence() {
+      (self.track_checkpoints_reached, config.checkpoint_positions.len(), config.checkpoint_positions.get(self.track_checkpoints_reached).cloned())
+    } else if config.with_checkpoint() {
+      (self.track_reached_checkpoint as usize, 1, if self.track_reached_checkpoint {None} else {Some(config.checkpoint_pos)})
+    } else {
+      (0, 0, None)
+    };
+    ExpertState {
+      last_point,
+      last_track_type,
+      heading: self.heading(),
+      current_target: next_checkpoint.unwrap_or(config.goal_pos),
+      goal: config.goal_pos,
+      checkpoints_reached,
+      checkpoint_count,
+      remaining_steps: config.step_limit.saturating_sub(self.current_step),
+      build_range: self.sim.build_range.clone()
+    }
+  }
+  /** Action of straight_line_expert for the current state, None for action types it can't handle */
+  pub fn get_expert_action(&self) -> Option<Action> {
+    match self.sim.config.action_type {
+      ActionType::FreePointsRelative | ActionType::FreePointsWithTpRelative => Some(straight_line_expert(&self.expert_state(), self.sim.config.action_type, None)),
+      _ => None
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{simulator::LineRiderSim, util::types::{ActionType, ObservationType, TargetType}};
+  use rusty_gym::{GymEnvironment, ReplayableGymEnvironment};
+  use ndarray::ArrayBase;
+
+  #[test]
+  fn expert_state_follows_checkpoints_for_every_observation_type() {
+    for observation_type in [ObservationType::BuildPoints, ObservationType::SlidingWindow, ObservationType::GoalAndLastPoint, ObservationType::View3D] {
+      let mut sim = LineRiderSim::default();
+      sim.config.action_type = ActionType::FreePointsRelative;
+      sim.config.target_type = TargetType::RandomWithCheckpointBelow;
+      sim.config.observation_type = observation_type;
+      sim.config.step_limit = 3;
+      let mut env = LineRider3DEnv::new(sim, Some(7));
+      env.reset();
+      let state = env.expert_state();
+      assert_eq!(state.last_point, env.lines[0].0);
+      assert_eq!(state.current_target, env.sim.config.checkpoint_pos);
+      assert_eq!((state.checkpoints_reached, state.checkpoint_count), (0, 1));
+      assert_eq!(state.remaining_steps, 3);
+      env.step(&ArrayBase::from(vec![1.0, 0.0, 0.0]).into_dyn());
+      env.track_reached_checkpoint = true;
+      let state = env.expert_state();
+      assert_eq!(state.last_point, env.lines[env.lines.len()-1].0);
+      assert_eq!(state.current_target, state.goal);
+      assert!(state.all_checkpoints_reached());
+      assert_eq!(state.remaining_steps, 2);
+      assert_eq!(state.to_map().get("remaining_steps"), Some(&"2".to_owned()));
+      assert_eq!(ExpertState::from_map(&env.get_expert_state().unwrap()), Some(state.clone()));
+      let expert_action = env.get_expert_action().unwrap();
+      let expected = (state.goal - state.last_point).normalize();
+      assert!((expert_action[0] as f32 - expected.x).abs() < 1e-5 && (expert_action[1] as f32 - expected.y).abs() < 1e-5);
+    }
+  }
+}
@@ -0,0 +1,260 @@
+use std::{io, path::Path};
+use bevy::{prelude::{Color, Mesh}, render::mesh::{Indices, VertexAttributeValues}};
+use serde_json::json;
+use crate::{simulator::LineRiderConfig, util::{consts::*, range::Range3D, track::make_track_pieces}};
+use super::LineRider3DEnv;
+
+const GLB_MAGIC: u32 = 0x46546C67;
+const GLB_CHUNK_JSON: u32 = 0x4E4F534A;
+const GLB_CHUNK_BIN: u32 = 0x004E4942;
+const GLTF_FLOAT: u32 = 5126;
+const GLTF_UNSIGNED_INT: u32 = 5125;
+const GLTF_ARRAY_BUFFER: u32 = 34962;
+const GLTF_ELEMENT_ARRAY_BUFFER: u32 = 34963;
+
+/** Triangle mesh with a single flat coloured material, the unit that is written by the OBJ and glTF exporters */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExportMesh {
+  pub name: String,
+  pub color: Color,
+  pub positions: Vec<[f32; 3]>,
+  pub indices: Vec<u32>
+}
+
+impl ExportMesh {
+  fn new(name: &str, color: Color) -> ExportMesh {
+    ExportMesh {name: name.to_owned(), color, positions: Vec::new(), indices: Vec::new()}
+  }
+
+  fn append_mesh(&mut self, mesh: &Mesh) {
+    let offset = self.positions.len() as u32;
+    if let Some(VertexAttributeValues::Float32x3(positions)) = mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+      self.positions.extend(positions.iter());
+    }
+    match mesh.indices() {
+      Some(Indices::U32(indices)) => self.indices.extend(indices.iter().map(|i| i + offset)),
+      Some(Indices::U16(indices)) => self.indices.extend(indices.iter().map(|i| *i as u32 + offset)),
+      None => self.indices.extend(offset..self.positions.len() as u32)
+    }
+  }
+
+  fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for position in &self.positions {
+      for axis in 0..3 {
+        min[axis] = min[axis].min(position[axis]);
+        max[axis] = max[axis].max(position[axis]);
+      }
+    }
+    (min, max)
+  }
+}
+
+/**
+ * Same geometry that setup_track, setup_goal_mesh and setup_checkpoint_mesh spawn, grouped by material.
+ * Does not need the Bevy app, so it works on headless environments.
+ */
+pub fn make_export_meshes(lines: &[crate::simulator::TrackPoint], free_points: bool, config: &LineRiderConfig, obstacles: &[Range3D<f32>]) -> Vec<ExportMesh> {
+  let mut normal = if config.use_cylinder_track {
+    ExportMesh::new("track", COLOR_TRACK.with_a(0.5))
+  } else {
+    ExportMesh::new("track", COLOR_TRACK)
+  };
+  let mut boost = ExportMesh::new("track_boost", COLOR_TRACK_BOOST);
+  let mut brake = ExportMesh::new("track_brake", COLOR_TRACK_BRAKE);
+  for piece in make_track_pieces(lines, free_points, config) {
+    let target = match piece.track_type {
+      TP_ACCELERATE => &mut boost,
+      TP_DECELERATE => &mut brake,
+      _ => &mut normal
+    };
+    target.append_mesh(&piece.mesh);
+  }
+  let mut goal = ExportMesh::new("goal", COLOR_GOAL);
+  goal.append_mesh(&Mesh::from(config.goal_position.to_box()));
+  let mut meshes = vec![normal, boost, brake, goal];
+  if config.with_checkpoint() {
+    let mut checkpoint = ExportMesh::new("checkpoint", COLOR_CHECKPOINT);
+    if config.with_checkpoint_sequence() {
+      for checkpoint_range in &config.checkpoint_ranges {
+        checkpoint.append_mesh(&Mesh::from(checkpoint_range.to_box()));
+      }
+    } else {
+      checkpoint.append_mesh(&Mesh::from(config.checkpoint_range.to_box()));
+    }
+    meshes.push(checkpoint);
+  }
+  let mut obstacle_mesh = ExportMesh::new("obstacle", COLOR_OBSTACLE);
+  for obstacle in obstacles {
+    obstacle_mesh.append_mesh(&Mesh::from(obstacle.to_box()));
+  }
+  meshes.push(obstacle_mesh);
+  meshes.retain(|mesh| !mesh.indices.is_empty());
+  meshes
+}
+
+/** Wavefront OBJ and the MTL it references as mtl_file_name */
+pub fn meshes_to_obj(meshes: &[ExportMesh], mtl_file_name: &str) -> (String, String) {
+  let mut obj = format!("mtllib {}\n", mtl_file_name);
+  let mut mtl = String::new();
+  let mut offset = 1;
+  for mesh in meshes {
+    let [r, g, b, a] = mesh.color.as_rgba_f32();
+    mtl.push_str(&format!("newmtl {}\nKd {} {} {}\nd {}\n\n", mesh.name, r, g, b, a));
+    obj.push_str(&format!("o {}\nusemtl {}\n", mesh.name, mesh.name));
+    for [x, y, z] in &mesh.positions {
+      obj.push_str(&format!("v {} {} {}\n", x, y, z));
+    }
+    for face in mesh.indices.chunks(3) {
+      obj.push_str(&format!("f {} {} {}\n", face[0] + offset, face[1] + offset, face[2] + offset));
+    }
+    offset += mesh.positions.len() as u32;
+  }
+  (obj, mtl)
+}
+
+fn push_chunk(glb: &mut Vec<u8>, chunk_type: u32, mut data: Vec<u8>, padding: u8) {
+  while !data.len().is_multiple_of(4) {
+    data.push(padding);
+  }
+  glb.extend((data.len() as u32).to_le_bytes());
+  glb.extend(chunk_type.to_le_bytes());
+  glb.extend(data);
+}
+
+/** Binary glTF 2.0 with one node per mesh */
+pub fn meshes_to_glb(meshes: &[ExportMesh]) -> Vec<u8> {
+  let mut buffer: Vec<u8> = Vec::new();
+  let mut buffer_views = Vec::new();
+  let mut accessors = Vec::new();
+  let mut gltf_meshes = Vec::new();
+  let mut materials = Vec::new();
+  let mut nodes = Vec::new();
+  for (i, mesh) in meshes.iter().enumerate() {
+    let positions_offset = buffer.len();
+    for position in &mesh.positions {
+      for value in position {
+        buffer.extend(value.to_le_bytes());
+      }
+    }
+    let indices_offset = buffer.len();
+    for index in &mesh.indices {
+      buffer.extend(index.to_le_bytes());
+    }
+    let (min, max) = mesh.bounds();
+    buffer_views.push(json!({"buffer": 0, "byteOffset": positions_offset, "byteLength": indices_offset - positions_offset, "target": GLTF_ARRAY_BUFFER}));
+    buffer_views.push(json!({"buffer": 0, "byteOffset": indices_offset, "byteLength": buffer.len() - indices_offset, "target": GLTF_ELEMENT_ARRAY_BUFFER}));
+    accessors.push(json!({"bufferView": 2 * i, "componentType": GLTF_FLOAT, "count": mesh.positions.len(), "type": "VEC3", "min": min, "max": max}));
+    accessors.push(json!({"bufferView": 2 * i + 1, "componentType": GLTF_UNSIGNED_INT, "count": mesh.indices.len(), "type": "SCALAR"}));
+    let color = mesh.color.as_linear_rgba_f32();
+    materials.push(json!({
+      "name": mesh.name,
+      "pbrMetallicRoughness": {"baseColorFactor": color, "metallicFactor": 0.0, "roughnessFactor": 1.0},
+      "alphaMode": if color[3] < 1.0 {"BLEND"} else {"OPAQUE"},
+      "doubleSided": true
+    }));
+    gltf_meshes.push(json!({"name": mesh.name, "primitives": [{"attributes": {"POSITION": 2 * i}, "indices": 2 * i + 1, "material": i}]}));
+    nodes.push(json!({"name": mesh.name, "mesh": i}));
+  }
+  let mut gltf = json!({
+    "asset": {"version": "2.0", "generator": "linerider"},
+    "scene": 0,
+    "scenes": [{"nodes": (0..meshes.len()).collect::<Vec<usize>>()}],
+    "nodes": nodes,
+    "meshes": gltf_meshes,
+    "materials": materials,
+    "accessors": accessors,
+    "bufferViews": buffer_views
+  });
+  if !buffer.is_empty() {
+    gltf["buffers"] = json!([{"byteLength": buffer.len()}]);
+  }
+  let mut glb = Vec::new();
+  glb.extend(GLB_MAGIC.to_le_bytes());
+  glb.extend(2u32.to_le_bytes());
+  glb.extend(0u32.to_le_bytes());
+  push_chunk(&mut glb, GLB_CHUNK_JSON, gltf.to_string().into_bytes(), b' ');
+  if !buffer.is_empty() {
+    push_chunk(&mut glb, GLB_CHUNK_BIN, buffer, 0);
+  }
+  let length = glb.len() as u32;
+  glb[8..12].copy_from_slice(&length.to_le_bytes());
+  glb
+}
+
+impl LineRider3DEnv {
+  pub fn export_meshes(&self) -> Vec<ExportMesh> {
+    make_export_meshes(&self.lines, self.sim.config.action_type.is_free_points(), &self.sim.config, &self.sim.obstacles)
+  }
+
+  /** Writes the track to path and its materials next to it with the extension .mtl */
+  pub fn export_obj<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let mtl_path = path.with_extension("mtl");
+    let mtl_file_name = mtl_path.file_name().and_then(|name| name.to_str()).unwrap_or("track.mtl");
+    let (obj, mtl) = meshes_to_obj(&self.export_meshes(), mtl_file_name);
+    std::fs::write(path, obj)?;
+    std::fs::write(&mtl_path, mtl)
+  }
+
+  /** Writes the track as binary glTF (.glb) */
+  pub fn export_gltf<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+    std::fs::write(path, meshes_to_glb(&self.export_meshes()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ndarray::ArrayBase;
+  use rusty_gym::GymEnvironment;
+  use crate::util::types::ActionType;
+
+  fn built_env() -> LineRider3DEnv {
+    let mut env = LineRider3DEnv::default();
+    env.sim.config.action_type = ActionType::StaticWithBoost;
+    env.skip_simulation = true;
+    env.reset();
+    for action in [ACTION_STRAIGHT_DOWN, ACTION_STRAIGHT_BOOST, ACTION_STRAIGHT_DOWN_DAMPEN] {
+      env.step(&ArrayBase::from(vec![action as f64]).into_dyn());
+    }
+    env
+  }
+
+  #[test]
+  fn exports_track_materials_and_goal() {
+    let env = built_env();
+    let meshes = env.export_meshes();
+    let names: Vec<&str> = meshes.iter().map(|mesh| mesh.name.as_str()).collect();
+    assert_eq!(names, vec!["track", "track_boost", "track_brake", "goal"]);
+    for mesh in &meshes {
+      assert_eq!(mesh.indices.len() % 3, 0);
+      assert!(mesh.indices.iter().all(|i| (*i as usize) < mesh.positions.len()));
+    }
+
+    let (obj, mtl) = meshes_to_obj(&meshes, "track.mtl");
+    let vertices = meshes.iter().map(|mesh| mesh.positions.len()).sum::<usize>();
+    let faces = meshes.iter().map(|mesh| mesh.indices.len() / 3).sum::<usize>();
+    assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), vertices);
+    assert_eq!(obj.lines().filter(|line| line.starts_with("f ")).count(), faces);
+    assert_eq!(mtl.matches("newmtl").count(), meshes.len());
+  }
+
+  #[test]
+  fn glb_is_well_formed() {
+    let meshes = built_env().export_meshes();
+    let glb = meshes_to_glb(&meshes);
+    let read_u32 = |offset: usize| u32::from_le_bytes(glb[offset..offset+4].try_into().unwrap());
+    assert_eq!(read_u32(0), GLB_MAGIC);
+    assert_eq!(read_u32(8) as usize, glb.len());
+    let json_length = read_u32(12) as usize;
+    assert_eq!(read_u32(16), GLB_CHUNK_JSON);
+    let gltf: serde_json::Value = serde_json::from_slice(&glb[20..20+json_length]).unwrap();
+    assert_eq!(gltf["meshes"].as_array().unwrap().len(), meshes.len());
+    assert_eq!(gltf["accessors"][0]["count"], meshes[0].positions.len());
+    let bin_length = read_u32(20 + json_length) as usize;
+    assert_eq!(read_u32(24 + json_length), GLB_CHUNK_BIN);
+    assert_eq!(gltf["buffers"][0]["byteLength"].as_u64().unwrap() as usize, bin_length);
+  }
+}
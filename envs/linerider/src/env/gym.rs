@@ -1,17 +1,17 @@
 
 use bevy::math::{Vec3, Quat};
 use rusty_gym::{ReplayableGymEnvironment,
-  GymEnvironment, Step, Observation, Action, Space,
-  util::rng::get_rng_for_type
+  GymEnvironment, Step, Observation, Action, Space
 };
+use xp_tools::rng::from_seed;
 
-use crate::{util::consts::*, algo::heuristic::straight_line_heuristica};
+use crate::{util::{consts::*, types::{ActionType, RewardComponent}}, algo::heuristic::{straight_line_expert, straight_line_heuristica}};
 
 use super::LineRider3DEnv;
 
 impl GymEnvironment for LineRider3DEnv {
   fn use_seed(&mut self, seed: u64) {
-    let (new_rng, new_seed) = get_rng_for_type(&None, Some(seed));
+    let (new_rng, new_seed) = from_seed(Some(seed));
     self.rng = new_rng;
     self.used_seed = new_seed;
   }
@@ -22,31 +22,38 @@ impl GymEnvironment for LineRider3DEnv {
 
   fn step(&mut self, action: &Action) -> Step {
     let mut is_done = false;
+    let mut is_truncated = false;
     let mut reward = 0.0;
-    let obs = self.make_obs();
+    let mut components: Vec<(RewardComponent, f64)> = Vec::new();
+    let mut valid_action = false;
+    let mut simulation_result = None;
+    let mimics = self.sim.config.uses_reward(RewardComponent::MimicStraightLineHeuristic) || self.sim.config.uses_reward(RewardComponent::MimicExpert);
+    let expert_state = mimics.then(|| self.expert_state());
     if self.current_step >= self.sim.config.step_limit {
       is_done = true;
-      reward += self.get_reward_through_simulation().0;
+      simulation_result = self.simulate_for_step(&mut reward, &mut components);
+      // the build phase ran out of steps, unless the ball made it to the goal this is a time limit and not a terminal state
+      is_truncated = !simulation_result.as_ref().is_some_and(|sim_res| sim_res.goal_reached);
     } else {
-      let added = match self.sim.config.action_type {
-        ACTION_TYPE_FREE_POINTS_RELATIVE | ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE => {
-          let track_type = if self.sim.config.action_type == ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE {action[3] as u8} else {TP_NORMAL};
-          if self.sim.config.reward_type.contains(&REWARD_USING_BOOSTER_TYPE_TRACK) {
-            if track_type == TP_ACCELERATE {
-              reward += 0.01;
-            }
+      let is_undo = self.is_undo_action(action);
+      let undone = is_undo && self.undo_last_piece();
+      let added = !is_undo && match self.sim.config.action_type {
+        ActionType::FreePointsRelative | ActionType::FreePointsWithTpRelative => {
+          let track_type = if self.sim.config.action_type == ActionType::FreePointsWithTpRelative {action[3] as u8} else {TP_NORMAL};
+          if self.sim.config.uses_reward(RewardComponent::UsingBoosterTypeTrack) && track_type == TP_ACCELERATE {
+            self.add_step_reward(&mut reward, &mut components, RewardComponent::UsingBoosterTypeTrack, 1.0);
           }
           let last_point = self.lines[self.lines.len()-1].0;
           let new_point = (Vec3::new(last_point.x + action[0] as f32, last_point.y + action[1] as f32, last_point.z + action[2] as f32), track_type);
           self.add_line_for_point(new_point)
         },
-        ACTION_TYPE_FREE_POINTS | ACTION_TYPE_FREE_POINTS_WITH_TP => {
-          let track_type = if self.sim.config.action_type == ACTION_TYPE_FREE_POINTS_WITH_TP {action[3] as u8} else {TP_NORMAL};
+        ActionType::FreePoints | ActionType::FreePointsWithTp => {
+          let track_type = if self.sim.config.action_type == ActionType::FreePointsWithTp {action[3] as u8} else {TP_NORMAL};
           let new_point = (Vec3::new(action[0] as f32, action[1] as f32, action[2] as f32), track_type);
           self.add_line_for_point(new_point)
         },
-        ACTION_TYPE_RADIAL | ACTION_TYPE_RADIAL_WITH_TP => {
-          let track_type = if self.sim.config.action_type == ACTION_TYPE_RADIAL_WITH_TP {action[3] as u8} else {TP_NORMAL};
+        ActionType::Radial | ActionType::RadialWithTp => {
+          let track_type = if self.sim.config.action_type == ActionType::RadialWithTp {action[3] as u8} else {TP_NORMAL};
           let yaw = Quat::from_rotation_y(action[1] as f32);
           let pitch = Quat::from_rotation_x(action[2] as f32);
           let distance = action[0] as f32;
@@ -58,57 +65,85 @@ impl GymEnvironment for LineRider3DEnv {
           let new_point = (last_point + moved_point, track_type);
           self.add_line_for_point(new_point)
         },
-        _ => {
+        ActionType::Static | ActionType::StaticWithEmpty | ActionType::StaticWithBoost => {
           self.add_line_for_action(action[0] as i64)
         }
       };
+      valid_action = added || undone;
       if added {
-        if self.sim.config.reward_type.contains(&REWARD_VALID_ACTION_CHOSEN) {
-          reward += 0.0001;
+        if self.sim.config.uses_reward(RewardComponent::ValidActionChosen) {
+          self.add_step_reward(&mut reward, &mut components, RewardComponent::ValidActionChosen, 1.0);
         }
-        if self.sim.config.reward_type.contains(&REWARD_TRACK_CLOSER_TO_GOAL_IN_STEP) {
+        if self.sim.config.uses_reward(RewardComponent::TrackCloserToGoalInStep) {
           let dist = self.sim.config.goal_pos.distance(self.lines[self.lines.len()-1].0);
           let distance_difference = self.track_distance_of_last_step - dist;
           if distance_difference > 0.0 {
-            reward += 0.01;
+            self.add_step_reward(&mut reward, &mut components, RewardComponent::TrackCloserToGoalInStep, 1.0);
           }
         }
-        if (self.sim.config.action_type == ACTION_TYPE_FREE_POINTS_RELATIVE || self.sim.config.action_type == ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE) && self.sim.config.reward_type.contains(&REWARD_MIMIC_STRAIGHT_LINE_HEURISTIC) {
-          let optimal_action = straight_line_heuristica(&obs, &self.get_config(), None);
-          let optimal_vec = Vec3::new(optimal_action[0] as f32, optimal_action[1] as f32, optimal_action[2] as f32);
+        if let (true, Some(state)) = (self.sim.config.action_type.is_relative(), &expert_state) {
           let chosen_vec = Vec3::new(action[0] as f32, action[1] as f32, action[2] as f32);
-          let dist = optimal_vec.distance(chosen_vec);          
-          reward += ((1.0 - dist) as f64) * 0.01;
+          let closeness = |optimal_action: Action| {
+            let optimal_vec = Vec3::new(optimal_action[0] as f32, optimal_action[1] as f32, optimal_action[2] as f32);
+            (1.0 - optimal_vec.distance(chosen_vec)) as f64
+          };
+          if self.sim.config.uses_reward(RewardComponent::MimicStraightLineHeuristic) {
+            let optimal_action = straight_line_heuristica(state, &self.get_config(), None);
+            self.add_step_reward(&mut reward, &mut components, RewardComponent::MimicStraightLineHeuristic, closeness(optimal_action));
+          }
+          if self.sim.config.uses_reward(RewardComponent::MimicExpert) {
+            let optimal_action = straight_line_expert(state, self.sim.config.action_type, None);
+            self.add_step_reward(&mut reward, &mut components, RewardComponent::MimicExpert, closeness(optimal_action));
+          }
         }
         let uses_checkpoint = self.sim.config.with_checkpoint();
         if uses_checkpoint && !self.track_reached_checkpoint {
-          if self.sim.config.checkpoint_range.vec3_in_range(&self.lines[self.lines.len()-1].0) {
+          let last_point = self.lines[self.lines.len()-1].0;
+          let reached_all_checkpoints = if self.sim.config.with_checkpoint_sequence() {
+            let checkpoint_ranges = &self.sim.config.checkpoint_ranges;
+            if checkpoint_ranges.get(self.track_checkpoints_reached).is_some_and(|next_checkpoint| next_checkpoint.vec3_in_range(&last_point)) {
+              self.track_checkpoints_reached += 1;
+              if self.track_checkpoints_reached > self.rewarded_track_checkpoints {
+                self.rewarded_track_checkpoints = self.track_checkpoints_reached;
+                if self.sim.config.uses_reward(RewardComponent::TrackReachEachCheckpoint) {
+                  self.add_step_reward(&mut reward, &mut components, RewardComponent::TrackReachEachCheckpoint, 1.0);
+                }
+              }
+            }
+            self.track_checkpoints_reached >= self.sim.config.checkpoint_ranges.len()
+          } else {
+            self.sim.config.checkpoint_range.vec3_in_range(&last_point)
+          };
+          if reached_all_checkpoints {
             self.track_reached_checkpoint = true;
-            if self.sim.config.reward_type.contains(&REWARD_TRACK_REACH_CHECKPOINT) {
-              reward += 0.5;
+            if !self.rewarded_track_checkpoint && self.sim.config.uses_reward(RewardComponent::TrackReachCheckpoint) {
+              self.add_step_reward(&mut reward, &mut components, RewardComponent::TrackReachCheckpoint, 1.0);
             }
+            self.rewarded_track_checkpoint = true;
           }
         }
         if !self.track_reached_goal && ((uses_checkpoint && self.track_reached_checkpoint) || !uses_checkpoint) {
-          if self.sim.config.goal_position.vec3_in_range(&self.lines[self.lines.len()-1].0) {
+          if self.last_point_reaches_goal() {
             self.track_reached_goal = true;
-            if self.sim.config.reward_type.contains(&REWARD_GOAL_REACHED_BY_TRACK) {
-              reward += 0.5;
+            if !self.rewarded_track_goal && self.sim.config.uses_reward(RewardComponent::GoalReachedByTrack) {
+              self.add_step_reward(&mut reward, &mut components, RewardComponent::GoalReachedByTrack, 1.0);
             }
-            if self.sim.config.reward_type.contains(&REWARD_END_BUILD_PHASE_IF_TRACK_REACHES_GOAL) {
+            self.rewarded_track_goal = true;
+            if self.sim.config.uses_reward(RewardComponent::EndBuildPhaseIfTrackReachesGoal) {
 
               self.current_step = self.sim.config.step_limit;
               is_done = true;
-              reward += self.get_reward_through_simulation().0;
+              simulation_result = self.simulate_for_step(&mut reward, &mut components);
             }
           }
         }
-      } else if self.sim.config.reward_type.contains(&REWARD_SCOLD_INVALID_ACTION) {
-        reward = -0.0001;
+      } else if !undone && self.sim.config.uses_reward(RewardComponent::ScoldInvalidAction) {
+        reward = self.weighted_reward(RewardComponent::ScoldInvalidAction, 1.0);
+        components = vec![(RewardComponent::ScoldInvalidAction, reward)];
       }
       self.current_step += 1;
-      if !is_done && self.sim.config.reward_type.contains(&REWARD_SIMULATE_INBETWEEN) && self.current_step % self.sim.config.intermediate_simulation_frequency == 0 {
-        reward += self.get_reward_through_simulation().0;
+      if !is_done && self.sim.config.uses_reward(RewardComponent::SimulateInbetween) && self.current_step % self.sim.config.intermediate_simulation_frequency == 0 {
+        self.simulate_for_step(&mut reward, &mut components);
         self.reset_simulation_only();
       }
     }
@@ -116,7 +151,9 @@ impl GymEnvironment for LineRider3DEnv {
       obs: self.make_obs(),
       reward,
       is_done,
-      action: action.clone()
+      is_truncated,
+      action: action.clone(),
+      info: self.make_step_info(&components, valid_action, simulation_result.as_ref())
     }
   }
 
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use bevy::prelude::{Mut, Vec3};
+use bevy_rapier3d::prelude::Collider;
+use rand_pcg::Pcg64Mcg;
+use rusty_gym::ResettableGymEnvironment;
+use serde::de::DeserializeOwned;
+use crate::{simulator::{LineRiderConfig, TrackPoint}, util::range::Range3D};
+use super::{LineRider3DEnv, UndoInfo};
+
+fn restored<T: DeserializeOwned>(state: &HashMap<String, String>, key: &str) -> T {
+  let raw_value = state.get(key).unwrap_or_else(|| panic!("Restorable state needs '{}' var", key));
+  serde_json::from_str(raw_value).unwrap_or_else(|e| panic!("value '{}' can be parsed: {}", key, e))
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+  serde_json::to_string(value).expect("restorable state is serializable")
+}
+
+/**
+ * Snapshot of the build phase, so that planners can branch from the middle of an episode.
+ * The config is not part of the state, restore into an env with the same config.
+ */
+impl ResettableGymEnvironment for LineRider3DEnv {
+  fn get_restorable_state(&mut self) -> HashMap<String, String> {
+    let config = &self.sim.config;
+    let mut map: HashMap<String, String> = HashMap::new();
+    map.insert("lines".to_owned(), to_json(&self.lines));
+    map.insert("line_colliders".to_owned(), to_json(&self.line_colliders));
+    map.insert("prev_points".to_owned(), to_json(&self.prev_points));
+    map.insert("undo_history".to_owned(), to_json(&self.undo_history));
+    map.insert("current_direction".to_owned(), self.current_direction.to_string());
+    map.insert("current_step".to_owned(), self.current_step.to_string());
+    map.insert("track_reached_goal".to_owned(), self.track_reached_goal.to_string());
+    map.insert("track_reached_checkpoint".to_owned(), self.track_reached_checkpoint.to_string());
+    map.insert("track_checkpoints_reached".to_owned(), self.track_checkpoints_reached.to_string());
+    map.insert("rewarded_track_goal".to_owned(), self.rewarded_track_goal.to_string());
+    map.insert("rewarded_track_checkpoint".to_owned(), self.rewarded_track_checkpoint.to_string());
+    map.insert("rewarded_track_checkpoints".to_owned(), self.rewarded_track_checkpoints.to_string());
+    map.insert("loop_start_colliders".to_owned(), to_json(&self.loop_start_colliders));
+    map.insert("track_distance_of_last_step".to_owned(), to_json(&self.track_distance_of_last_step));
+    map.insert("origin".to_owned(), to_json(&self.sim.origin));
+    map.insert("goal_pos".to_owned(), to_json(&config.goal_pos));
+    map.insert("goal_position".to_owned(), to_json(&config.goal_position));
+    map.insert("checkpoint_pos".to_owned(), to_json(&config.checkpoint_pos));
+    map.insert("checkpoint_range".to_owned(), to_json(&config.checkpoint_range));
+    map.insert("checkpoint_positions".to_owned(), to_json(&config.checkpoint_positions));
+    map.insert("checkpoint_ranges".to_owned(), to_json(&config.checkpoint_ranges));
+    map.insert("obstacles".to_owned(), to_json(&self.sim.obstacles));
+    map.insert("rng".to_owned(), to_json(&self.rng));
+    map.insert("used_seed".to_owned(), self.used_seed.to_string());
+    map
+  }
+
+  fn restore_state(&mut self, state: &HashMap<String, String>) {
+    self.reset_simulation_only();
+    self.lines = restored::<Vec<TrackPoint>>(state, "lines");
+    self.line_colliders = restored::<Vec<Collider>>(state, "line_colliders");
+    self.prev_points = restored::<[Vec3; 4]>(state, "prev_points");
+    self.undo_history = restored::<Vec<UndoInfo>>(state, "undo_history");
+    self.current_direction = restored(state, "current_direction");
+    self.current_step = restored(state, "current_step");
+    self.track_reached_goal = restored(state, "track_reached_goal");
+    self.track_reached_checkpoint = restored(state, "track_reached_checkpoint");
+    self.track_checkpoints_reached = restored(state, "track_checkpoints_reached");
+    self.rewarded_track_goal = restored(state, "rewarded_track_goal");
+    self.rewarded_track_checkpoint = restored(state, "rewarded_track_checkpoint");
+    self.rewarded_track_checkpoints = restored(state, "rewarded_track_checkpoints");
+    self.loop_start_colliders = restored::<Option<usize>>(state, "loop_start_colliders");
+    self.track_distance_of_last_step = restored(state, "track_distance_of_last_step");
+    self.sim.origin = restored::<Option<Vec3>>(state, "origin");
+    self.sim.set_max_width(self.sim.config.max_width);
+    self.sim.set_goal_position(restored(state, "goal_pos"));
+    self.sim.config.goal_position = restored::<Range3D<f32>>(state, "goal_position");
+    self.sim.config.checkpoint_pos = restored(state, "checkpoint_pos");
+    self.sim.config.checkpoint_range = restored::<Range3D<f32>>(state, "checkpoint_range");
+    self.sim.config.checkpoint_positions = restored::<Vec<Vec3>>(state, "checkpoint_positions");
+    self.sim.config.checkpoint_ranges = restored::<Vec<Range3D<f32>>>(state, "checkpoint_ranges");
+    self.sim.set_obstacles(restored::<Vec<Range3D<f32>>>(state, "obstacles"));
+    self.rng = restored::<Pcg64Mcg>(state, "rng");
+    self.used_seed = restored(state, "used_seed");
+    {
+      let mut app_config: Mut<LineRiderConfig> = self.sim.app.world.resource_mut();
+      app_config.copy_from(&self.sim.config);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ndarray::ArrayBase;
+  use rusty_gym::{GymEnvironment, Step};
+  use crate::util::{consts::*, types::{RewardComponent, TargetType}};
+
+  fn step_all(env: &mut LineRider3DEnv, actions: &[i64]) -> Vec<Step> {
+    actions.iter().map(|action| env.step(&ArrayBase::from(vec![*action as f64]).into_dyn())).collect()
+  }
+
+  #[test]
+  fn restored_env_continues_like_the_original() {
+    let mut env = LineRider3DEnv::default();
+    env.sim.config.reward_type = vec![RewardComponent::ValidActionChosen, RewardComponent::TrackCloserToGoalInStep];
+    env.sim.config.target_type = TargetType::RandomStartAndEnd;
+    env.skip_simulation = true;
+    env.use_seed(7);
+    env.reset();
+    step_all(&mut env, &[ACTION_DOWN, ACTION_LEFT, ACTION_STRAIGHT]);
+    let state = env.get_restorable_state();
+    let continuation = [ACTION_LEFT, ACTION_LEFT, ACTION_DOWN, ACTION_RIGHT, ACTION_STRAIGHT];
+    let original_steps = step_all(&mut env, &continuation);
+    let original_lines = env.lines.clone();
+    let original_reset = env.reset();
+
+    let mut other_env = LineRider3DEnv::default();
+    other_env.sim.config.reward_type = env.sim.config.reward_type.clone();
+    other_env.sim.config.target_type = TargetType::RandomStartAndEnd;
+    other_env.skip_simulation = true;
+    other_env.restore_state(&state);
+    let restored_steps = step_all(&mut other_env, &continuation);
+    for (original, restored) in original_steps.iter().zip(restored_steps.iter()) {
+      assert_eq!(original.obs, restored.obs);
+      assert_eq!(original.reward, restored.reward);
+      assert_eq!(original.is_done, restored.is_done);
+    }
+    assert_eq!(other_env.lines, original_lines);
+    // the rng continues where it was, so the next episode is generated the same way
+    assert_eq!(other_env.reset(), original_reset);
+  }
+}
@@ -0,0 +1,366 @@
+use std::{collections::{BTreeMap, HashMap}, fmt, io, path::Path};
+use bevy::prelude::{Mut, Vec3};
+use bevy_rapier3d::prelude::Collider;
+use rusty_gym::Observation;
+use serde::{Deserialize, Serialize, Serializer};
+use crate::{
+  simulator::{config::{format_config_errors, ConfigError, LineRiderConfig}, TrackPoint},
+  util::{consts::*, range::Range3D, track::{get_free_mesh_points, get_mesh_points, make_build_range, make_goal_range, MESH_INDICES_COLLIDER}, types::TrackType}
+};
+use super::LineRider3DEnv;
+
+pub const TRACK_FILE_VERSION: u32 = 1;
+/** Config keys that are derived from start, goal and checkpoint of the track and therefore not stored as overrides */
+const DERIVED_CONFIG_KEYS: [&str; 7] = ["config_version", "goal_position", "goal_pos", "checkpoint_pos", "checkpoint_range", "checkpoint_positions", "checkpoint_ranges"];
+
+#[derive(Debug)]
+pub enum TrackFileError {
+  Io(io::Error),
+  Parse(serde_json::Error),
+  UnsupportedVersion(u32),
+  Config(Vec<ConfigError>),
+  /** Index into points of a point that lies outside of the build range */
+  PointOutOfBounds(usize),
+  /** Every point is a piece the agent could have placed, so a track can't have more of them than step_limit */
+  TooManyPoints {points: usize, step_limit: usize},
+  /** The target type of the config decides whether there is a single checkpoint or a sequence, the file has to agree with it */
+  CheckpointMismatch {target_has_checkpoint: bool}
+}
+
+impl fmt::Display for TrackFileError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      TrackFileError::Io(error) => {write!(f, "could not access track file: {}", error)},
+      TrackFileError::Parse(error) => {write!(f, "could not parse track file: {}", error)},
+      TrackFileError::UnsupportedVersion(version) => {write!(f, "track file version {} is newer than the supported version {}", version, TRACK_FILE_VERSION)},
+      TrackFileError::Config(errors) => {f.write_str(&format_config_errors(errors))},
+      TrackFileError::PointOutOfBounds(index) => {write!(f, "point {} lies outside of the build range", index)},
+      TrackFileError::TooManyPoints {points, step_limit} => {write!(f, "the track has {} points but step_limit only allows {}", points, step_limit)},
+      TrackFileError::CheckpointMismatch {target_has_checkpoint: true} => {f.write_str("the track does not have the kind of checkpoints the target type needs")},
+      TrackFileError::CheckpointMismatch {target_has_checkpoint: false} => {f.write_str("the track has checkpoints but the target type does not use any")}
+    }
+  }
+}
+
+impl std::error::Error for TrackFileError {}
+
+impl From<io::Error> for TrackFileError {
+  fn from(error: io::Error) -> TrackFileError {TrackFileError::Io(error)}
+}
+
+impl From<serde_json::Error> for TrackFileError {
+  fn from(error: serde_json::Error) -> TrackFileError {TrackFileError::Parse(error)}
+}
+
+fn default_track_type() -> TrackType {TrackType::Normal}
+
+/** Track files are meant to be edited by hand, so the track type is written by name */
+fn serialize_track_type_name<S: Serializer>(track_type: &TrackType, serializer: S) -> Result<S::Ok, S::Error> {
+  serializer.serialize_str(track_type.name())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrackFilePoint {
+  pub position: [f32; 3],
+  /** Surface of the piece that ends in this point, either its name or its TP_* id */
+  #[serde(default = "default_track_type", serialize_with = "serialize_track_type_name")]
+  pub track_type: TrackType
+}
+
+/**
+ * Fixed layout for LineRider3DEnv, stored as JSON:
+ * {"version": 1, "start": [0, 0, 0], "points": [{"position": [1, -1, 0], "track_type": "boost"}], "goal": [3, -3, 0]}
+ * Goal and checkpoint are the centres of cubes with the half width goal_size, which can be set in config like any other key.
+ * Tracks for the target type random_with_checkpoints list their checkpoints in the order they have to be reached instead.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TrackFile {
+  pub version: u32,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+  pub start: [f32; 3],
+  /** Track points after the start in the order they are connected */
+  #[serde(default)]
+  pub points: Vec<TrackFilePoint>,
+  pub goal: [f32; 3],
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub checkpoint: Option<[f32; 3]>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub checkpoints: Vec<[f32; 3]>,
+  /** Boxes the track has to route around, they replace the obstacles the env would generate */
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub obstacles: Vec<Range3D<f32>>,
+  /** Overrides of the LineRiderConfig, applied on top of the config of the env the track is loaded into */
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub config: BTreeMap<String, serde_json::Value>
+}
+
+impl TrackFile {
+  pub fn from_json(json: &str) -> Result<TrackFile, TrackFileError> {
+    let track: TrackFile = serde_json::from_str(json)?;
+    if track.version > TRACK_FILE_VERSION {
+      return Err(TrackFileError::UnsupportedVersion(track.version));
+    }
+    Ok(track)
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).expect("TrackFile is serializable")
+  }
+
+  /** config in the string form that try_load_config expects */
+  pub fn config_overrides(&self) -> HashMap<String, String> {
+    self.config.iter().map(|(key, value)| {
+      let raw_value = match value {
+        serde_json::Value::String(string) => string.clone(),
+        other => other.to_string()
+      };
+      (key.clone(), raw_value)
+    }).collect()
+  }
+}
+
+pub fn load_track_file<P: AsRef<Path>>(path: P) -> Result<TrackFile, TrackFileError> {
+  TrackFile::from_json(&std::fs::read_to_string(path)?)
+}
+
+pub fn save_track_file<P: AsRef<Path>>(path: P, track: &TrackFile) -> Result<(), TrackFileError> {
+  std::fs::write(path, track.to_json())?;
+  Ok(())
+}
+
+/** Keys of config whose values differ from the default config */
+fn config_differences(config: &LineRiderConfig) -> BTreeMap<String, serde_json::Value> {
+  let current = serde_json::to_value(config).expect("LineRiderConfig is serializable");
+  let default = serde_json::to_value(LineRiderConfig::default()).expect("LineRiderConfig is serializable");
+  current.as_object().expect("LineRiderConfig serializes to an object").iter()
+    .filter(|(key, value)| !DERIVED_CONFIG_KEYS.contains(&key.as_str()) && default.get(key.as_str()) != Some(value))
+    .map(|(key, value)| (key.clone(), value.clone()))
+    .collect()
+}
+
+impl LineRider3DEnv {
+  /** Current track, goal and checkpoint together with the config keys that differ from the default */
+  pub fn to_track_file(&self, name: Option<String>) -> TrackFile {
+    let config = &self.sim.config;
+    TrackFile {
+      version: TRACK_FILE_VERSION,
+      name,
+      start: self.lines[0].0.to_array(),
+      points: self.lines[1..].iter().map(|(position, track_type)| TrackFilePoint {
+        position: position.to_array(),
+        track_type: TrackType::try_from(*track_type).unwrap_or(TrackType::Normal)
+      }).collect(),
+      goal: config.goal_pos.to_array(),
+      checkpoint: if config.with_checkpoint() && !config.with_checkpoint_sequence() {Some(config.checkpoint_pos.to_array())} else {None},
+      checkpoints: config.checkpoint_positions.iter().map(|checkpoint| checkpoint.to_array()).collect(),
+      obstacles: self.sim.obstacles.clone(),
+      config: config_differences(config)
+    }
+  }
+
+  pub fn save_track<P: AsRef<Path>>(&self, path: P, name: Option<String>) -> Result<(), TrackFileError> {
+    save_track_file(path, &self.to_track_file(name))
+  }
+
+  /**
+   * Starts a new episode on the given track instead of a generated start and goal.
+   * The config overrides are applied first and stay active for following episodes.
+   * The track is validated before anything is changed, so a track that can't be loaded leaves the env as it was.
+   * The points are placed as given without collision checks, building continues from the last one and every loaded piece counts as a step.
+   */
+  pub fn reset_to_track(&mut self, track: &TrackFile) -> Result<Observation, TrackFileError> {
+    if track.version > TRACK_FILE_VERSION {
+      return Err(TrackFileError::UnsupportedVersion(track.version));
+    }
+    let mut config = self.sim.config.clone();
+    config.apply_string_map(&track.config_overrides()).map_err(TrackFileError::Config)?;
+    config.validate().map_err(TrackFileError::Config)?;
+    let target_has_checkpoint = config.with_checkpoint();
+    let (has_expected, has_unexpected) = if config.with_checkpoint_sequence() {
+      (!track.checkpoints.is_empty(), track.checkpoint.is_some())
+    } else {
+      (track.checkpoint.is_some(), !track.checkpoints.is_empty())
+    };
+    if target_has_checkpoint != has_expected || has_unexpected {
+      return Err(TrackFileError::CheckpointMismatch {target_has_checkpoint});
+    }
+    if track.points.len() > config.step_limit {
+      return Err(TrackFileError::TooManyPoints {points: track.points.len(), step_limit: config.step_limit});
+    }
+    let start = Vec3::from_array(track.start);
+    let origin = if config.use_new_goalgen {Some(start)} else {None};
+    let build_range = make_build_range(&config, 0.5, &origin);
+    if let Some(index) = track.points.iter().position(|point| !build_range.vec3_in_range(&Vec3::from_array(point.position))) {
+      return Err(TrackFileError::PointOutOfBounds(index));
+    }
+
+    self.set_config(config).map_err(TrackFileError::Config)?;
+    self.reset_state();
+    self.lines.clear();
+    self.lines.push((start, TP_NORMAL));
+    self.sim.origin = origin;
+    self.sim.set_max_width(self.sim.config.max_width);
+    self.sim.set_obstacles(track.obstacles.clone());
+    for point in &track.points {
+      self.place_loaded_piece((Vec3::from_array(point.position), u8::from(point.track_type)));
+    }
+    self.current_step = self.lines.len() - 1;
+    self.sim.set_goal_position(Vec3::from_array(track.goal));
+    if let Some(checkpoint) = track.checkpoint {
+      let checkpoint = Vec3::from_array(checkpoint);
+      self.sim.config.checkpoint_range = make_goal_range(&checkpoint, &self.sim.config);
+      self.sim.config.checkpoint_pos = checkpoint;
+    }
+    if self.sim.config.with_checkpoint_sequence() {
+      self.sim.config.checkpoint_positions = track.checkpoints.iter().map(|checkpoint| Vec3::from_array(*checkpoint)).collect();
+      self.sim.config.checkpoint_ranges = self.sim.config.checkpoint_positions.iter().map(|checkpoint| make_goal_range(checkpoint, &self.sim.config)).collect();
+    }
+    self.update_loop_start();
+    self.track_distance_of_last_step = self.sim.config.goal_pos.distance(self.lines[self.lines.len()-1].0);
+    {
+      let mut app_config: Mut<LineRiderConfig> = self.sim.app.world.resource_mut();
+      app_config.copy_from(&self.sim.config);
+    }
+    Ok(self.make_obs())
+  }
+
+  /** Adds the piece like add_line_for_point would, including its colliders and undo entry, but without range and collision checks */
+  fn place_loaded_piece(&mut self, track_point: TrackPoint) {
+    let undo_info = self.make_undo_info();
+    let prev_point = self.lines[self.lines.len()-1].0;
+    let (all_points, _) = get_free_mesh_points(prev_point, track_point.0, &self.prev_points, &self.sim.config);
+    self.prev_points = [all_points[1][1], all_points[2][1], all_points[3][1], all_points[4][1]];
+    // same as place_line_for_point, which only builds colliders when they are checked against
+    if track_point.1 != TP_EMPTY && self.sim.config.skip_collision_check_on_last_x_pieces != 999 {
+      self.line_colliders.push(Collider::trimesh(get_mesh_points(&all_points, 1), MESH_INDICES_COLLIDER.to_vec()));
+    }
+    self.lines.push(track_point);
+    self.undo_history.push(undo_info);
+    self.update_loop_start();
+  }
+
+  pub fn reset_to_track_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Observation, TrackFileError> {
+    let track = load_track_file(path)?;
+    self.reset_to_track(&track)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rusty_gym::GymEnvironment;
+  use ndarray::ArrayBase;
+  use crate::{env::tracks::prepare_bezier_jump, util::types::{ActionType, TargetType}};
+
+  #[test]
+  fn hand_written_track_loads_into_env() {
+    let json = r#"{
+      "version": 1,
+      "name": "small jump",
+      "start": [0, 0, 0],
+      "points": [
+        {"position": [1, -1, 0]},
+        {"position": [2, -1.5, 0], "track_type": "boost"},
+        {"position": [3, -1, 0], "track_type": 3}
+      ],
+      "goal": [6, 0, 0],
+      "config": {"action_type": "free_points", "max_width": 30}
+    }"#;
+    let track = TrackFile::from_json(json).unwrap();
+    let mut env = LineRider3DEnv::default();
+    env.skip_simulation = true;
+    env.reset_to_track(&track).unwrap();
+    assert_eq!(env.sim.config.action_type, ActionType::FreePoints);
+    assert_eq!(env.sim.config.max_width, 30.0);
+    assert_eq!(env.lines, vec![
+      (Vec3::ZERO, TP_NORMAL),
+      (Vec3::new(1.0, -1.0, 0.0), TP_NORMAL),
+      (Vec3::new(2.0, -1.5, 0.0), TP_ACCELERATE),
+      (Vec3::new(3.0, -1.0, 0.0), TP_DECELERATE)
+    ]);
+    assert_eq!(env.sim.config.goal_pos, Vec3::new(6.0, 0.0, 0.0));
+    assert!(env.sim.config.goal_position.vec3_in_range(&Vec3::new(6.0, 0.0, 0.0)));
+  }
+
+  #[test]
+  fn saved_track_round_trips() {
+    let mut env = LineRider3DEnv::default();
+    env.sim.config.action_type = ActionType::FreePointsWithTp;
+    env.skip_simulation = true;
+    env.reset();
+    prepare_bezier_jump(&mut env);
+    let track = env.to_track_file(Some("bezier jump".to_owned()));
+    assert_eq!(track.config.get("action_type"), Some(&serde_json::json!(4)));
+    assert!(track.config.contains_key("max_width"));
+    let loaded = TrackFile::from_json(&track.to_json()).unwrap();
+    assert_eq!(loaded, track);
+
+    let mut other_env = LineRider3DEnv::default();
+    other_env.skip_simulation = true;
+    other_env.reset_to_track(&loaded).unwrap();
+    assert_eq!(other_env.lines, env.lines);
+    assert_eq!(other_env.sim.config.goal_pos, env.sim.config.goal_pos);
+    assert_eq!(other_env.sim.config.action_type, ActionType::FreePointsWithTp);
+  }
+
+  #[test]
+  fn rejects_invalid_tracks() {
+    let newer = r#"{"version": 2, "start": [0, 0, 0], "goal": [1, 0, 0]}"#;
+    assert!(matches!(TrackFile::from_json(newer), Err(TrackFileError::UnsupportedVersion(2))));
+    let unknown_type = r#"{"version": 1, "start": [0, 0, 0], "points": [{"position": [1, 0, 0], "track_type": "ice"}], "goal": [1, 0, 0]}"#;
+    assert!(matches!(TrackFile::from_json(unknown_type), Err(TrackFileError::Parse(_))));
+
+    let mut env = LineRider3DEnv::default();
+    env.skip_simulation = true;
+    let far_away = TrackFile::from_json(r#"{"version": 1, "start": [0, 0, 0], "points": [{"position": [500, 0, 0]}], "goal": [1, 0, 0]}"#).unwrap();
+    assert!(matches!(env.reset_to_track(&far_away), Err(TrackFileError::PointOutOfBounds(0))));
+    let mut too_long = far_away.clone();
+    too_long.points = (1..=4).map(|x| TrackFilePoint {position: [x as f32, 0.0, 0.0], track_type: TrackType::Normal}).collect();
+    too_long.config.insert("step_limit".to_owned(), serde_json::json!(3));
+    too_long.config.insert("max_width".to_owned(), serde_json::json!(30));
+    let config_before = env.sim.config.to_string_map();
+    assert!(matches!(env.reset_to_track(&too_long), Err(TrackFileError::TooManyPoints {points: 4, step_limit: 3})));
+    assert_eq!(env.sim.config.to_string_map(), config_before);
+    let mut with_checkpoint = far_away.clone();
+    with_checkpoint.points.clear();
+    with_checkpoint.config.insert("target_type".to_owned(), serde_json::json!(TargetType::RandomWithCheckpointAbove.name()));
+    assert!(matches!(env.reset_to_track(&with_checkpoint), Err(TrackFileError::CheckpointMismatch {target_has_checkpoint: true})));
+  }
+
+  #[test]
+  fn loaded_track_continues_like_a_built_one() {
+    let json = r#"{
+      "version": 1,
+      "start": [0, 0, 0],
+      "points": [{"position": [1, -1, 0]}, {"position": [2, -1, 0], "track_type": "empty"}, {"position": [3, -1.5, 0]}],
+      "goal": [6, 0, 0],
+      "config": {"action_type": "free_points", "step_limit": 4, "allow_undo": true, "skip_collision_check_on_last_x_pieces": 1}
+    }"#;
+    let mut env = LineRider3DEnv::default();
+    env.skip_simulation = true;
+    env.reset_to_track(&TrackFile::from_json(json).unwrap()).unwrap();
+    assert_eq!(env.current_step, 3);
+    assert_eq!(env.undo_history.len(), 3);
+    let mut built_env = LineRider3DEnv::default();
+    built_env.sim.config.action_type = ActionType::FreePoints;
+    built_env.sim.config.step_limit = 4;
+    built_env.sim.config.skip_collision_check_on_last_x_pieces = 1;
+    built_env.skip_simulation = true;
+    built_env.reset();
+    built_env.lines = vec![(Vec3::ZERO, TP_NORMAL)];
+    for point in &env.lines[1..] {
+      assert!(built_env.add_line_for_point(*point));
+    }
+    assert_eq!(env.line_colliders.len(), 2);
+    assert_eq!(env.line_colliders.len(), built_env.line_colliders.len());
+    assert_eq!(env.prev_points, built_env.prev_points);
+    env.step(&ArrayBase::from(vec![4.0, -1.5, 0.0]).into_dyn());
+    let step = env.step(&ArrayBase::from(vec![5.0, -1.5, 0.0]).into_dyn());
+    assert!(step.is_done);
+    assert!(env.undo_last_piece());
+    assert_eq!(env.lines.len(), 4);
+  }
+}
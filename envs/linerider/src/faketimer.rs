@@ -33,6 +33,14 @@ impl Plugin for FakeTimePlugin {
     }
 }
 
+/// Puts the time resources back into the state FakeTimePlugin creates them in
+pub fn reset_fake_time(world: &mut World) {
+    world.insert_resource(Time::<()>::default());
+    world.insert_resource(Time::<Virtual>::from_max_delta(Duration::from_secs(6)));
+    world.insert_resource(Time::<Fixed>::from_duration(Duration::from_secs(2)));
+    world.insert_resource(FakeTimeStepSize::default());
+}
+
 fn fake_time_system(
     mut time: ResMut<Time>,
     mut v_time: ResMut<Time::<Virtual>>,
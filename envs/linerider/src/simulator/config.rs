@@ -1,12 +1,20 @@
 use bevy::prelude::{Vec3, Resource};
-use crate::util::{range::Range3D, consts::*};
-use std::collections::HashMap;
+use crate::util::{consts::{STATIC_GOAL_POSITION, STATIC_START_POSITION}, range::Range3D, track::make_goal_range, types::*};
+use serde::{Serialize, Deserialize};
+use rusty_gym::StepInfo;
+use std::collections::{BTreeMap, HashMap};
+
+pub const LINERIDER_CONFIG_VERSION: u32 = 1;
+/** Keys that string configs of recorded runs contain but which are not part of the LineRiderConfig */
+pub const CONFIG_METADATA_KEYS: [&str; 2] = ["name", "run_id"];
 
 #[derive(Clone, Debug, Default)]
 pub struct LineRiderSimulationResult {
   pub steps_taken: usize,
   pub goal_reached: bool,
   pub checkpoint_reached: bool,
+  /** Checkpoints the rider passed in the order they have to be reached */
+  pub checkpoints_reached: usize,
   pub velocity_at_end: f32,
   pub overall_velocity: f32,
   pub overall_rotation: f32,
@@ -15,13 +23,39 @@ pub struct LineRiderSimulationResult {
   pub ended_because_of_no_movement: bool,
   pub time_rider_touched_track: u64,
   pub time_rider_airborne: u64,
-  pub total_time: u64
+  pub total_time: u64,
+  /** Laps the rider completed on a TargetType::LoopTrack */
+  pub laps: usize,
+  /** Seconds the fastest completed lap took, 0 without a completed lap */
+  pub lap_time: f32,
+  /** State of the rider every trajectory_stride steps and at the end, empty if trajectory_stride is 0 */
+  pub trajectory: Vec<TrajectorySample>
 }
 
 impl LineRiderSimulationResult {
+  pub fn to_info(&self) -> StepInfo {
+    let mut info = StepInfo::new();
+    info.insert("steps_taken".to_owned(), self.steps_taken.into());
+    info.insert("goal_reached".to_owned(), self.goal_reached.into());
+    info.insert("checkpoint_reached".to_owned(), self.checkpoint_reached.into());
+    info.insert("checkpoints_reached".to_owned(), self.checkpoints_reached.into());
+    info.insert("velocity_at_end".to_owned(), self.velocity_at_end.into());
+    info.insert("overall_velocity".to_owned(), self.overall_velocity.into());
+    info.insert("overall_rotation".to_owned(), self.overall_rotation.into());
+    info.insert("overall_height_gain".to_owned(), self.overall_height_gain.into());
+    info.insert("closest_to_goal".to_owned(), self.closest_to_goal.into());
+    info.insert("ended_because_of_no_movement".to_owned(), self.ended_because_of_no_movement.into());
+    info.insert("time_rider_touched_track".to_owned(), self.time_rider_touched_track.into());
+    info.insert("time_rider_airborne".to_owned(), self.time_rider_airborne.into());
+    info.insert("total_time".to_owned(), self.total_time.into());
+    info.insert("laps".to_owned(), self.laps.into());
+    info.insert("lap_time".to_owned(), self.lap_time.into());
+    info
+  }
   pub fn to_map(&self) -> HashMap<String, String> {
     let mut additional_info: HashMap<String, String> = HashMap::new();
     additional_info.insert("checkpoint_reached".to_owned(), self.checkpoint_reached.to_string());
+    additional_info.insert("checkpoints_reached".to_owned(), self.checkpoints_reached.to_string());
     additional_info.insert("total_time".to_owned(), self.total_time.to_string());
     additional_info.insert("velocity_at_end".to_owned(), self.velocity_at_end.to_string());
     additional_info.insert("overall_velocity".to_owned(), self.overall_velocity.to_string());
@@ -33,12 +67,112 @@ impl LineRiderSimulationResult {
     additional_info.insert("time_rider_airborne".to_owned(), self.time_rider_airborne.to_string());
     additional_info.insert("total_time".to_owned(), self.total_time.to_string());
     additional_info.insert("steps_taken".to_owned(), self.total_time.to_string());
+    additional_info.insert("laps".to_owned(), self.laps.to_string());
+    additional_info.insert("lap_time".to_owned(), self.lap_time.to_string());
+    if !self.trajectory.is_empty() {
+      additional_info.insert("trajectory".to_owned(), serde_json::to_string(&self.trajectory).expect("trajectory is serializable"));
+    }
     additional_info
   }
 }
 
-#[derive(Clone, Resource)]
+/** State of the rider after a simulation step, recorded by simulate_till_end */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrajectorySample {
+  pub step: usize,
+  pub position: [f32; 3],
+  /** Quaternion as x, y, z, w */
+  pub rotation: [f32; 4],
+  pub linear_velocity: [f32; 3],
+  pub angular_velocity: [f32; 3],
+  pub touching_track: bool,
+  /** Velocity added by the booster the rider is on, zero if there is none */
+  pub active_booster: [f32; 3]
+}
+
+/**
+ * Laps of a rider on a TargetType::LoopTrack, which starts in the goal.
+ * A lap is counted when the rider is back in the goal after it left the surroundings of the goal.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct LapCounter {
+  left_start: bool,
+  lap_started_at: usize,
+  pub laps: usize,
+  pub fastest_lap_steps: Option<usize>
+}
+
+impl LapCounter {
+  pub fn update(&mut self, config: &LineRiderConfig, rider_position: &Vec3, step: usize) {
+    if !config.goal_position.expanded(config.goal_size).vec3_in_range(rider_position) {
+      self.left_start = true;
+    } else if self.left_start && config.goal_position.vec3_in_range(rider_position) {
+      self.left_start = false;
+      self.laps += 1;
+      let lap_steps = step - self.lap_started_at;
+      self.fastest_lap_steps = Some(self.fastest_lap_steps.map_or(lap_steps, |fastest| fastest.min(lap_steps)));
+      self.lap_started_at = step;
+    }
+  }
+  /** Seconds of the fastest lap with physics_delta milliseconds per step */
+  pub fn lap_time(&self, physics_delta: u64) -> f32 {
+    self.fastest_lap_steps.map_or(0.0, |steps| (steps as u64 * physics_delta) as f32 / 1000.0)
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+  UnknownKey(String),
+  InvalidValue {key: String, value: String, reason: String},
+  OutOfRange {key: String, value: String, expected: String},
+  IncompatibleCombination {keys: Vec<String>, reason: String},
+  UnsupportedVersion(u32)
+}
+
+impl std::fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ConfigError::UnknownKey(key) => {write!(f, "unknown key '{}'", key)},
+      ConfigError::InvalidValue {key, value, reason} => {write!(f, "value '{}' of '{}' can not be parsed: {}", value, key, reason)},
+      ConfigError::OutOfRange {key, value, expected} => {write!(f, "value '{}' of '{}' is out of range, expected {}", value, key, expected)},
+      ConfigError::IncompatibleCombination {keys, reason} => {write!(f, "incompatible combination of {}: {}", keys.join(", "), reason)},
+      ConfigError::UnsupportedVersion(version) => {write!(f, "config version {} is newer than the supported version {}", version, LINERIDER_CONFIG_VERSION)}
+    }
+  }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub fn format_config_errors(errors: &[ConfigError]) -> String {
+  let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+  format!("Invalid LineRider config: {}", messages.join("; "))
+}
+
+/**
+ * Older runs were recorded while unknown reward ids were silently ignored, e.g. "[254, 0]".
+ * Those ids never had an effect, so they are dropped to keep such .tlr files loadable.
+ * Returns the cleaned config and the dropped ids, so the caller can decide whether to report them.
+ */
+pub fn drop_unknown_reward_ids(config: &HashMap<String, String>) -> (HashMap<String, String>, Vec<serde_json::Value>) {
+  let mut cleaned = config.clone();
+  let mut dropped = Vec::new();
+  if let Some(raw_rewards) = config.get("reward_type") {
+    if let Ok(rewards) = serde_json::from_str::<Vec<serde_json::Value>>(raw_rewards) {
+      let (known, unknown): (Vec<serde_json::Value>, Vec<serde_json::Value>) = rewards.into_iter()
+        .partition(|reward| serde_json::from_value::<RewardComponent>(reward.clone()).is_ok());
+      if !unknown.is_empty() {
+        cleaned.insert("reward_type".to_owned(), serde_json::to_string(&known).expect("reward ids are serializable"));
+        dropped = unknown;
+      }
+    }
+  }
+  (cleaned, dropped)
+}
+
+#[derive(Clone, Debug, Resource, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct LineRiderConfig {
+  pub config_version: u32,
   // Simulation Settings
   pub max_width: f32,
   pub max_height: f32,
@@ -58,17 +192,29 @@ pub struct LineRiderConfig {
   pub goal_pos: Vec3,
   pub checkpoint_pos: Vec3,
   pub checkpoint_range: Range3D<f32>,
+  /** Ordered checkpoints of TargetType::RandomWithCheckpoints, derived like checkpoint_pos */
+  pub checkpoint_positions: Vec<Vec3>,
+  pub checkpoint_ranges: Vec<Range3D<f32>>,
   pub premature_end_min_distance: f32,
   pub premature_end_after_steps_without_movement: usize,
   pub booster_strength: f32,
   pub use_cylinder_track: bool,
   // Env settings
-  pub observation_type: u8,
-  pub action_type: u8,
-  pub reward_type: Vec<u8>,
-  pub target_type: u8,
+  pub observation_type: ObservationType,
+  pub action_type: ActionType,
+  pub reward_type: Vec<RewardComponent>,
+  /** Overrides weight, normalisation or clip of reward components, listed components are enabled as well */
+  pub reward_spec: BTreeMap<RewardComponent, RewardTermSpec>,
+  pub target_type: TargetType,
+  /** Amount of checkpoints placed for TargetType::RandomWithCheckpoints */
+  pub checkpoint_count: usize,
+  pub checkpoint_observation: CheckpointObservation,
+  /** Laps the rider has to complete on a TargetType::LoopTrack to reach the goal */
+  pub loop_laps: usize,
   pub step_limit: usize,
   pub simulation_steps: usize,
+  /** Record a TrajectorySample every this many simulation steps, 0 disables the recording */
+  pub trajectory_stride: usize,
   pub max_piece_length: f32,
   pub physics_delta: u64,
   pub physics_substeps: usize,
@@ -76,17 +222,40 @@ pub struct LineRiderConfig {
   pub skip_collision_check_on_last_x_pieces: usize,
   pub max_up_angle: Vec<f32>,
   pub obs_sliding_window_size: usize,
-  pub use_new_goalgen: bool
+  /** Divides the coordinates of ObservationType::Egocentric by track_piece_length */
+  pub obs_normalize_by_piece_length: bool,
+  pub use_new_goalgen: bool,
+  /** Adds an action that removes the last track piece, it uses up a step like any other action */
+  pub allow_undo: bool,
+  /** Boxes the track has to route around in every episode, in addition to the generated ones */
+  pub obstacles: Vec<Range3D<f32>>,
+  /** Amount of boxes and pillars placed at random in the build range each episode */
+  pub obstacle_count: usize,
+  /** Largest half width of the generated obstacles */
+  pub obstacle_size: f32
 }
 
 impl LineRiderConfig {
   pub fn with_checkpoint(&self) -> bool {
-    match self.target_type {
-      TARGET_RANDOM_WITH_CHECKPOINT_ABOVE | TARGET_RANDOM_WITH_CHECKPOINT_BELOW => true,
-      _ => false
-    }
+    self.target_type.has_checkpoint()
+  }
+  pub fn with_checkpoint_sequence(&self) -> bool {
+    self.target_type.has_checkpoint_sequence()
+  }
+  pub fn uses_reward(&self, component: RewardComponent) -> bool {
+    self.reward_type.contains(&component) || self.reward_spec.contains_key(&component)
+  }
+  /** Components of reward_type in their configured order followed by the ones only listed in reward_spec */
+  pub fn active_rewards(&self) -> Vec<RewardComponent> {
+    let mut active = self.reward_type.clone();
+    active.extend(self.reward_spec.keys().filter(|component| !self.reward_type.contains(component)));
+    active
+  }
+  pub fn reward_term(&self, component: RewardComponent) -> RewardTerm {
+    component.term_with(self.reward_spec.get(&component))
   }
   pub fn copy_from(&mut self, other_conf: &LineRiderConfig) {
+    self.config_version = other_conf.config_version;
     self.max_width = other_conf.max_width;
     self.max_height = other_conf.max_height;
     self.rider_shape = other_conf.rider_shape;
@@ -102,17 +271,25 @@ impl LineRiderConfig {
     self.smooth_free_points = other_conf.smooth_free_points;
     self.goal_position = other_conf.goal_position.clone();
     self.goal_pos = other_conf.goal_pos;
+    self.goal_size = other_conf.goal_size;
     self.checkpoint_pos = other_conf.checkpoint_pos;
     self.checkpoint_range = other_conf.checkpoint_range.clone();
+    self.checkpoint_positions = other_conf.checkpoint_positions.clone();
+    self.checkpoint_ranges = other_conf.checkpoint_ranges.clone();
     self.premature_end_min_distance = other_conf.premature_end_min_distance;
     self.premature_end_after_steps_without_movement = other_conf.premature_end_after_steps_without_movement;
     self.booster_strength = other_conf.booster_strength;
     self.observation_type = other_conf.observation_type;
     self.action_type = other_conf.action_type;
     self.reward_type = other_conf.reward_type.clone();
+    self.reward_spec = other_conf.reward_spec.clone();
     self.target_type = other_conf.target_type;
+    self.checkpoint_count = other_conf.checkpoint_count;
+    self.checkpoint_observation = other_conf.checkpoint_observation;
+    self.loop_laps = other_conf.loop_laps;
     self.step_limit = other_conf.step_limit;
     self.simulation_steps = other_conf.simulation_steps;
+    self.trajectory_stride = other_conf.trajectory_stride;
     self.max_piece_length = other_conf.max_piece_length;
     self.physics_delta = other_conf.physics_delta;
     self.physics_substeps = other_conf.physics_substeps;
@@ -121,13 +298,181 @@ impl LineRiderConfig {
     self.use_cylinder_track = other_conf.use_cylinder_track;
     self.max_up_angle = other_conf.max_up_angle.clone();
     self.obs_sliding_window_size = other_conf.obs_sliding_window_size;
+    self.obs_normalize_by_piece_length = other_conf.obs_normalize_by_piece_length;
     self.use_new_goalgen = other_conf.use_new_goalgen;
+    self.allow_undo = other_conf.allow_undo;
+    self.obstacles = other_conf.obstacles.clone();
+    self.obstacle_count = other_conf.obstacle_count;
+    self.obstacle_size = other_conf.obstacle_size;
+  }
+  /**
+   * String representation used by ReplayableGymEnvironment::get_config and the python bindings.
+   * Numbers and booleans use their plain string form, vectors and ranges are stored as JSON.
+   */
+  pub fn to_string_map(&self) -> HashMap<String, String> {
+    let mut env_conf = HashMap::new();
+    env_conf.insert("config_version".to_owned(), self.config_version.to_string());
+    env_conf.insert("max_width".to_owned(), self.max_width.to_string());
+    env_conf.insert("max_height".to_owned(), self.max_height.to_string());
+    env_conf.insert("rider_shape".to_owned(), self.rider_shape.to_string());
+    env_conf.insert("rider_mass".to_owned(), self.rider_mass.to_string());
+    env_conf.insert("rider_density".to_owned(), self.rider_density.to_string());
+    env_conf.insert("rider_size".to_owned(), serde_json::to_string(&self.rider_size).expect("rider_size is serializable"));
+    env_conf.insert("track_width".to_owned(), self.track_width.to_string());
+    env_conf.insert("track_wall_height".to_owned(), self.track_wall_height.to_string());
+    env_conf.insert("track_wall_width".to_owned(), self.track_wall_width.to_string());
+    env_conf.insert("track_piece_length".to_owned(), self.track_piece_length.to_string());
+    env_conf.insert("starting_force_multiplier".to_owned(), self.starting_force_multiplier.to_string());
+    env_conf.insert("skip_collision_check_on_last_x_pieces".to_owned(), self.skip_collision_check_on_last_x_pieces.to_string());
+    env_conf.insert("bezier_resolution".to_owned(), self.bezier_resolution.to_string());
+    env_conf.insert("smooth_free_points".to_owned(), self.smooth_free_points.to_string());
+    env_conf.insert("goal_size".to_owned(), self.goal_size.to_string());
+    env_conf.insert("goal_position".to_owned(), serde_json::to_string(&self.goal_position).expect("goal_position is serializable"));
+    env_conf.insert("checkpoint_range".to_owned(), serde_json::to_string(&self.checkpoint_range).expect("checkpoint_range is serializable"));
+    env_conf.insert("checkpoint_ranges".to_owned(), serde_json::to_string(&self.checkpoint_ranges).expect("checkpoint_ranges are serializable"));
+    env_conf.insert("observation_type".to_owned(), u8::from(self.observation_type).to_string());
+    env_conf.insert("action_type".to_owned(), u8::from(self.action_type).to_string());
+    env_conf.insert("reward_type".to_owned(), serde_json::to_string(&self.reward_type).expect("Reward type can be serialized to JSON"));
+    env_conf.insert("reward_spec".to_owned(), serde_json::to_string(&self.reward_spec).expect("Reward spec can be serialized to JSON"));
+    env_conf.insert("target_type".to_owned(), u8::from(self.target_type).to_string());
+    env_conf.insert("checkpoint_count".to_owned(), self.checkpoint_count.to_string());
+    env_conf.insert("checkpoint_observation".to_owned(), u8::from(self.checkpoint_observation).to_string());
+    env_conf.insert("loop_laps".to_owned(), self.loop_laps.to_string());
+    env_conf.insert("step_limit".to_owned(), self.step_limit.to_string());
+    env_conf.insert("simulation_steps".to_owned(), self.simulation_steps.to_string());
+    env_conf.insert("trajectory_stride".to_owned(), self.trajectory_stride.to_string());
+    env_conf.insert("max_piece_length".to_owned(), self.max_piece_length.to_string());
+    env_conf.insert("physics_delta".to_owned(), self.physics_delta.to_string());
+    env_conf.insert("physics_substeps".to_owned(), self.physics_substeps.to_string());
+    env_conf.insert("intermediate_simulation_frequency".to_owned(), self.intermediate_simulation_frequency.to_string());
+    env_conf.insert("premature_end_after_steps_without_movement".to_owned(), self.premature_end_after_steps_without_movement.to_string());
+    env_conf.insert("premature_end_min_distance".to_owned(), self.premature_end_min_distance.to_string());
+    env_conf.insert("booster_strength".to_owned(), self.booster_strength.to_string());
+    env_conf.insert("use_cylinder_track".to_owned(), self.use_cylinder_track.to_string());
+    env_conf.insert("max_up_angle".to_owned(), serde_json::to_string(&self.max_up_angle).expect("maxupangle jsonable"));
+    env_conf.insert("obs_sliding_window_size".to_owned(), self.obs_sliding_window_size.to_string());
+    env_conf.insert("obs_normalize_by_piece_length".to_owned(), self.obs_normalize_by_piece_length.to_string());
+    env_conf.insert("use_new_goalgen".to_owned(), self.use_new_goalgen.to_string());
+    env_conf.insert("allow_undo".to_owned(), self.allow_undo.to_string());
+    env_conf.insert("obstacles".to_owned(), serde_json::to_string(&self.obstacles).expect("obstacles are serializable"));
+    env_conf.insert("obstacle_count".to_owned(), self.obstacle_count.to_string());
+    env_conf.insert("obstacle_size".to_owned(), self.obstacle_size.to_string());
+    env_conf
+  }
+  /**
+   * Applies a (partial) string config on top of this one.
+   * Each value is read as JSON so that numbers, booleans, vectors and ranges share one code path.
+   * Nothing is changed if any key is unknown, can not be parsed or the result does not validate.
+   */
+  pub fn apply_string_map(&mut self, config: &HashMap<String, String>) -> Result<(), Vec<ConfigError>> {
+    let mut errors: Vec<ConfigError> = Vec::new();
+    let mut merged = serde_json::to_value(&*self).expect("LineRiderConfig is serializable");
+    let mut keys: Vec<&String> = config.keys().collect();
+    keys.sort();
+    for key in keys {
+      if CONFIG_METADATA_KEYS.contains(&key.as_str()) {
+        continue;
+      }
+      let raw_value = &config[key];
+      let fields = merged.as_object_mut().expect("LineRiderConfig serializes to an object");
+      if !fields.contains_key(key) {
+        errors.push(ConfigError::UnknownKey(key.clone()));
+        continue;
+      }
+      let value = serde_json::from_str(raw_value).unwrap_or(serde_json::Value::String(raw_value.clone()));
+      let previous_value = fields.insert(key.clone(), value);
+      if let Err(parse_error) = serde_json::from_value::<LineRiderConfig>(merged.clone()) {
+        errors.push(ConfigError::InvalidValue {key: key.clone(), value: raw_value.clone(), reason: parse_error.to_string()});
+        if let Some(previous) = previous_value {
+          merged.as_object_mut().expect("LineRiderConfig serializes to an object").insert(key.clone(), previous);
+        }
+      }
+    }
+    if !errors.is_empty() {
+      return Err(errors);
+    }
+    let new_config: LineRiderConfig = serde_json::from_value(merged).expect("merged config was checked key by key");
+    new_config.validate()?;
+    *self = new_config;
+    Ok(())
+  }
+  pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+    let mut errors: Vec<ConfigError> = Vec::new();
+    let mut check = |is_valid: bool, key: &str, value: String, expected: &str| {
+      if !is_valid {
+        errors.push(ConfigError::OutOfRange {key: key.to_owned(), value, expected: expected.to_owned()});
+      }
+    };
+    check(self.max_width > 0.0, "max_width", self.max_width.to_string(), "> 0");
+    check(self.max_height > 0.0, "max_height", self.max_height.to_string(), "> 0");
+    check(self.rider_mass > 0.0, "rider_mass", self.rider_mass.to_string(), "> 0");
+    check(self.rider_density > 0.0, "rider_density", self.rider_density.to_string(), "> 0");
+    check(self.track_width > 0.0, "track_width", self.track_width.to_string(), "> 0");
+    check(self.track_piece_length > 0.0, "track_piece_length", self.track_piece_length.to_string(), "> 0");
+    check(self.max_piece_length > 0.0, "max_piece_length", self.max_piece_length.to_string(), "> 0");
+    check(self.bezier_resolution >= 1, "bezier_resolution", self.bezier_resolution.to_string(), ">= 1");
+    check(self.goal_size > 0.0, "goal_size", self.goal_size.to_string(), "> 0");
+    check(self.booster_strength >= 0.0, "booster_strength", self.booster_strength.to_string(), ">= 0");
+    check(self.step_limit >= 1, "step_limit", self.step_limit.to_string(), ">= 1");
+    check(self.simulation_steps >= 1, "simulation_steps", self.simulation_steps.to_string(), ">= 1");
+    check(self.physics_delta >= 1, "physics_delta", self.physics_delta.to_string(), ">= 1");
+    check(self.physics_substeps >= 1, "physics_substeps", self.physics_substeps.to_string(), ">= 1");
+    check(self.intermediate_simulation_frequency >= 1, "intermediate_simulation_frequency", self.intermediate_simulation_frequency.to_string(), ">= 1");
+    check(self.checkpoint_count >= 1, "checkpoint_count", self.checkpoint_count.to_string(), ">= 1");
+    check(self.loop_laps >= 1, "loop_laps", self.loop_laps.to_string(), ">= 1");
+    check(self.obstacle_size > 0.0, "obstacle_size", self.obstacle_size.to_string(), "> 0");
+    let valid_obstacles = self.obstacles.iter().all(|o| o.x_min < o.x_max && o.y_min < o.y_max && o.z_min < o.z_max);
+    check(valid_obstacles, "obstacles", serde_json::to_string(&self.obstacles).unwrap_or_default(), "ranges with min < max on every axis");
+    // random starts and goals can't be checked here, generated obstacles keep their distance to them on their own
+    let goal_range = make_goal_range(&STATIC_GOAL_POSITION, self);
+    let blocks_target = self.obstacles.iter().any(|o| {
+      (self.target_type.has_static_start() && o.vec3_in_range(&STATIC_START_POSITION))
+        || (self.target_type.has_static_goal() && o.overlaps(&goal_range))
+    });
+    check(!blocks_target, "obstacles", serde_json::to_string(&self.obstacles).unwrap_or_default(), &format!("ranges that leave start and goal of target type {} free", self.target_type));
+    check(self.obs_sliding_window_size >= 1, "obs_sliding_window_size", self.obs_sliding_window_size.to_string(), ">= 1");
+    check(self.config_version <= LINERIDER_CONFIG_VERSION, "config_version", self.config_version.to_string(), &format!("<= {}", LINERIDER_CONFIG_VERSION));
+    let valid_up_angle = self.max_up_angle.len() == 2 && self.max_up_angle[0] <= self.max_up_angle[1]
+      && self.max_up_angle.iter().all(|angle| (-90.0..=90.0).contains(angle));
+    check(valid_up_angle, "max_up_angle", format!("{:?}", self.max_up_angle), "[min, max] with -90 <= min <= max <= 90");
+    for (component, spec) in &self.reward_spec {
+      let key = format!("reward_spec.{}", component);
+      if let Some(weight) = spec.weight {
+        check(weight.is_finite(), &key, weight.to_string(), "a finite weight");
+      }
+      if let Some(normalize) = spec.normalize {
+        check(normalize.is_finite() && normalize != 0.0, &key, normalize.to_string(), "a finite, non zero normalize");
+      }
+      if let Some([min, max]) = spec.clip {
+        check(min <= max, &key, format!("[{}, {}]", min, max), "clip as [min, max] with min <= max");
+      }
+    }
+    if self.with_checkpoint() {
+      if !self.observation_type.shows_checkpoint() {
+        errors.push(ConfigError::IncompatibleCombination {
+          keys: vec!["target_type".to_owned(), "observation_type".to_owned()],
+          reason: format!("observation type {} has no slot for the checkpoint of target type {}", self.observation_type, self.target_type)
+        });
+      }
+    } else {
+      for reward_type in &self.active_rewards() {
+        if matches!(reward_type, RewardComponent::ReachCheckpoint | RewardComponent::TrackReachCheckpoint
+          | RewardComponent::ReachEachCheckpoint | RewardComponent::TrackReachEachCheckpoint) {
+          errors.push(ConfigError::IncompatibleCombination {
+            keys: vec!["target_type".to_owned(), "reward_type".to_owned()],
+            reason: format!("reward type {} needs a target type with checkpoint but target type is {}", reward_type, self.target_type)
+          });
+        }
+      }
+    }
+    if errors.is_empty() {Ok(())} else {Err(errors)}
   }
 }
 
 impl Default for LineRiderConfig {
   fn default() -> LineRiderConfig {
     LineRiderConfig {
+      config_version: LINERIDER_CONFIG_VERSION,
       max_width: 10.0,
       max_height: 5.0,
       rider_shape: 1,
@@ -148,12 +493,19 @@ impl Default for LineRiderConfig {
       goal_pos: Vec3::new(0.5, 0.125, 0.25),
       checkpoint_pos: Vec3::new(-999.0, -999.0, -999.0),
       checkpoint_range: Range3D::default(),
-      observation_type: 0,
-      action_type: 0,
-      reward_type: vec![REWARD_GOAL_REACHED_BY_BALL, REWARD_SCOLD_INVALID_ACTION, REWARD_DISTANCE_TO_GOAL_IN_SIMULATION],
-      target_type: 0,
+      checkpoint_positions: Vec::new(),
+      checkpoint_ranges: Vec::new(),
+      observation_type: ObservationType::BuildPoints,
+      action_type: ActionType::Static,
+      reward_type: vec![RewardComponent::GoalReachedByBall, RewardComponent::ScoldInvalidAction, RewardComponent::DistanceToGoalInSimulation],
+      reward_spec: BTreeMap::new(),
+      target_type: TargetType::StaticStartAndEnd,
+      checkpoint_count: 3,
+      checkpoint_observation: CheckpointObservation::Next,
+      loop_laps: 1,
       step_limit: 10,
       simulation_steps: (1000/80) * 100,
+      trajectory_stride: 0,
       physics_delta: 80,
       physics_substeps: 1,
       intermediate_simulation_frequency: 3,
@@ -163,14 +515,140 @@ impl Default for LineRiderConfig {
       use_cylinder_track: false,
       max_up_angle: vec![-90.0, 90.0],
       obs_sliding_window_size: 4,
-      use_new_goalgen: false
+      obs_normalize_by_piece_length: false,
+      use_new_goalgen: false,
+      allow_undo: false,
+      obstacles: Vec::new(),
+      obstacle_count: 0,
+      obstacle_size: 1.0
     }
   }
 }
 
-pub fn is_freepoint_actionspace(action_type: u8) -> bool {
+pub fn is_freepoint_actionspace(action_type: ActionType) -> bool {
   match action_type {
-    ACTION_TYPE_STATIC_WITH_BOOST | ACTION_TYPE_STATIC_WITH_EMPTY | ACTION_TYPE_STATIC => {false},
-    _ => {true}
+    ActionType::StaticWithBoost | ActionType::StaticWithEmpty | ActionType::Static => {false},
+    ActionType::FreePoints | ActionType::FreePointsWithTp | ActionType::FreePointsRelative
+      | ActionType::FreePointsWithTpRelative | ActionType::Radial | ActionType::RadialWithTp => {true}
   }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn string_map_round_trip() {
+    let config = LineRiderConfig {
+      max_width: 20.0,
+      reward_type: vec![RewardComponent::GoalReachedByBall, RewardComponent::AirTime],
+      action_type: ActionType::RadialWithTp,
+      reward_spec: BTreeMap::from([(RewardComponent::AirTime, RewardTermSpec {weight: Some(0.5), ..RewardTermSpec::default()})]),
+      max_up_angle: vec![-45.0, 60.0],
+      ..LineRiderConfig::default()
+    };
+    let mut loaded = LineRiderConfig::default();
+    loaded.apply_string_map(&config.to_string_map()).expect("own string map is valid");
+    assert_eq!(loaded.to_string_map(), config.to_string_map());
+    assert_eq!(serde_json::to_string(&loaded).unwrap(), serde_json::to_string(&config).unwrap());
+  }
+  #[test]
+  fn invalid_string_map_is_rejected() {
+    let mut config = LineRiderConfig::default();
+    let mut string_config = HashMap::new();
+    string_config.insert("name".to_owned(), "LineRider3D-Env-v0".to_owned());
+    string_config.insert("reward_typ".to_owned(), "[0]".to_owned());
+    string_config.insert("step_limit".to_owned(), "ten".to_owned());
+    let errors = config.apply_string_map(&string_config).unwrap_err();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0], ConfigError::UnknownKey("reward_typ".to_owned()));
+    assert!(matches!(&errors[1], ConfigError::InvalidValue {key, ..} if key == "step_limit"));
+    assert_eq!(config.step_limit, LineRiderConfig::default().step_limit);
+  }
+  #[test]
+  fn validate_ranges_and_combinations() {
+    assert_eq!(LineRiderConfig::default().validate(), Ok(()));
+    let config = LineRiderConfig {max_width: 0.0, checkpoint_count: 0, max_up_angle: vec![90.0, -90.0], ..LineRiderConfig::default()};
+    let keys: Vec<String> = config.validate().unwrap_err().into_iter().map(|e| match e {
+      ConfigError::OutOfRange {key, ..} => key,
+      other => panic!("unexpected error {:?}", other)
+    }).collect();
+    assert_eq!(keys, vec!["max_width", "checkpoint_count", "max_up_angle"]);
+
+    let spec = RewardTermSpec {normalize: Some(0.0), clip: Some([1.0, -1.0]), ..RewardTermSpec::default()};
+    let config = LineRiderConfig {reward_spec: BTreeMap::from([(RewardComponent::AirTime, spec)]), ..LineRiderConfig::default()};
+    assert_eq!(config.validate().unwrap_err().len(), 2);
+    let config = LineRiderConfig {reward_spec: BTreeMap::from([(RewardComponent::ReachCheckpoint, RewardTermSpec::default())]), ..LineRiderConfig::default()};
+    assert!(matches!(config.validate().unwrap_err()[0], ConfigError::IncompatibleCombination {..}));
+
+    let mut config = LineRiderConfig {
+      target_type: TargetType::RandomWithCheckpointBelow,
+      observation_type: ObservationType::GoalAndLastPoint,
+      ..LineRiderConfig::default()
+    };
+    assert!(matches!(config.validate().unwrap_err()[0], ConfigError::IncompatibleCombination {..}));
+    config.observation_type = ObservationType::SlidingWindow;
+    assert_eq!(config.validate(), Ok(()));
+
+    let on_goal = Range3D::new(STATIC_GOAL_POSITION.x, STATIC_GOAL_POSITION.x, STATIC_GOAL_POSITION.y, STATIC_GOAL_POSITION.y, STATIC_GOAL_POSITION.z, STATIC_GOAL_POSITION.z).expanded(0.25);
+    let mut config = LineRiderConfig {obstacles: vec![on_goal.clone()], ..LineRiderConfig::default()};
+    assert!(matches!(&config.validate().unwrap_err()[0], ConfigError::OutOfRange {key, ..} if key == "obstacles"));
+    config.obstacles = vec![Range3D::new(-0.5, 0.5, -0.5, 0.5, -0.5, 0.5)];
+    assert!(matches!(&config.validate().unwrap_err()[0], ConfigError::OutOfRange {key, ..} if key == "obstacles"));
+    // only the generated goal can be moved out of the way
+    config.target_type = TargetType::StaticStartRandomEnd;
+    assert!(config.validate().is_err());
+    config.obstacles = vec![on_goal];
+    assert_eq!(config.validate(), Ok(()));
+  }
+  #[test]
+  fn copy_from_keeps_every_key() {
+    let mut string_config = HashMap::new();
+    string_config.insert("goal_size".to_owned(), "2.5".to_owned());
+    let mut config = LineRiderConfig::default();
+    config.apply_string_map(&string_config).unwrap();
+    let mut app_config = LineRiderConfig::default();
+    app_config.copy_from(&config);
+    assert_eq!(app_config.goal_size, 2.5);
+    assert_eq!(app_config.to_string_map(), config.to_string_map());
+  }
+  #[test]
+  fn laps_count_returns_to_the_goal() {
+    let config = LineRiderConfig {goal_position: Range3D::new(-1.0, 1.0, -1.0, 1.0, -1.0, 1.0), goal_size: 1.0, ..LineRiderConfig::default()};
+    let mut lap_counter = LapCounter::default();
+    let lap = [Vec3::new(0.0, 0.4, 0.0), Vec3::new(1.5, 0.0, 0.0), Vec3::new(2.5, 0.0, 0.0), Vec3::new(4.0, 0.0, 2.0), Vec3::new(0.5, 0.0, 0.0)];
+    for (step, position) in lap.iter().chain(lap[1..].iter()).enumerate() {
+      lap_counter.update(&config, position, step * 10);
+    }
+    // leaving the goal but staying close to it is no lap
+    assert_eq!(lap_counter.laps, 2);
+    assert_eq!(lap_counter.fastest_lap_steps, Some(40));
+    assert_eq!(lap_counter.lap_time(80), 3.2);
+    assert_eq!(LapCounter::default().lap_time(80), 0.0);
+  }
+  #[test]
+  fn types_load_from_ids_and_names() {
+    let mut config = LineRiderConfig::default();
+    let mut string_config = HashMap::new();
+    string_config.insert("action_type".to_owned(), "6".to_owned());
+    string_config.insert("target_type".to_owned(), "random_with_checkpoint_above".to_owned());
+    string_config.insert("observation_type".to_owned(), "\"sliding_window\"".to_owned());
+    string_config.insert("reward_type".to_owned(), "[0, \"reach_checkpoint\"]".to_owned());
+    config.apply_string_map(&string_config).expect("ids and names are valid");
+    assert_eq!(config.action_type, ActionType::FreePointsWithTpRelative);
+    assert_eq!(config.target_type, TargetType::RandomWithCheckpointAbove);
+    assert_eq!(config.observation_type, ObservationType::SlidingWindow);
+    assert_eq!(config.reward_type, vec![RewardComponent::GoalReachedByBall, RewardComponent::ReachCheckpoint]);
+    assert_eq!(config.to_string_map()["reward_type"], "[0,26]");
+    assert_eq!(config.to_string_map()["target_type"], "8");
+
+    let mut typo_config = HashMap::new();
+    typo_config.insert("reward_type".to_owned(), "[254, 0]".to_owned());
+    let errors = LineRiderConfig::default().apply_string_map(&typo_config).unwrap_err();
+    assert!(matches!(&errors[0], ConfigError::InvalidValue {key, ..} if key == "reward_type"));
+    let mut legacy = LineRiderConfig::default();
+    let (cleaned, dropped) = drop_unknown_reward_ids(&typo_config);
+    assert_eq!(dropped, vec![serde_json::json!(254)]);
+    legacy.apply_string_map(&cleaned).expect("unknown ids are dropped");
+    assert_eq!(legacy.reward_type, vec![RewardComponent::GoalReachedByBall]);
+    assert!(drop_unknown_reward_ids(&cleaned).1.is_empty());
+  }
+}
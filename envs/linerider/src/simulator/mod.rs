@@ -5,15 +5,15 @@ pub mod system;
 pub use system::*;
 
 use bevy::{
-  app::{App, First, FixedUpdate, Last, PostStartup, PostUpdate, PreStartup, PreUpdate, RunFixedUpdateLoop, ScheduleRunnerPlugin, Startup, StateTransition}, asset::AssetPlugin, ecs::schedule::{common_conditions::in_state, IntoSystemConfigs, NextState, OnEnter, OnExit, Schedule, ScheduleLabel, States}, input::keyboard::KeyCode, log::LogPlugin, math::Vec3, pbr::PbrPlugin, prelude::{Entity, FrameCountPlugin, ImagePlugin, Mut, PluginGroup, SystemSet, TaskPoolPlugin, Transform, TypeRegistrationPlugin, Update}, render::{settings::WgpuSettings, RenderPlugin}, scene::ScenePlugin, transform::TransformPlugin, utils::default, window::WindowPlugin, DefaultPlugins
+  app::{App, First, FixedUpdate, Last, PostStartup, PostUpdate, PreStartup, PreUpdate, RunFixedUpdateLoop, ScheduleRunnerPlugin, Startup, StateTransition}, asset::{Asset, AssetApp, AssetId, AssetPlugin, Assets}, core::FrameCount, ecs::{event::Events, world::World, schedule::{apply_state_transition, common_conditions::in_state, IntoSystemConfigs, NextState, OnEnter, OnExit, Schedule, ScheduleLabel, States}}, input::keyboard::KeyCode, log::LogPlugin, math::Vec3, pbr::StandardMaterial, scene::ScenePlugin, prelude::{Entity, FrameCountPlugin, ImagePlugin, Mesh, Mut, PluginGroup, SystemSet, TaskPoolPlugin, Transform, TypeRegistrationPlugin, Update}, transform::TransformPlugin, DefaultPlugins
 };
 use bevy_rapier3d::{
-  geometry::Collider, prelude::{RapierPhysicsPlugin, NoUserData, 
-    Velocity,  RapierConfiguration, TimestepMode}, render::RapierDebugRenderPlugin
+  geometry::Collider, prelude::{RapierPhysicsPlugin, NoUserData, RapierContext, CollisionEvent, ContactForceEvent, MassModifiedEvent,
+    Velocity,  RapierConfiguration, TimestepMode}, plugin::SimulationToRenderTime, render::RapierDebugRenderPlugin
 };
 use crate::{
-  faketimer::{FakeTimePlugin, FakeTimeStepSize},
-  util::{consts::REWARD_REACH_CHECKPOINT, range::Range3D, track::*, vel_to_f32}
+  faketimer::{FakeTimePlugin, FakeTimeStepSize, reset_fake_time},
+  util::{types::{RewardComponent, TargetType}, range::Range3D, track::*, vel_to_f32}
 };
 use bevy_flycam::*;
 
@@ -46,10 +46,21 @@ pub struct LineRiderSim {
   pub with_ui: bool,
   pub origin: Option<Vec3>,
   pub build_range: Range3D<f32>,
+  /** Obstacles of the current episode, kept here so they survive resets of the headless app */
+  pub obstacles: Vec<Range3D<f32>>,
   pub simulation_range: Range3D<f32>
 }
 
 
+/** Removes every asset of the storage without emitting removal events, like the empty storage of a new app */
+fn clear_asset_storage<A: Asset>(world: &mut World) {
+  let mut assets: Mut<Assets<A>> = world.resource_mut();
+  let ids: Vec<AssetId<A>> = assets.ids().collect();
+  for id in ids {
+    assets.remove_untracked(id);
+  }
+}
+
 pub fn make_app_singlethreaded(app: &mut App) {
   let schedule_setter = |schedule: &mut Schedule| {
     schedule.set_executor_kind(bevy::ecs::schedule::ExecutorKind::SingleThreaded);
@@ -74,10 +85,12 @@ impl LineRiderSim  {
     let mut app = App::new();
     app.insert_resource(config);
     app.insert_resource(TrackToAdd(Vec::new(), false, Vec3::ZERO));
+    app.insert_resource(Obstacles(Vec::new()));
     app.insert_resource(UseDebugCamera(false));
     app.insert_resource(ShowAABB(false));
     app.insert_resource(GoalReached(false));
     app.insert_resource(CheckpointReached(false));
+    app.insert_resource(CheckpointsReached(0));
     app.insert_resource(RiderTouchingTrackTimer(false, 0, 0));
     app.insert_resource(CurrentlyActiveBooster(Vec3::ZERO));
     app.insert_resource(DriverEntityRef(Entity::from_raw(0)));
@@ -100,17 +113,13 @@ impl LineRiderSim  {
       app.add_plugins(ScheduleRunnerPlugin::run_once());
       app.add_plugins(TransformPlugin);
       app.add_plugins(AssetPlugin::default());
-      app.add_plugins(WindowPlugin::default());
-      app.add_plugins(ScenePlugin);
-      app.add_plugins(RenderPlugin {
-        render_creation: WgpuSettings {
-            backends: None,
-            ..default()
-        }
-        .into(),
-      });
       app.add_plugins(ImagePlugin::default());
-      app.add_plugins(PbrPlugin::default());
+      app.add_plugins(ScenePlugin); // needed by the async scene colliders of rapier
+      // The physics only needs the mesh and material storages, render and pbr plugins are not required
+      app.init_asset::<Mesh>();
+      app.init_asset::<StandardMaterial>();
+      app.insert_resource(RerunSimulationSetup(false));
+      app.add_systems(StateTransition, rerun_simulation_setup.after(apply_state_transition::<GameState>));
       make_app_singlethreaded(&mut app); // Should improve performance for simulation
     }
     app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default());
@@ -134,6 +143,7 @@ impl LineRiderSim  {
     app.add_systems(Update, check_checkpoint_reached.run_if(in_state(GameState::InSimulation)));
     app.add_systems(Update, check_goal_no_rapier.run_if(in_state(GameState::InSimulation)));
     app.add_systems(Update, check_checkpoint_reached_no_rapier.run_if(in_state(GameState::InSimulation)));  
+    app.add_systems(Update, check_next_checkpoint_reached.run_if(in_state(GameState::InSimulation)));
 
     app
   }
@@ -147,7 +157,8 @@ impl LineRiderSim  {
       config,
       driver_id: Entity::from_raw(0),
       with_ui,
-      origin: None
+      origin: None,
+      obstacles: Vec::new()
     };
     if !with_ui {
       sim.set_physics_delta(sim.config.physics_delta, sim.config.physics_substeps);
@@ -195,9 +206,44 @@ impl LineRiderSim  {
       let mut nextstate: Mut<NextState<GameState>> = self.app.world.resource_mut();
       nextstate.set(GameState::ChooseTraining);
     } else {
-      self.app = LineRiderSim::prepare_app(self.config.clone(), self.with_ui);
+      self.clear_headless_app();
     }
   }
+  /**
+   * Reuses the headless app instead of building a new one.
+   * Everything an episode touches is put back into the state of a freshly prepared app,
+   * so simulation results are identical to rebuilding the app.
+   */
+  fn clear_headless_app(&mut self) {
+    let world = &mut self.app.world;
+    // Before the first update the initial state transition still runs the setup on its own
+    let needs_setup_rerun = world.resource::<FrameCount>().0 > 0 || world.resource::<RerunSimulationSetup>().0;
+    world.clear_entities();
+    // The entities holding the handles are gone, so the meshes and materials of the last episode are never used again
+    clear_asset_storage::<Mesh>(world);
+    clear_asset_storage::<StandardMaterial>(world);
+    world.insert_resource(self.config.clone());
+    world.insert_resource(TrackToAdd(Vec::new(), false, Vec3::ZERO));
+    world.insert_resource(Obstacles(self.obstacles.clone()));
+    world.insert_resource(UseDebugCamera(false));
+    world.insert_resource(ShowAABB(false));
+    world.insert_resource(GoalReached(false));
+    world.insert_resource(CheckpointReached(false));
+    world.insert_resource(CheckpointsReached(0));
+    world.insert_resource(RiderTouchingTrackTimer(false, 0, 0));
+    world.insert_resource(CurrentlyActiveBooster(Vec3::ZERO));
+    world.insert_resource(DriverEntityRef(Entity::from_raw(0)));
+    world.insert_resource(UseImageTexture(false));
+    world.insert_resource(FrameCount::default());
+    reset_fake_time(world);
+    world.insert_resource(RapierConfiguration::default());
+    world.insert_resource(RapierContext::default());
+    world.insert_resource(SimulationToRenderTime::default());
+    world.insert_resource(Events::<CollisionEvent>::default());
+    world.insert_resource(Events::<ContactForceEvent>::default());
+    world.insert_resource(Events::<MassModifiedEvent>::default());
+    world.insert_resource(RerunSimulationSetup(needs_setup_rerun));
+  }
   pub fn simulation_step(&mut self) {
     self.app.update();
   }
@@ -207,6 +253,10 @@ impl LineRiderSim  {
     self.config.goal_position = make_goal_range(&goal_pos, &self.config);
     self.config.goal_pos = goal_pos;
   }
+  pub fn set_obstacles(&mut self, obstacles: Vec<Range3D<f32>>) {
+    self.app.world.insert_resource(Obstacles(obstacles.clone()));
+    self.obstacles = obstacles;
+  }
   pub fn get_driver_transform(&self) -> &Transform {
     let driver_entity: &DriverEntityRef = self.app.world.resource();
     let driver_ref = self.app.world.entity(driver_entity.0);
@@ -244,6 +294,10 @@ impl LineRiderSim  {
     let mut steps_taken = 0;
     let mut goal_reached = false;
     let mut checkpoint_reached = false;
+    let mut checkpoints_reached = 0;
+    let with_checkpoint_sequence = self.config.with_checkpoint_sequence();
+    let is_loop = self.config.target_type == TargetType::LoopTrack;
+    let mut lap_counter = LapCounter::default();
     let mut previous_rotation = 0.0;
     let mut overall_velocity = 0.0;
     let mut overall_rotation = 0.0;
@@ -255,6 +309,8 @@ impl LineRiderSim  {
     let mut steps_without_movement = 0;
     let mut last_movement_comparison_pos = Vec3::ZERO;
     let mut ended_because_of_no_movement = false;
+    let trajectory_stride = self.config.trajectory_stride;
+    let mut trajectory: Vec<TrajectorySample> = Vec::new();
     // TODO: values of overall vel and rotation are dependent on delta. hhigher fps = higher numbers, lower fps = lower numbers
     'SIM_END: for i in 0..max_steps {
       self.simulation_step();
@@ -265,6 +321,9 @@ impl LineRiderSim  {
         last_movement_comparison_pos = driver_pos;
       }
       steps_taken = i;
+      if trajectory_stride > 0 && i % trajectory_stride == 0 {
+        trajectory.push(self.make_trajectory_sample(i));
+      }
       overall_velocity += vel_to_f32(self.get_driver_velocity());
       
       let position = self.get_driver_transform();
@@ -279,13 +338,23 @@ impl LineRiderSim  {
       previous_height = position.translation.y;
       
       
-      if !checkpoint_reached {
+      if with_checkpoint_sequence {
+        let app_checkpoints_reached: &CheckpointsReached = self.app.world.resource();
+        checkpoints_reached = app_checkpoints_reached.0;
+        checkpoint_reached = checkpoints_reached >= self.config.checkpoint_ranges.len();
+      } else if !checkpoint_reached {
         let app_checkpoint_reached: &CheckpointReached = self.app.world.resource();
-        if app_checkpoint_reached.0 {checkpoint_reached = true;}
+        if app_checkpoint_reached.0 {
+          checkpoint_reached = true;
+          checkpoints_reached = 1;
+        }
       }
 
+      if is_loop {
+        lap_counter.update(&self.config, &position.translation, i);
+      }
       let app_reached_goal: &GoalReached = self.app.world.resource();
-      if app_reached_goal.0 {
+      if (is_loop && lap_counter.laps >= self.config.loop_laps) || (!is_loop && app_reached_goal.0) {
         goal_reached = true;
         closest_to_goal = 0.0;
         starting_goal_distance = 0.0;
@@ -296,7 +365,10 @@ impl LineRiderSim  {
           break 'SIM_END;
         }
         let get_distance_to =
-        if !checkpoint_reached && self.config.reward_type.contains(&REWARD_REACH_CHECKPOINT) {
+        if !checkpoint_reached && with_checkpoint_sequence
+          && (self.config.uses_reward(RewardComponent::ReachCheckpoint) || self.config.uses_reward(RewardComponent::ReachEachCheckpoint)) {
+          self.config.checkpoint_positions[checkpoints_reached]
+        } else if !checkpoint_reached && self.config.uses_reward(RewardComponent::ReachCheckpoint) {
           self.config.checkpoint_pos
         } else {
           self.config.goal_pos
@@ -319,6 +391,9 @@ impl LineRiderSim  {
       }
     }
     let velocity_at_end = vel_to_f32(self.get_driver_velocity()); // velocity[0].abs() + velocity[1].abs();
+    if trajectory_stride > 0 && trajectory.last().is_some_and(|sample| sample.step != steps_taken) {
+      trajectory.push(self.make_trajectory_sample(steps_taken));
+    }
 
     let touch_timer: &RiderTouchingTrackTimer = self.app.world.resource();
     let time_rider_touched_track = touch_timer.2;
@@ -327,9 +402,26 @@ impl LineRiderSim  {
 
     LineRiderSimulationResult {
       steps_taken, goal_reached, velocity_at_end, overall_velocity, overall_rotation,
-      overall_height_gain, checkpoint_reached,
+      overall_height_gain, checkpoint_reached, checkpoints_reached,
       closest_to_goal: starting_goal_distance - closest_to_goal,
-      ended_because_of_no_movement, time_rider_touched_track, time_rider_airborne, total_time
+      ended_because_of_no_movement, time_rider_touched_track, time_rider_airborne, total_time,
+      laps: lap_counter.laps, lap_time: lap_counter.lap_time(self.config.physics_delta),
+      trajectory
+    }
+  }
+  fn make_trajectory_sample(&self, step: usize) -> TrajectorySample {
+    let transform = self.get_driver_transform();
+    let velocity = self.get_driver_velocity();
+    let touch_timer: &RiderTouchingTrackTimer = self.app.world.resource();
+    let active_booster: &CurrentlyActiveBooster = self.app.world.resource();
+    TrajectorySample {
+      step,
+      position: transform.translation.to_array(),
+      rotation: transform.rotation.to_array(),
+      linear_velocity: velocity.linvel.to_array(),
+      angular_velocity: velocity.angvel.to_array(),
+      touching_track: touch_timer.0,
+      active_booster: active_booster.0.to_array()
     }
   }
 
@@ -4,7 +4,7 @@ use bevy_rapier3d::prelude::{RigidBody, Collider, GravityScale, ComputedCollider
     Sleeping, Ccd, ColliderMassProperties, Velocity, Restitution, Friction, ActiveEvents, CollisionEvent, Sensor, RapierContext};
 use nalgebra::Point3;
 use super::GameState;
-use crate::util::{consts::*, track::*};
+use crate::util::{consts::*, range::Range3D, track::*};
 use super::LineRiderConfig;
 use std::{num, ops::Sub};
 #[derive(Component)]
@@ -40,6 +40,10 @@ pub struct InSimulation;
 #[derive(Resource)]
 pub struct TrackToAdd(pub Vec<TrackPoint>, pub bool, pub Vec3);
 
+/** Boxes the rider collides with, they are not part of the track */
+#[derive(Resource)]
+pub struct Obstacles(pub Vec<Range3D<f32>>);
+
 #[derive(Resource)]
 pub struct UseImageTexture(pub bool);
 
@@ -58,6 +62,10 @@ pub struct GoalReached(pub bool);
 #[derive(Resource)]
 pub struct CheckpointReached(pub bool);
 
+/** Amount of checkpoint_ranges the rider passed, only the next one in order counts */
+#[derive(Resource)]
+pub struct CheckpointsReached(pub usize);
+
 /**
  * 1 = Currently touching track
  * 2 = total time driving
@@ -69,6 +77,13 @@ pub struct RiderTouchingTrackTimer(pub bool, pub u64, pub u64);
 #[derive(Resource)]
 pub struct CurrentlyActiveBooster(pub Vec3);
 
+/**
+ * Set when a headless app was cleared for reuse, the setup of GameState::InSimulation then runs again
+ * at the same point of the frame as it would in a freshly built app
+ */
+#[derive(Resource)]
+pub struct RerunSimulationSetup(pub bool);
+
 #[derive(Resource)]
 pub struct Cubemap {
     is_loaded: bool,
@@ -181,6 +196,14 @@ pub fn check_checkpoint_reached_no_rapier(mut checkpoint_reached: ResMut<Checkpo
     checkpoint_reached.0 = true;
   }
 }
+
+pub fn check_next_checkpoint_reached(mut checkpoints_reached: ResMut<CheckpointsReached>, config_res: Res<LineRiderConfig>, rider_query: Query<&Transform, With<MovingMesh>>) {
+  if let Some(next_checkpoint) = config_res.checkpoint_ranges.get(checkpoints_reached.0) {
+    if next_checkpoint.vec3_in_range(&rider_query.single().translation) {
+      checkpoints_reached.0 += 1;
+    }
+  }
+}
  
 pub fn check_goal_no_rapier(mut goal_reached: ResMut<GoalReached>, config_res: Res<LineRiderConfig>, rider_query: Query<&mut Transform, With<MovingMesh>>) {
   let rider_transform = rider_query.single();
@@ -224,10 +247,19 @@ pub fn setup_checkpoint_mesh(config_res: Res<LineRiderConfig>, mut commands: Com
   entity.insert(InSimulation);
   entity.insert(PbrBundle {
     mesh: mesh_handle.into(),
-    material: material_handle,
+    material: material_handle.clone(),
     transform: Transform::from_translation(Vec3::ZERO),
     ..default()
   });
+  // the sequence is checked through check_next_checkpoint_reached, its meshes are only shown
+  for checkpoint_range in &config.checkpoint_ranges {
+    commands.spawn(PbrBundle {
+      mesh: meshes.add(Mesh::from(checkpoint_range.to_box())),
+      material: material_handle.clone(),
+      transform: Transform::from_translation(Vec3::ZERO),
+      ..default()
+    }).insert(InSimulation);
+  }
 }
 
 pub fn setup_rider_exclusive(world: &mut World) {
@@ -345,7 +377,7 @@ pub fn line_plane_intersection(line_direction_v: Vec3, line_point_p: Vec3, plane
 pub fn setup_track(mut commands: Commands, track_to_add: Res<TrackToAdd>, use_image_texture: Res<UseImageTexture>,
 config: Res<LineRiderConfig>, mut meshes: ResMut<Assets<Mesh>>,
 mut materials: ResMut<Assets<StandardMaterial>>, asset_server: Res<AssetServer>,
-mut driver_query: Query<&mut Velocity, With<MovingMesh>>, show_aabb: Res<ShowAABB>) {
+mut driver_query: Query<&mut Velocity, With<MovingMesh>>, show_aabb: Res<ShowAABB>, obstacles: Res<Obstacles>) {
   let points = &track_to_add.0;
   let material_handle = if use_image_texture.0 {
     let texture_handle = asset_server.load("textures/Grass003_4K_Color.jpg");
@@ -375,203 +407,73 @@ mut driver_query: Query<&mut Velocity, With<MovingMesh>>, show_aabb: Res<ShowAAB
     material_handle
   };
   
-  if config.use_cylinder_track {
-    if points.len() > 1 {
-      let prev_point = points[0].0;
-      let current_point = points[1].0;
-      let diff1 = current_point - prev_point;
-      // Initial Force for movement
-      let mut driver_velocity = driver_query.get_single_mut().unwrap();
-      driver_velocity.linvel = diff1 * config.starting_force_multiplier;
-      
-    }
-    let new_points = if config.smooth_free_points {
-      catmull_rom(points, points.len() * config.bezier_resolution)
-    } else {points.to_vec()};
-    let np: Vec<Vec3> = new_points.iter().map(|a| {a.0.clone()}).collect();
-    let num_segments = 16;
-    let radius = 10.1;
-    let mut end_vertices: Option<Vec<Vec3>> = None;
-      // Iterate over each point in the path
-      // let current_point = new_points[i].0;
-      // let next_point = if i < new_points.len() - 1 {
-      //   new_points[i + 1].0
-      // } else {
-      //   new_points[i].0
-      // };
-      // let (vertices_1, indices, prev_end) = generate_pipe_vertices(
-      //   current_point, next_point, radius, num_segments, None
-      // );
-      let (vertices_1, indices) = devin(
-        &np, radius, num_segments
-      );
-      // end_vertices = Some(prev_end);
-      let vertices_to_use = vertices_1;
-      let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
-      let collected: Vec<[f32; 3]> = vertices_to_use.iter().map(|p| [p.x, p.y, p.z]).collect();
-      let normals: Vec<[f32; 3]> = vertices_to_use.iter().map(|_p| [1.0, 1.0, 1.0]).collect();
-      let uvs: Vec<[f32; 2]> = vertices_to_use.iter().map(|_p| [0.0, 0.0]).collect();
-      mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, collected.clone());
-      mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
-      mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
-      println!("INDICES ARE {:?} vertices are {:?}", indices, vertices_to_use);
-      mesh.set_indices(Some(Indices::U32(indices.to_vec())));
-      // let mut collider_indices: Vec<[u32; 3]> = Vec::with_capacity(indices_to_use.len()/2);
-      // let mut i = 0;
-      // while i < indices.len() {
-      //   collider_indices.push([indices[i], indices[i+1], indices[i+2]]);
-      //   i += 3
-      // }
+  if points.len() > 1 {
+    let diff1 = points[1].0 - points[0].0;
+    // Initial Force for movement
+    let mut driver_velocity = driver_query.get_single_mut().unwrap();
+    driver_velocity.linvel = diff1 * config.starting_force_multiplier;
+  }
+  for piece in make_track_pieces(points, track_to_add.1, &config) {
+    if config.use_cylinder_track {
       let color_to_use = COLOR_TRACK.with_a(0.5);
       let material_handle_purple = materials.add(color_to_use.into());
       let mut entity = commands.spawn_empty();
       entity.insert(RigidBody::Fixed)
-      .insert(Collider::from_bevy_mesh(&mesh, &ComputedColliderShape::TriMesh).expect("collidermakeable"))
-      // .insert(Sleeping::disabled())
+      .insert(Collider::from_bevy_mesh(&piece.mesh, &ComputedColliderShape::TriMesh).expect("collidermakeable"))
       .insert(InSimulation);
-  
-      let mesh_handle = meshes.add(mesh.into());
+      let mesh_handle = meshes.add(piece.mesh);
       entity.insert(PbrBundle {
-        mesh: mesh_handle.into(),
-        material: material_handle_purple.clone(),
+        mesh: mesh_handle,
+        material: material_handle_purple,
         transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
         ..default()
       });
-
-  } else {
-    // let material_handle = asset_server.load("herringbone_parquet_1k.gltf#Material0");
-    if track_to_add.1 {
-      let mut i = 1;
-      if points.len() > 1 {
-        let prev_point = points[0].0;
-        let current_point = points[1].0;
-        let diff1 = current_point - prev_point;
-        // Initial Force for movement
-        if i == 1 {
-          let mut driver_velocity = driver_query.get_single_mut().unwrap();
-          driver_velocity.linvel = diff1 * config.starting_force_multiplier;
-        }
-      }
-      let new_points = if config.smooth_free_points {
-        catmull_rom(points, points.len() * config.bezier_resolution)
-      } else {points.to_vec()};
-      let mut prev_left = Vec3::ZERO;
-      let mut prev_right = Vec3::ZERO;
-      let mut prev_left_wall = Vec3::ZERO;
-      let mut prev_right_wall = Vec3::ZERO;
-      while i < new_points.len() {
-        if new_points[i].1 != TP_EMPTY {
-          let (all_points, diff1) = get_free_mesh_points(new_points[i-1].0, new_points[i].0, &[prev_left, prev_right, prev_left_wall, prev_right_wall], &config);
-          prev_left = all_points[1][1];
-          prev_left_wall = all_points[3][1];
-          prev_right = all_points[2][1];
-          prev_right_wall = all_points[4][1];
-          let (mesh, mesh_points) = make_track_mesh(&all_points, 1);
-
-          if show_aabb.0 {
-            let mesh_aabb = mesh.compute_aabb().unwrap();
-            commands.spawn_empty().insert(RigidBody::Fixed)
-            .insert(Collider::cuboid( mesh_aabb.half_extents.x,  mesh_aabb.half_extents.y,  mesh_aabb.half_extents.z))
-            .insert(Sleeping::disabled())
-            .insert(TransformBundle::from_transform(Transform::from_translation(Vec3::new(mesh_aabb.center.x, mesh_aabb.center.y, mesh_aabb.center.z))))
-            .insert(InSimulation);
-          }
-
-          let mesh_handle = meshes.add(mesh.into());
-          let material_to_use = match points[i].1 {
-            TP_ACCELERATE => {material_handle_boost.clone()},
-            TP_DECELERATE => {material_handle_decelerate.clone()},
-            _ => {material_handle.clone()}
-          };
-          let mut entity = commands.spawn_empty();
-          entity.insert(RigidBody::Fixed)
-          .insert(Collider::trimesh(mesh_points, MESH_INDICES_COLLIDER.to_vec()))
-          .insert(Sleeping::disabled())
-          .insert(InSimulation)
-          .insert(PbrBundle {
-            mesh: mesh_handle.into(),
-            material: material_to_use,
-            transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
-            ..default()
-          });
-          if new_points[i].1 >= TP_ACCELERATE {
-            let sign_changer = if new_points[i].1 == TP_DECELERATE {-1.0} else {1.0};
-            entity.insert(Booster(diff1 * sign_changer * config.booster_strength, false));
-          }
-        }
-        i += 1;
-      }
     } else {
-      let mut prev_direction = DIRECTION_FORWARD;
-      let mut i = 1;
-      while i < points.len() {
-        let prev_real_point = points[i-1].0;
-        let mut current_real_point = points[i].0;
-        let diff1 = current_real_point - prev_real_point;
-        // Initial Force for movement
-        if i == 1 {
-          let mut driver_velocity = driver_query.get_single_mut().unwrap();
-          driver_velocity.linvel = diff1 * config.starting_force_multiplier;
-        }
-        let current_direction = get_direction(prev_direction, prev_real_point, current_real_point);
-        if points[i].1 != TP_EMPTY {
-          let mut u = i + 1;
-          if diff1.y != 0.0 { // workaround to prevent left right combination to be seen as one big left curve
-            'POINT_CONNECTOR: while u < points.len() {
-              let next_point = points[u].0;
-              let diff2 = next_point - current_real_point;
-              if diff1 == diff2 {
-                current_real_point = next_point;
-                i = u;
-              } else {
-                break 'POINT_CONNECTOR;
-              }
-              u += 1;
-            }
-          }
-          // diff1 = current_real_point - prev_real_point;
-          let current_action = get_action(prev_direction, prev_real_point, current_real_point);
-          let all_points = get_all_points(prev_real_point, current_real_point, prev_direction, current_direction, current_action, &config);
-          for u in 1..all_points[0].len() {
-            let (mesh, mesh_points) = make_track_mesh(&all_points, u);
-
-            if show_aabb.0 {
-              let mesh_aabb = mesh.compute_aabb().unwrap();
-              commands.spawn_empty().insert(RigidBody::Fixed)
-              .insert(Collider::cuboid( mesh_aabb.half_extents.x,  mesh_aabb.half_extents.y,  mesh_aabb.half_extents.z))
-              .insert(Sleeping::disabled())
-              .insert(TransformBundle::from_transform(Transform::from_translation(Vec3::new(mesh_aabb.center.x, mesh_aabb.center.y, mesh_aabb.center.z))))
-              .insert(InSimulation);
-            }
-
-            let mesh_handle = meshes.add(mesh.into());
-            // let material_handle = materials.add(Color::PURPLE.into());
-            let material_to_use = match points[i].1 {
-              TP_ACCELERATE => {material_handle_boost.clone()},
-              TP_DECELERATE => {material_handle_decelerate.clone()},
-              _ => {material_handle.clone()}
-            };
-            let mut entity = commands.spawn_empty();
-            entity.insert(RigidBody::Fixed)
-            .insert(Collider::trimesh(mesh_points, MESH_INDICES_COLLIDER.to_vec()))
-            .insert(Sleeping::disabled())
-            .insert(PbrBundle {
-              mesh: mesh_handle.into(),
-              material: material_to_use,
-              transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
-              ..default()
-            }).insert(InSimulation);
-            if points[i].1 >= TP_ACCELERATE {
-              let sign_changer = if points[i].1 == TP_DECELERATE {-1.0} else {1.0};
-              entity.insert(Booster((diff1*1.1) * sign_changer * config.booster_strength, false));
-            }
-          }
-        }
-        prev_direction = current_direction;
-        i += 1;
+      if show_aabb.0 {
+        let mesh_aabb = piece.mesh.compute_aabb().unwrap();
+        commands.spawn_empty().insert(RigidBody::Fixed)
+        .insert(Collider::cuboid( mesh_aabb.half_extents.x,  mesh_aabb.half_extents.y,  mesh_aabb.half_extents.z))
+        .insert(Sleeping::disabled())
+        .insert(TransformBundle::from_transform(Transform::from_translation(Vec3::new(mesh_aabb.center.x, mesh_aabb.center.y, mesh_aabb.center.z))))
+        .insert(InSimulation);
+      }
+      let mesh_handle = meshes.add(piece.mesh);
+      let material_to_use = match piece.track_type {
+        TP_ACCELERATE => {material_handle_boost.clone()},
+        TP_DECELERATE => {material_handle_decelerate.clone()},
+        _ => {material_handle.clone()}
+      };
+      let mut entity = commands.spawn_empty();
+      entity.insert(RigidBody::Fixed)
+      .insert(Collider::trimesh(piece.mesh_points, MESH_INDICES_COLLIDER.to_vec()))
+      .insert(Sleeping::disabled())
+      .insert(InSimulation)
+      .insert(PbrBundle {
+        mesh: mesh_handle,
+        material: material_to_use,
+        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
+        ..default()
+      });
+      if let Some(booster) = piece.booster {
+        entity.insert(Booster(booster, false));
       }
     }
   }
+  if !obstacles.0.is_empty() {
+    let material_handle_obstacle = materials.add(COLOR_OBSTACLE.into());
+    for obstacle in &obstacles.0 {
+      let half_extents = obstacle.half_extents();
+      commands.spawn_empty().insert(RigidBody::Fixed)
+      .insert(Collider::cuboid(half_extents.x, half_extents.y, half_extents.z))
+      .insert(InSimulation)
+      .insert(PbrBundle {
+        mesh: meshes.add(Mesh::from(shape::Box::new(half_extents.x * 2.0, half_extents.y * 2.0, half_extents.z * 2.0))),
+        material: material_handle_obstacle.clone(),
+        transform: Transform::from_translation(obstacle.center()),
+        ..default()
+      });
+    }
+  }
 }
 
 pub fn setup_goal_position_exclusive(world: &mut World) {
@@ -682,6 +584,16 @@ pub fn switch_back_to_simuation2(mut nextstate: ResMut<NextState<GameState>>) {
   nextstate.set(GameState::InSimulation);
 }
 
+pub fn rerun_simulation_setup(world: &mut World) {
+  let rerun = {
+    let mut rerun_setup: Mut<RerunSimulationSetup> = world.resource_mut();
+    std::mem::replace(&mut rerun_setup.0, false)
+  };
+  if rerun {
+    world.run_schedule(OnEnter(GameState::InSimulation));
+  }
+}
+
 /// Despawn all entities with a given component type
 pub fn despawn_with<T: Component>(mut commands: Commands, q: Query<Entity, With<T>>) {
   for e in q.iter() {
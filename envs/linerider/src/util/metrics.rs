@@ -0,0 +1,221 @@
+use bevy::prelude::Vec3;
+use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+use std::f32::consts::PI;
+use crate::simulator::{LineRiderConfig, TrackPoint, TrajectorySample};
+use super::{consts::*, range::Range3D, radians_to_degree};
+
+/** Slopes from -90 to 90 degrees are counted in bins of 30 degrees, steepest descent first */
+pub const SLOPE_HISTOGRAM_BINS: usize = 6;
+/** Joints that bend the track by less than this many degrees do not count as a change of direction */
+pub const DIRECTION_CHANGE_MIN_DEGREES: f32 = 10.0;
+/** Acceleration of the rapier default gravity, 1 g */
+pub const GRAVITY: f32 = 9.81;
+
+/**
+ * Quality measures of a track for the evaluation of generated tracks.
+ * Geometry uses every point, length and coverage only the pieces that are not empty.
+ * Curvature is the turning angle at a joint divided by the mean length of the two pieces,
+ * torsion the angle between the planes of two successive joints divided by the length of the piece in between,
+ * so that a zigzag within one plane has no torsion.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrackMetrics {
+  pub piece_count: usize,
+  pub total_length: f32,
+  pub curvature_mean: f32,
+  pub curvature_max: f32,
+  pub curvature_std: f32,
+  pub torsion_mean: f32,
+  pub torsion_max: f32,
+  pub torsion_std: f32,
+  pub slope_histogram: [usize; SLOPE_HISTOGRAM_BINS],
+  pub direction_changes: usize,
+  pub empty_fraction: f32,
+  pub boost_fraction: f32,
+  pub brake_fraction: f32,
+  /** Fraction of the cells of the build range, one track_piece_length wide, that the track passes through */
+  pub coverage: f32,
+  /** Proper acceleration of the rider in g between two trajectory samples, None without a trajectory */
+  pub g_force_mean: Option<f32>,
+  pub g_force_max: Option<f32>
+}
+
+impl TrackMetrics {
+  pub fn to_map(&self) -> HashMap<String, String> {
+    let mut metrics: HashMap<String, String> = HashMap::new();
+    metrics.insert("metric_piece_count".to_owned(), self.piece_count.to_string());
+    metrics.insert("metric_total_length".to_owned(), self.total_length.to_string());
+    metrics.insert("metric_curvature_mean".to_owned(), self.curvature_mean.to_string());
+    metrics.insert("metric_curvature_max".to_owned(), self.curvature_max.to_string());
+    metrics.insert("metric_curvature_std".to_owned(), self.curvature_std.to_string());
+    metrics.insert("metric_torsion_mean".to_owned(), self.torsion_mean.to_string());
+    metrics.insert("metric_torsion_max".to_owned(), self.torsion_max.to_string());
+    metrics.insert("metric_torsion_std".to_owned(), self.torsion_std.to_string());
+    metrics.insert("metric_slope_histogram".to_owned(), serde_json::to_string(&self.slope_histogram).expect("slope histogram is serializable"));
+    metrics.insert("metric_direction_changes".to_owned(), self.direction_changes.to_string());
+    metrics.insert("metric_empty_fraction".to_owned(), self.empty_fraction.to_string());
+    metrics.insert("metric_boost_fraction".to_owned(), self.boost_fraction.to_string());
+    metrics.insert("metric_brake_fraction".to_owned(), self.brake_fraction.to_string());
+    metrics.insert("metric_coverage".to_owned(), self.coverage.to_string());
+    if let (Some(g_force_mean), Some(g_force_max)) = (self.g_force_mean, self.g_force_max) {
+      metrics.insert("metric_g_force_mean".to_owned(), g_force_mean.to_string());
+      metrics.insert("metric_g_force_max".to_owned(), g_force_max.to_string());
+    }
+    metrics
+  }
+}
+
+/** Mean, maximum and standard deviation, all 0 for no values */
+fn mean_max_std(values: &[f32]) -> (f32, f32, f32) {
+  if values.is_empty() {
+    return (0.0, 0.0, 0.0);
+  }
+  let mean = values.iter().sum::<f32>() / values.len() as f32;
+  let max = values.iter().cloned().fold(0.0, f32::max);
+  let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f32>() / values.len() as f32;
+  (mean, max, variance.sqrt())
+}
+
+fn cell_of(point: &Vec3, build_range: &Range3D<f32>, cell_size: f32) -> (i32, i32, i32) {
+  (
+    ((point.x - build_range.x_min) / cell_size).floor() as i32,
+    ((point.y - build_range.y_min) / cell_size).floor() as i32,
+    ((point.z - build_range.z_min) / cell_size).floor() as i32
+  )
+}
+
+/**
+ * Computes the TrackMetrics of the points of a track within its build range.
+ * The trajectory is the one recorded by simulate_till_end, the g-forces are left out if it is empty.
+ */
+pub fn compute_track_metrics(lines: &[TrackPoint], trajectory: &[TrajectorySample], build_range: &Range3D<f32>, config: &LineRiderConfig) -> TrackMetrics {
+  let mut metrics = TrackMetrics::default();
+  let pieces: Vec<(Vec3, u8)> = lines.windows(2).map(|pair| (pair[1].0 - pair[0].0, pair[1].1)).collect();
+  metrics.piece_count = pieces.len();
+
+  let mut curvatures: Vec<f32> = Vec::new();
+  let mut binormals: Vec<Option<Vec3>> = Vec::new();
+  for pair in pieces.windows(2) {
+    let (before, after) = (pair[0].0, pair[1].0);
+    let angle = before.angle_between(after);
+    if !angle.is_finite() {
+      binormals.push(None);
+      continue;
+    }
+    curvatures.push(angle / ((before.length() + after.length()) / 2.0));
+    if radians_to_degree(angle) >= DIRECTION_CHANGE_MIN_DEGREES {
+      metrics.direction_changes += 1;
+    }
+    let binormal = before.cross(after);
+    binormals.push(if binormal.length_squared() > f32::EPSILON {Some(binormal)} else {None});
+  }
+  (metrics.curvature_mean, metrics.curvature_max, metrics.curvature_std) = mean_max_std(&curvatures);
+
+  // binormal i belongs to the joint between piece i and i+1, so piece i+1 lies between two successive binormals
+  let torsions: Vec<f32> = binormals.windows(2).enumerate().filter_map(|(i, pair)| match (pair[0], pair[1]) {
+    (Some(first), Some(second)) => {
+      let angle = first.angle_between(second);
+      Some(angle.min(PI - angle) / pieces[i + 1].0.length())
+    },
+    _ => None
+  }).collect();
+  (metrics.torsion_mean, metrics.torsion_max, metrics.torsion_std) = mean_max_std(&torsions);
+
+  let cell_size = config.track_piece_length;
+  let mut visited_cells: HashSet<(i32, i32, i32)> = HashSet::new();
+  for (start, (piece, track_type)) in lines.iter().zip(pieces.iter()) {
+    let slope = radians_to_degree(piece.y.atan2(Vec3::new(piece.x, 0.0, piece.z).length()));
+    let bin = ((slope + 90.0) / (180.0 / SLOPE_HISTOGRAM_BINS as f32)) as usize;
+    metrics.slope_histogram[bin.min(SLOPE_HISTOGRAM_BINS - 1)] += 1;
+    match *track_type {
+      TP_EMPTY => {metrics.empty_fraction += 1.0; continue;},
+      TP_ACCELERATE => {metrics.boost_fraction += 1.0;},
+      TP_DECELERATE => {metrics.brake_fraction += 1.0;},
+      _ => {}
+    }
+    metrics.total_length += piece.length();
+    let samples = (piece.length() / (cell_size / 2.0)).ceil().max(1.0) as usize;
+    for sample in 0..=samples {
+      let point = start.0 + *piece * (sample as f32 / samples as f32);
+      if build_range.vec3_in_range(&point) {
+        visited_cells.insert(cell_of(&point, build_range, cell_size));
+      }
+    }
+  }
+  if !pieces.is_empty() {
+    metrics.empty_fraction /= pieces.len() as f32;
+    metrics.boost_fraction /= pieces.len() as f32;
+    metrics.brake_fraction /= pieces.len() as f32;
+  }
+  let extents = build_range.half_extents() * 2.0 / cell_size;
+  let cell_count = extents.x.ceil() * extents.y.ceil() * extents.z.ceil();
+  metrics.coverage = if cell_count > 0.0 {visited_cells.len() as f32 / cell_count} else {0.0};
+
+  let g_forces: Vec<f32> = trajectory.windows(2).filter_map(|pair| {
+    let seconds = (pair[1].step - pair[0].step) as f32 * config.physics_delta as f32 / 1000.0;
+    if seconds <= 0.0 {
+      return None;
+    }
+    let acceleration = (Vec3::from_array(pair[1].linear_velocity) - Vec3::from_array(pair[0].linear_velocity)) / seconds;
+    // resting on the track is 1 g, free fall 0 g
+    Some((acceleration + Vec3::new(0.0, GRAVITY, 0.0)).length() / GRAVITY)
+  }).collect();
+  if !g_forces.is_empty() {
+    let (g_force_mean, g_force_max, _) = mean_max_std(&g_forces);
+    metrics.g_force_mean = Some(g_force_mean);
+    metrics.g_force_max = Some(g_force_max);
+  }
+  metrics
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::f32::consts::FRAC_PI_2;
+
+  fn approx(value: f32, expected: f32) -> bool {
+    (value - expected).abs() < 1e-5
+  }
+
+  #[test]
+  fn metrics_of_a_staircase() {
+    let config = LineRiderConfig::default();
+    let build_range = Range3D::new(-2.0, 2.0, -2.0, 2.0, -2.0, 2.0);
+    // forward, down, forward as boost, right turn and an empty piece
+    let lines: Vec<TrackPoint> = vec![
+      (Vec3::new(-1.5, 0.5, 0.5), TP_NORMAL),
+      (Vec3::new(-0.5, 0.5, 0.5), TP_NORMAL),
+      (Vec3::new(-0.5, -0.5, 0.5), TP_NORMAL),
+      (Vec3::new(0.5, -0.5, 0.5), TP_ACCELERATE),
+      (Vec3::new(0.5, -0.5, 1.5), TP_NORMAL),
+      (Vec3::new(0.5, -0.5, 2.5), TP_EMPTY),
+    ];
+    let metrics = compute_track_metrics(&lines, &[], &build_range, &config);
+    assert_eq!(metrics.piece_count, 5);
+    assert!(approx(metrics.total_length, 4.0));
+    assert_eq!(metrics.direction_changes, 3);
+    assert!(approx(metrics.curvature_max, FRAC_PI_2));
+    assert!(approx(metrics.curvature_mean, 3.0 * FRAC_PI_2 / 4.0));
+    // the plane turns from x-y to x-z between the down and the right turn
+    assert!(approx(metrics.torsion_max, FRAC_PI_2));
+    assert_eq!(metrics.slope_histogram, [1, 0, 0, 4, 0, 0]);
+    assert!(approx(metrics.empty_fraction, 0.2) && approx(metrics.boost_fraction, 0.2));
+    assert_eq!(metrics.brake_fraction, 0.0);
+    assert!(metrics.coverage > 0.0 && metrics.coverage < 1.0);
+    assert_eq!(metrics.g_force_mean, None);
+    assert!(!metrics.to_map().contains_key("metric_g_force_max"));
+  }
+
+  #[test]
+  fn g_force_of_resting_and_falling_rider() {
+    let config = LineRiderConfig {physics_delta: 100, ..Default::default()};
+    let sample = |step: usize, linear_velocity: [f32; 3]| TrajectorySample {step, linear_velocity, ..Default::default()};
+    let resting = [sample(0, [1.0, 0.0, 0.0]), sample(2, [1.0, 0.0, 0.0])];
+    let metrics = compute_track_metrics(&[], &resting, &Range3D::default(), &config);
+    assert!(approx(metrics.g_force_max.unwrap(), 1.0));
+    let falling = [sample(0, [0.0, 0.0, 0.0]), sample(10, [0.0, -GRAVITY, 0.0])];
+    let metrics = compute_track_metrics(&[], &falling, &Range3D::default(), &config);
+    assert!(approx(metrics.g_force_mean.unwrap(), 0.0));
+  }
+}
@@ -1,6 +1,8 @@
 pub mod range;
 pub mod consts;
+pub mod types;
 pub mod track;
+pub mod metrics;
 #[cfg(feature = "libbuild")]
 pub mod pylib;
 
@@ -1,8 +1,12 @@
 use pyo3::prelude::*;
+use pyo3::exceptions::PyValueError;
 use std::collections::HashMap;
-use crate::simulator::LineRiderSim;
+use std::sync::Arc;
+use bevy::prelude::Vec3;
+use crate::simulator::{LineRiderSim, LineRiderConfig, TrajectorySample, format_config_errors};
+use crate::util::{metrics::compute_track_metrics, track::make_build_range};
 use crate::env::LineRider3DEnv;
-use rusty_gym::{ReplayableGymEnvironment, env::python::RustToPyGym};
+use rusty_gym::{ReplayableGymEnvironment, GymRecorder, VecEnvironmentMaker, env::python::{RustToPyGym, RustToPyVecGym}};
 
 #[pyfunction]
 pub fn make_env(config_opt: Option<HashMap<String, String>>, record_with_id: Option<String>) -> RustToPyGym {
@@ -18,8 +22,47 @@ pub fn make_env(config_opt: Option<HashMap<String, String>>, record_with_id: Opt
   })
 }
 
+#[pyfunction]
+pub fn make_vec_env(num_envs: usize, config_opt: Option<HashMap<String, String>>, record_with_id: Option<String>, num_threads: Option<usize>) -> PyResult<RustToPyVecGym> {
+  let config = config_opt.unwrap_or(HashMap::new());
+  let maker: VecEnvironmentMaker = Arc::new(move |env_index| {
+    let sim: LineRiderSim = LineRiderSim::new(false);
+    let mut env = LineRider3DEnv::new(sim, None);
+    env.load_config(&config);
+    let boxed_env: Box<dyn ReplayableGymEnvironment> = match &record_with_id {
+      Some(record_id) => {Box::new(GymRecorder::new(Box::new(env), Some(format!("{}_{}", record_id, env_index))))},
+      None => {Box::new(env)}
+    };
+    boxed_env
+  });
+
+  Python::with_gil(|py| {
+    RustToPyVecGym::new(py, num_envs, num_threads, maker)
+  })
+}
+
+/**
+ * TrackMetrics of a track given as (x, y, z, track type) points, keyed like in the enriched episodes.
+ * The trajectory is the JSON stored as "trajectory" by the simulation result.
+ */
+#[pyfunction]
+pub fn track_metrics(track: Vec<(f32, f32, f32, u8)>, config_opt: Option<HashMap<String, String>>, trajectory_json: Option<String>) -> PyResult<HashMap<String, String>> {
+  let mut config = LineRiderConfig::default();
+  config.apply_string_map(&config_opt.unwrap_or_default()).map_err(|errors| PyValueError::new_err(format_config_errors(&errors)))?;
+  let trajectory: Vec<TrajectorySample> = match trajectory_json {
+    Some(json) => serde_json::from_str(&json).map_err(|error| PyValueError::new_err(format!("could not parse trajectory: {}", error)))?,
+    None => Vec::new()
+  };
+  let lines: Vec<(Vec3, u8)> = track.iter().map(|(x, y, z, track_type)| (Vec3::new(*x, *y, *z), *track_type)).collect();
+  let origin = if config.use_new_goalgen {lines.first().map(|point| point.0)} else {None};
+  let build_range = make_build_range(&config, 0.5, &origin);
+  Ok(compute_track_metrics(&lines, &trajectory, &build_range, &config).to_map())
+}
+
 #[pymodule]
 fn linerider(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(make_env, m)?)?;
+    m.add_function(wrap_pyfunction!(make_vec_env, m)?)?;
+    m.add_function(wrap_pyfunction!(track_metrics, m)?)?;
     Ok(())
 }
@@ -28,7 +28,7 @@ impl<X: Scalar + PartialOrd> Range2D<X> {
   }
 }
 
-#[derive(Default, Serialize, Deserialize, Debug, Clone)]
+#[derive(Default, Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Range3D<X: Scalar + PartialOrd> {
   pub x_min: X,
   pub x_max: X,
@@ -61,6 +61,22 @@ impl Range3D<f32> {
   pub fn vec3_in_range(&self, pos: &Vec3) -> bool {
     self.is_in_range(pos.x, pos.y, pos.z)
   }
+  pub fn center(&self) -> Vec3 {
+    Vec3::new((self.x_min + self.x_max) / 2.0, (self.y_min + self.y_max) / 2.0, (self.z_min + self.z_max) / 2.0)
+  }
+  pub fn half_extents(&self) -> Vec3 {
+    Vec3::new((self.x_max - self.x_min) / 2.0, (self.y_max - self.y_min) / 2.0, (self.z_max - self.z_min) / 2.0)
+  }
+  /** Whether the ranges share some volume, touching faces do not count */
+  pub fn overlaps(&self, other: &Range3D<f32>) -> bool {
+    self.x_min < other.x_max && other.x_min < self.x_max
+      && self.y_min < other.y_max && other.y_min < self.y_max
+      && self.z_min < other.z_max && other.z_min < self.z_max
+  }
+  /** Same range grown by margin on every side */
+  pub fn expanded(&self, margin: f32) -> Range3D<f32> {
+    Range3D::new(self.x_min - margin, self.x_max + margin, self.y_min - margin, self.y_max + margin, self.z_min - margin, self.z_max + margin)
+  }
   pub fn to_box(&self) -> Box {
     Box {
       max_x: self.x_max,
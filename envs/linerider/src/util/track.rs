@@ -1,5 +1,5 @@
 use bevy::{prelude::{Vec3, Mesh, Quat}, render::{render_resource::PrimitiveTopology, mesh::Indices}};
-use bevy_rapier3d::{prelude::Collider, parry::query::contact};
+use bevy_rapier3d::{prelude::Collider, parry::{query::contact, shape::Cuboid}};
 use nalgebra::{IsometryMatrix3, Vector3, Isometry3};
 use crate::{util::consts::*, simulator::{LineRiderConfig, TrackPoint}};
 use nalgebra as na;
@@ -403,6 +403,101 @@ pub fn make_track_mesh(all_points: &[Vec<Vec3>], u: usize) -> (Mesh, Vec<Vec3>)
   (mesh, mesh_points)
 }
 
+/** Geometry of one piece of track as spawned by setup_track, independent of the Bevy world */
+pub struct TrackPiece {
+  pub mesh: Mesh,
+  /** Points the collider of the piece is built from */
+  pub mesh_points: Vec<Vec3>,
+  pub track_type: u8,
+  /** Force applied to the rider by boost and brake pieces */
+  pub booster: Option<Vec3>
+}
+
+fn make_cylinder_mesh(vertices: &[Vec3], indices: Vec<u32>) -> Mesh {
+  let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+  let collected: Vec<[f32; 3]> = vertices.iter().map(|p| [p.x, p.y, p.z]).collect();
+  let normals: Vec<[f32; 3]> = vertices.iter().map(|_p| [1.0, 1.0, 1.0]).collect();
+  let uvs: Vec<[f32; 2]> = vertices.iter().map(|_p| [0.0, 0.0]).collect();
+  mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, collected);
+  mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+  mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+  mesh.set_indices(Some(Indices::U32(indices)));
+  mesh
+}
+
+/**
+ * Turns the track points into the meshes that make up the track.
+ * free_points selects the free roaming track that is built between arbitrary points instead of the static directions.
+ */
+pub fn make_track_pieces(points: &[TrackPoint], free_points: bool, config: &LineRiderConfig) -> Vec<TrackPiece> {
+  let mut pieces = Vec::new();
+  if config.use_cylinder_track {
+    let new_points = if config.smooth_free_points {
+      catmull_rom(points, points.len() * config.bezier_resolution)
+    } else {points.to_vec()};
+    let np: Vec<Vec3> = new_points.iter().map(|a| a.0).collect();
+    let (vertices, indices) = devin(&np, 10.1, 16);
+    pieces.push(TrackPiece {mesh: make_cylinder_mesh(&vertices, indices), mesh_points: vertices, track_type: TP_NORMAL, booster: None});
+  } else if free_points {
+    let new_points = if config.smooth_free_points {
+      catmull_rom(points, points.len() * config.bezier_resolution)
+    } else {points.to_vec()};
+    let mut prev_points: PrevPoints = [Vec3::ZERO; 4];
+    for i in 1..new_points.len() {
+      if new_points[i].1 != TP_EMPTY {
+        let (all_points, diff1) = get_free_mesh_points(new_points[i-1].0, new_points[i].0, &prev_points, config);
+        prev_points = [all_points[1][1], all_points[2][1], all_points[3][1], all_points[4][1]];
+        let (mesh, mesh_points) = make_track_mesh(&all_points, 1);
+        let track_type = new_points[i].1;
+        let booster = if track_type >= TP_ACCELERATE {
+          let sign_changer = if track_type == TP_DECELERATE {-1.0} else {1.0};
+          Some(diff1 * sign_changer * config.booster_strength)
+        } else {None};
+        pieces.push(TrackPiece {mesh, mesh_points, track_type, booster});
+      }
+    }
+  } else {
+    let mut prev_direction = DIRECTION_FORWARD;
+    let mut i = 1;
+    while i < points.len() {
+      let prev_real_point = points[i-1].0;
+      let mut current_real_point = points[i].0;
+      let diff1 = current_real_point - prev_real_point;
+      let current_direction = get_direction(prev_direction, prev_real_point, current_real_point);
+      if points[i].1 != TP_EMPTY {
+        let mut u = i + 1;
+        if diff1.y != 0.0 { // workaround to prevent left right combination to be seen as one big left curve
+          'POINT_CONNECTOR: while u < points.len() {
+            let next_point = points[u].0;
+            let diff2 = next_point - current_real_point;
+            if diff1 == diff2 {
+              current_real_point = next_point;
+              i = u;
+            } else {
+              break 'POINT_CONNECTOR;
+            }
+            u += 1;
+          }
+        }
+        let current_action = get_action(prev_direction, prev_real_point, current_real_point);
+        let all_points = get_all_points(prev_real_point, current_real_point, prev_direction, current_direction, current_action, config);
+        let track_type = points[i].1;
+        for u in 1..all_points[0].len() {
+          let (mesh, mesh_points) = make_track_mesh(&all_points, u);
+          let booster = if track_type >= TP_ACCELERATE {
+            let sign_changer = if track_type == TP_DECELERATE {-1.0} else {1.0};
+            Some((diff1*1.1) * sign_changer * config.booster_strength)
+          } else {None};
+          pieces.push(TrackPiece {mesh, mesh_points, track_type, booster});
+        }
+      }
+      prev_direction = current_direction;
+      i += 1;
+    }
+  }
+  pieces
+}
+
 pub fn aabbs_intersect(aabb: &(Vec3, Vec3), prev_aabb: &(Vec3, Vec3)) -> bool {
   aabb.0.x < prev_aabb.1.x &&
   aabb.1.x > prev_aabb.0.x &&
@@ -428,6 +523,18 @@ pub fn check_collision_with_colliders(colliders: &[Collider], new_collider: &Col
   false  
 }
 
+/** Whether the new collider touches or reaches into one of the box obstacles */
+pub fn check_collision_with_obstacles(obstacles: &[Range3D<f32>], new_collider: &Collider) -> bool {
+  let p1 = Isometry3::translation(0.0, 0.0, 0.0);
+  obstacles.iter().any(|obstacle| {
+    let center = obstacle.center();
+    let half_extents = obstacle.half_extents();
+    let obstacle_shape = Cuboid::new(Vector3::new(half_extents.x, half_extents.y, half_extents.z));
+    let obstacle_position = Isometry3::translation(center.x, center.y, center.z);
+    contact(&obstacle_position, &obstacle_shape, &p1, &*new_collider.raw, 0.0).expect("cuboid contacts are supported").is_some()
+  })
+}
+
 pub fn check_collision(aabbs: &[(Vec3, Vec3)], aabb_tuple: (Vec3, Vec3)) -> bool {
   for prev_aabb_tuple in aabbs {
     if aabbs_intersect(prev_aabb_tuple, &aabb_tuple) {
@@ -0,0 +1,375 @@
+use serde::{de::{self, Visitor}, Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownVariant {
+  pub kind: &'static str,
+  pub value: String
+}
+
+impl fmt::Display for UnknownVariant {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "unknown {} '{}'", self.kind, self.value)
+  }
+}
+
+impl std::error::Error for UnknownVariant {}
+
+/**
+ * Declares a config enum that is stored as its numeric id (as in old .tlr files)
+ * but can also be read from its name, e.g. "free_points_relative" or "5".
+ */
+macro_rules! config_enum {
+  ($(#[$meta:meta])* $name:ident, $kind:literal, {$($variant:ident = $value:literal => $str:literal),+ $(,)?}) => {
+    $(#[$meta])*
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub enum $name {
+      $($variant = $value),+
+    }
+
+    impl $name {
+      pub const ALL: &'static [$name] = &[$($name::$variant),+];
+      pub fn name(&self) -> &'static str {
+        match self {
+          $($name::$variant => $str),+
+        }
+      }
+      pub fn from_name(name: &str) -> Option<$name> {
+        match name {
+          $($str => Some($name::$variant),)+
+          _ => None
+        }
+      }
+    }
+
+    impl TryFrom<u8> for $name {
+      type Error = UnknownVariant;
+      fn try_from(value: u8) -> Result<$name, UnknownVariant> {
+        match value {
+          $($value => Ok($name::$variant),)+
+          _ => Err(UnknownVariant {kind: $kind, value: value.to_string()})
+        }
+      }
+    }
+
+    impl From<$name> for u8 {
+      fn from(value: $name) -> u8 {value as u8}
+    }
+
+    impl FromStr for $name {
+      type Err = UnknownVariant;
+      fn from_str(value: &str) -> Result<$name, UnknownVariant> {
+        let trimmed = value.trim();
+        match trimmed.parse::<u8>() {
+          Ok(id) => $name::try_from(id),
+          Err(_) => $name::from_name(trimmed).ok_or(UnknownVariant {kind: $kind, value: trimmed.to_owned()})
+        }
+      }
+    }
+
+    impl fmt::Display for $name {
+      fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+      }
+    }
+
+    impl Serialize for $name {
+      fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(u8::from(*self))
+      }
+    }
+
+    impl<'de> Deserialize<'de> for $name {
+      fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<$name, D::Error> {
+        struct IdOrNameVisitor;
+        impl<'de> Visitor<'de> for IdOrNameVisitor {
+          type Value = $name;
+          fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "the id or name of a {}", $kind)
+          }
+          fn visit_u64<E: de::Error>(self, value: u64) -> Result<$name, E> {
+            u8::try_from(value).map_err(|_| E::custom(UnknownVariant {kind: $kind, value: value.to_string()}))
+              .and_then(|id| $name::try_from(id).map_err(E::custom))
+          }
+          fn visit_i64<E: de::Error>(self, value: i64) -> Result<$name, E> {
+            u64::try_from(value).map_err(|_| E::custom(UnknownVariant {kind: $kind, value: value.to_string()}))
+              .and_then(|id| self.visit_u64(id))
+          }
+          fn visit_str<E: de::Error>(self, value: &str) -> Result<$name, E> {
+            value.parse().map_err(E::custom)
+          }
+        }
+        deserializer.deserialize_any(IdOrNameVisitor)
+      }
+    }
+  };
+}
+
+config_enum!(
+  /** How the agent builds the track */
+  ActionType, "action type", {
+  Static = 0 => "static",
+  StaticWithEmpty = 1 => "static_with_empty",
+  StaticWithBoost = 2 => "static_with_boost",
+  FreePoints = 3 => "free_points",
+  FreePointsWithTp = 4 => "free_points_with_tp",
+  FreePointsRelative = 5 => "free_points_relative",
+  FreePointsWithTpRelative = 6 => "free_points_with_tp_relative",
+  Radial = 7 => "radial",
+  RadialWithTp = 8 => "radial_with_tp"
+});
+
+config_enum!(
+  /** What the agent sees of the track and the target */
+  ObservationType, "observation type", {
+  BuildPoints = 0 => "build_points",
+  View3D = 1 => "3d_view",
+  View3DOnehot = 2 => "3d_view_onehot",
+  GoalAndLastPoint = 3 => "goal_and_last_point",
+  SlidingWindow = 4 => "sliding_window",
+  BuildPointsWithObstacles = 5 => "build_points_with_obstacles",
+  Egocentric = 6 => "egocentric"
+});
+
+config_enum!(
+  /** Where start, goal and checkpoint of an episode are placed */
+  TargetType, "target type", {
+  StaticStartAndEnd = 0 => "static_start_and_end",
+  RandomStartAndEnd = 1 => "random_start_and_end",
+  StaticStartRandomEnd = 2 => "static_start_random_end",
+  RandomStartStaticEnd = 3 => "random_start_static_end",
+  AboveStart = 4 => "above_start",
+  SameHeightAsStart = 5 => "same_height_as_start",
+  LoopTrack = 6 => "loop_track", // the goal is the start, the track has to return to it for the rider to drive loop_laps laps
+  RandomWithCheckpointBelow = 7 => "random_with_checkpoint_below",
+  RandomWithCheckpointAbove = 8 => "random_with_checkpoint_above",
+  RandomWithCheckpoints = 9 => "random_with_checkpoints" // checkpoint_count checkpoints, passed in order before the goal counts
+});
+
+config_enum!(
+  /** Which checkpoints of TargetType::RandomWithCheckpoints the observation shows */
+  CheckpointObservation, "checkpoint observation", {
+  Next = 0 => "next",
+  Remaining = 1 => "remaining"
+});
+
+config_enum!(
+  /** Surface of a track piece, the ids are the TP_* constants */
+  TrackType, "track type", {
+  Empty = 0 => "empty",
+  Normal = 1 => "normal",
+  Accelerate = 2 => "boost",
+  Decelerate = 3 => "brake"
+});
+
+config_enum!(
+  /** One term of the reward, the configured components are summed up */
+  RewardComponent, "reward type", {
+  GoalReachedByBall = 0 => "goal_reached_by_ball",
+  GoalReachedByTrack = 1 => "goal_reached_by_track",
+  GoalReachedByBothOnly = 2 => "goal_reached_by_both_only",
+  GoingUp = 3 => "going_up",
+  LongestTrack = 4 => "longest_track",
+  ShortestTrack = 5 => "shortest_track",
+  SpeedTotal = 6 => "speed_total",
+  SpeedAtEnd = 7 => "speed_at_end",
+  LowSpeedAtEnd = 8 => "low_speed_at_end",
+  MostRotation = 9 => "most_rotation",
+  LeastRotation = 10 => "least_rotation",
+  TrackTouches = 11 => "track_touches",
+  AirTime = 12 => "air_time",
+  ValidActionChosen = 13 => "valid_action_chosen",
+  ScoldInvalidAction = 14 => "scold_invalid_action",
+  DistanceToGoalInSimulation = 15 => "distance_to_goal_in_simulation",
+  SimulateInbetween = 16 => "simulate_inbetween",
+  FastestGoalReach = 17 => "fastest_goal_reach",
+  EndBuildPhaseIfTrackReachesGoal = 18 => "end_build_phase_if_track_reaches_goal",
+  DistanceToGoalInSimulationIfTrackReachedGoal = 20 => "distance_to_goal_in_simulation_if_track_reached_goal",
+  DistanceOfTrackToGoalAtEnd = 21 => "distance_of_track_to_goal_at_end",
+  TrackCloserToGoalInStep = 22 => "track_closer_to_goal_in_step",
+  ScoldPrematureEnd = 23 => "scold_premature_end",
+  MimicStraightLineHeuristic = 24 => "mimic_straight_line_heuristic",
+  UsingBoosterTypeTrack = 25 => "using_booster_type_track",
+  ReachCheckpoint = 26 => "reach_checkpoint",
+  TrackReachCheckpoint = 27 => "track_reach_checkpoint",
+  ReachEachCheckpoint = 28 => "reach_each_checkpoint",
+  TrackReachEachCheckpoint = 29 => "track_reach_each_checkpoint",
+  MimicExpert = 30 => "mimic_expert" // like MimicStraightLineHeuristic, but heads for the next checkpoint before the goal
+});
+
+impl ActionType {
+  pub fn is_free_points(&self) -> bool {
+    match self {
+      ActionType::Static | ActionType::StaticWithEmpty | ActionType::StaticWithBoost
+        | ActionType::Radial | ActionType::RadialWithTp => false,
+      ActionType::FreePoints | ActionType::FreePointsWithTp
+        | ActionType::FreePointsRelative | ActionType::FreePointsWithTpRelative => true
+    }
+  }
+  pub fn is_relative(&self) -> bool {
+    matches!(self, ActionType::FreePointsRelative | ActionType::FreePointsWithTpRelative)
+  }
+}
+
+/**
+ * Weight, optional normalisation and optional clip of one reward component.
+ * The value of a term is clip(raw / normalize * weight).
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RewardTerm {
+  pub weight: f64,
+  pub normalize: Option<f64>,
+  pub clip: Option<[f64; 2]>
+}
+
+impl RewardTerm {
+  pub fn new(weight: f64) -> RewardTerm {
+    RewardTerm {weight, normalize: None, clip: None}
+  }
+  pub fn normalized(weight: f64, normalize: f64) -> RewardTerm {
+    RewardTerm {weight, normalize: Some(normalize), clip: None}
+  }
+  pub fn apply(&self, raw: f64) -> f64 {
+    let normalized = match self.normalize {
+      Some(normalize) => raw / normalize,
+      None => raw
+    };
+    let weighted = normalized * self.weight;
+    match self.clip {
+      Some([min, max]) => weighted.clamp(min, max),
+      None => weighted
+    }
+  }
+}
+
+/** User supplied override of a RewardTerm, unset fields keep the default of the component */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewardTermSpec {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub weight: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub normalize: Option<f64>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub clip: Option<[f64; 2]>
+}
+
+impl RewardComponent {
+  /**
+   * The weights that were hard-coded before rewards became configurable.
+   * EndBuildPhaseIfTrackReachesGoal and SimulateInbetween only switch behaviour, their term is unused.
+   */
+  pub fn default_term(&self) -> RewardTerm {
+    match self {
+      RewardComponent::GoalReachedByBall | RewardComponent::GoalReachedByBothOnly
+        | RewardComponent::ReachCheckpoint => RewardTerm::new(2.0),
+      RewardComponent::GoalReachedByTrack | RewardComponent::TrackReachCheckpoint => RewardTerm::new(0.5),
+      RewardComponent::ReachEachCheckpoint => RewardTerm::new(1.0),
+      RewardComponent::TrackReachEachCheckpoint => RewardTerm::new(0.25),
+      RewardComponent::GoingUp | RewardComponent::DistanceToGoalInSimulation
+        | RewardComponent::DistanceToGoalInSimulationIfTrackReachedGoal => RewardTerm::normalized(1.0, 1000.0),
+      RewardComponent::LongestTrack | RewardComponent::ShortestTrack
+        | RewardComponent::TrackTouches | RewardComponent::AirTime => RewardTerm::new(0.25),
+      RewardComponent::SpeedAtEnd => RewardTerm::normalized(1.0, 50.0),
+      RewardComponent::SpeedTotal | RewardComponent::LowSpeedAtEnd | RewardComponent::MostRotation
+        | RewardComponent::LeastRotation | RewardComponent::FastestGoalReach => RewardTerm::new(1.0),
+      RewardComponent::DistanceOfTrackToGoalAtEnd => RewardTerm::new(0.1),
+      RewardComponent::ScoldPrematureEnd => RewardTerm::new(-1.0),
+      RewardComponent::ValidActionChosen => RewardTerm::new(0.0001),
+      RewardComponent::ScoldInvalidAction => RewardTerm::new(-0.0001),
+      RewardComponent::TrackCloserToGoalInStep | RewardComponent::MimicStraightLineHeuristic
+        | RewardComponent::MimicExpert | RewardComponent::UsingBoosterTypeTrack => RewardTerm::new(0.01),
+      RewardComponent::EndBuildPhaseIfTrackReachesGoal | RewardComponent::SimulateInbetween => RewardTerm::new(1.0)
+    }
+  }
+  pub fn term_with(&self, spec: Option<&RewardTermSpec>) -> RewardTerm {
+    let default_term = self.default_term();
+    match spec {
+      Some(spec) => RewardTerm {
+        weight: spec.weight.unwrap_or(default_term.weight),
+        normalize: spec.normalize.or(default_term.normalize),
+        clip: spec.clip.or(default_term.clip)
+      },
+      None => default_term
+    }
+  }
+}
+
+impl TargetType {
+  pub fn has_checkpoint(&self) -> bool {
+    match self {
+      TargetType::RandomWithCheckpointBelow | TargetType::RandomWithCheckpointAbove
+        | TargetType::RandomWithCheckpoints => true,
+      TargetType::StaticStartAndEnd | TargetType::RandomStartAndEnd | TargetType::StaticStartRandomEnd
+        | TargetType::RandomStartStaticEnd | TargetType::AboveStart | TargetType::SameHeightAsStart
+        | TargetType::LoopTrack => false
+    }
+  }
+  /** Whether the checkpoints are the ordered list of checkpoint_ranges instead of the single checkpoint_range */
+  pub fn has_checkpoint_sequence(&self) -> bool {
+    matches!(self, TargetType::RandomWithCheckpoints)
+  }
+  /** Whether every episode starts at STATIC_START_POSITION */
+  pub fn has_static_start(&self) -> bool {
+    matches!(self, TargetType::StaticStartAndEnd | TargetType::StaticStartRandomEnd)
+  }
+  /** Whether every episode ends at STATIC_GOAL_POSITION */
+  pub fn has_static_goal(&self) -> bool {
+    matches!(self, TargetType::StaticStartAndEnd | TargetType::RandomStartStaticEnd)
+  }
+}
+
+impl ObservationType {
+  /** Whether the observation has a slot for the checkpoint of checkpoint target types */
+  pub fn shows_checkpoint(&self) -> bool {
+    match self {
+      ObservationType::BuildPoints | ObservationType::SlidingWindow | ObservationType::BuildPointsWithObstacles
+        | ObservationType::Egocentric => true,
+      ObservationType::View3D | ObservationType::View3DOnehot | ObservationType::GoalAndLastPoint => false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[test]
+  fn ids_and_names_round_trip() {
+    for reward in RewardComponent::ALL {
+      assert_eq!(RewardComponent::try_from(u8::from(*reward)), Ok(*reward));
+      assert_eq!(reward.name().parse::<RewardComponent>(), Ok(*reward));
+      assert_eq!(u8::from(*reward).to_string().parse::<RewardComponent>(), Ok(*reward));
+    }
+    for action in ActionType::ALL {
+      assert_eq!(action.to_string().parse::<ActionType>(), Ok(*action));
+    }
+    assert_eq!(ObservationType::from_name("3d_view"), Some(ObservationType::View3D));
+    assert_eq!(TargetType::try_from(8), Ok(TargetType::RandomWithCheckpointAbove));
+    assert_eq!("remaining".parse::<CheckpointObservation>(), Ok(CheckpointObservation::Remaining));
+    assert_eq!(RewardComponent::try_from(19), Err(UnknownVariant {kind: "reward type", value: "19".to_owned()}));
+    assert!("254".parse::<RewardComponent>().is_err());
+    assert!("free_point".parse::<ActionType>().is_err());
+  }
+  #[test]
+  fn serializes_as_id_and_reads_ids_or_names() {
+    let rewards = vec![RewardComponent::GoalReachedByBall, RewardComponent::AirTime];
+    assert_eq!(serde_json::to_string(&rewards).unwrap(), "[0,12]");
+    let parsed: Vec<RewardComponent> = serde_json::from_str("[0, \"air_time\"]").unwrap();
+    assert_eq!(parsed, rewards);
+    assert!(serde_json::from_str::<Vec<RewardComponent>>("[254, 0]").is_err());
+    assert!(serde_json::from_str::<ActionType>("-1").is_err());
+  }
+  #[test]
+  fn reward_terms_apply_spec_over_defaults() {
+    let distance = RewardComponent::DistanceToGoalInSimulation;
+    assert_eq!(distance.term_with(None).apply(500.0), 0.5);
+    let spec = RewardTermSpec {weight: Some(-2.0), clip: Some([-0.5, 0.0]), ..RewardTermSpec::default()};
+    let term = distance.term_with(Some(&spec));
+    assert_eq!(term.normalize, Some(1000.0));
+    assert_eq!(term.apply(100.0), -0.2);
+    assert_eq!(term.apply(900.0), -0.5);
+    let parsed: std::collections::BTreeMap<RewardComponent, RewardTermSpec> = serde_json::from_str("{\"air_time\": {\"weight\": 0.5}, \"0\": {\"clip\": [0, 1]}}").unwrap();
+    assert_eq!(serde_json::to_string(&parsed).unwrap(), "{\"0\":{\"clip\":[0.0,1.0]},\"12\":{\"weight\":0.5}}");
+    assert!(serde_json::from_str::<RewardTermSpec>("{\"wieght\": 1}").is_err());
+  }
+}
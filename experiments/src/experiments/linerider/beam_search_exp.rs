@@ -0,0 +1,69 @@
+use compressed_vec::CompressedVec;
+use rusty_gym::{GymEnvironment, ReplayableGymEnvironment, RunData, RUNTYPE_TRAINING};
+use linerider::{
+  algo::beam_search::{BeamSearchParams, record_demonstrations, simulation_goal_reached},
+  env::LineRider3DEnv, simulator::LineRiderSim,
+  util::types::{ActionType, TargetType}
+};
+use xp_tools::{fs::save_cbor_and_flate_to_path, rng::from_seed};
+use rand::RngCore;
+use std::time::Instant;
+
+pub const BEAM_SEARCH_EPISODES: usize = 100;
+pub const BEAM_SEARCH_SEED: u64 = 42;
+
+/** Rider success rate of the straight line expert on the seeds record_demonstrations draws from seed */
+fn straight_line_success_rate(env: &mut LineRider3DEnv, episode_amount: usize, seed: u64) -> f32 {
+  let (mut rng, _) = from_seed(Some(seed));
+  let mut successes = 0;
+  for _ in 0..episode_amount {
+    env.use_seed(rng.next_u64());
+    env.reset();
+    loop {
+      let action = env.get_expert_action().expect("expert supports the action type");
+      let step = env.step(&action);
+      if step.is_done {
+        successes += simulation_goal_reached(&step.info) as usize;
+        break;
+      }
+    }
+  }
+  successes as f32 / episode_amount as f32
+}
+
+/**
+ * Records beam search demonstrations for targets above the start and with checkpoints, saves them as .tlr runs
+ * in result_dir/<env>/beam_search_<target_type> and compares their success rate with the straight line expert.
+ */
+pub fn run_beam_search_demonstrations(result_dir_opt: &Option<&String>) {
+  let default_dir = "./trl-experiments".to_owned();
+  let result_dir = result_dir_opt.unwrap_or(&default_dir);
+  let params = BeamSearchParams::default();
+  for target_type in [TargetType::AboveStart, TargetType::RandomWithCheckpointAbove, TargetType::RandomWithCheckpointBelow] {
+    let mut env = LineRider3DEnv::new(LineRiderSim::new(false), None);
+    let mut config = env.get_config();
+    config.insert("action_type".to_owned(), ActionType::FreePointsWithTpRelative.to_string());
+    config.insert("target_type".to_owned(), target_type.to_string());
+    env.load_config(&config);
+    let start = Instant::now();
+    let demonstrations = record_demonstrations(&mut env, &params, BEAM_SEARCH_EPISODES, Some(BEAM_SEARCH_SEED));
+    let time_needed = start.elapsed().as_millis() as u64;
+    let beam_search_rate = demonstrations.iter().filter(|demonstration| demonstration.goal_reached).count() as f32 / BEAM_SEARCH_EPISODES as f32;
+    let straight_line_rate = straight_line_success_rate(&mut env, BEAM_SEARCH_EPISODES, BEAM_SEARCH_SEED);
+    println!("{}: beam search reaches the goal in {}%, the straight line expert in {}%", target_type, beam_search_rate * 100.0, straight_line_rate * 100.0);
+
+    let algo_name = format!("beam_search_{}", target_type);
+    let algo_dir = format!("{}/{}/{}", result_dir, env.get_name(), algo_name);
+    std::fs::create_dir_all(&algo_dir).expect("Able to create the directory of the demonstrations");
+    let mut episodes = CompressedVec::new();
+    let mut rewards = Vec::with_capacity(demonstrations.len());
+    for demonstration in demonstrations {
+      rewards.push(demonstration.reward);
+      episodes.push(demonstration.episode);
+    }
+    episodes.finalize();
+    let mut run = RunData::new(RUNTYPE_TRAINING, env.get_name(), algo_name, episodes, env.get_config(), None, time_needed, None, None);
+    run.reward_per_episode = Some(rewards);
+    save_cbor_and_flate_to_path(format!("{}/{}.tlr", algo_dir, run.uid).as_str(), &run);
+  }
+}
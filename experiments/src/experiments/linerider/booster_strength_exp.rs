@@ -1,15 +1,13 @@
 use std::collections::HashMap;
-use rusty_gym::{GymEnvironment, GymRecorder, Observation, ReplayableGymEnvironment};
+use rusty_gym::ReplayableGymEnvironment;
 use linerider::{
-  algo::heuristic::{straight_line_heuristic_general},
+  algo::heuristic::{HeuristicAlgorithm, straight_line_heuristic_general},
   env::LineRider3DEnv, simulator::LineRiderSim,
-  util::consts::{ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE,
-    TARGET_ABOVE_START, TARGET_SAME_HEIGHT_AS_START,
-    TARGET_RANDOM_START_AND_END
-  }
+  util::types::{ActionType, TargetType}
 };
 use rayon::prelude::*;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::MultiProgress;
+use super::heuristic_log::{make_progress_bar, run_heuristic};
 
 pub fn find_optimal_booster_strength() {
   let m = MultiProgress::new();
@@ -22,9 +20,9 @@ pub fn find_optimal_booster_strength() {
     let sim = LineRiderSim::new(false);
     let mut env = LineRider3DEnv::new(sim, None);
     let mut config = env.get_config();
-    config.insert("action_type".to_owned(), ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE.to_string());
-    config.insert("reward_type".to_owned(), "[254, 0]".to_owned());
-    config.insert("target_type".to_owned(), TARGET_ABOVE_START.to_string());
+    config.insert("action_type".to_owned(), ActionType::FreePointsWithTpRelative.to_string());
+    config.insert("reward_type".to_owned(), "[0]".to_owned());
+    config.insert("target_type".to_owned(), TargetType::AboveStart.to_string());
     config.insert("booster_strength".to_owned(), booster_strength.to_string());
     config.insert("max_up_angle".to_owned(), "[-90.0, 90.0]".to_string());
     config.insert("simulation_steps".to_owned(), ((1000/80)*600).to_string());
@@ -44,10 +42,10 @@ pub fn find_starting_force() {
     let sim = LineRiderSim::new(false);
     let mut env = LineRider3DEnv::new(sim, None);
     let mut config = env.get_config();
-    config.insert("action_type".to_owned(), ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE.to_string());
-    config.insert("reward_type".to_owned(), "[254, 0]".to_owned());
+    config.insert("action_type".to_owned(), ActionType::FreePointsWithTpRelative.to_string());
+    config.insert("reward_type".to_owned(), "[0]".to_owned());
     config.insert("smooth_free_points".to_owned(), "true".to_owned());
-    config.insert("target_type".to_owned(), TARGET_RANDOM_START_AND_END.to_string());
+    config.insert("target_type".to_owned(), TargetType::RandomStartAndEnd.to_string());
     config.insert("simulation_steps".to_owned(), ((1000/80)*600).to_string());
     config.insert("starting_force_multiplier".to_owned(), starting_force_multiplier.to_string());
     let success = make_episodes_for_heuristic(100000, &config, &m, &format!("startingforce_{}", starting_force_multiplier));
@@ -74,9 +72,9 @@ pub fn find_optimal_booster_strength__() {
     let sim = LineRiderSim::new(false);
     let mut env = LineRider3DEnv::new(sim, None);
     let mut config = env.get_config();
-    config.insert("action_type".to_owned(), ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE.to_string());
-    config.insert("reward_type".to_owned(), "[254, 0, 1]".to_owned());
-    config.insert("target_type".to_owned(), TARGET_ABOVE_START.to_string());
+    config.insert("action_type".to_owned(), ActionType::FreePointsWithTpRelative.to_string());
+    config.insert("reward_type".to_owned(), "[0, 1]".to_owned());
+    config.insert("target_type".to_owned(), TargetType::AboveStart.to_string());
     config.insert("booster_strength".to_owned(), "0.3".to_owned());
     config.insert("max_up_angle".to_owned(), booster_strength.to_string());
     config.insert("simulation_steps".to_owned(), ((1000/80)*600).to_string());
@@ -88,39 +86,11 @@ pub fn find_optimal_booster_strength__() {
 }
 
 pub fn make_episodes_for_heuristic(episode_amount: usize, config: &HashMap<String, String>, m: &MultiProgress, name_addon: &str) -> usize {
-  let target_type = config.get("target_type").expect("Target Type must be in Config");
-  let target_type_val = target_type.parse::<u8>().expect("Target Type is of u8 type");
-  let name_to_use = name_addon.to_string();
-  let sty = ProgressStyle::with_template(
-    "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
-  )
-  .unwrap()
-  .progress_chars("##-");
-  let pb = m.add(ProgressBar::new(episode_amount as u64));
-  pb.set_style(sty.clone());
-  let sim = LineRiderSim::new(false);
-  let mut env = LineRider3DEnv::new(sim, None);
-  env.load_config(config);
-  let mut recording_env = GymRecorder::new(Box::new(env), Some(name_addon.to_owned()));
-
-  let mut obs: Observation = recording_env.reset();
-  let mut current_episode = 0;
-  let mut success_counter = 0;
-  while current_episode < episode_amount {
-    let step = recording_env.step(&straight_line_heuristic_general(&obs, config, Some(1.5)));
-    if step.is_done {
-      if step.reward >= 2.0 {
-        success_counter += 1;
-      }
-      obs = recording_env.reset();
-      current_episode += 1;
-      pb.inc(1);
-    } else {
-      obs = step.obs;
-    }
-  }
+  let mut algo = HeuristicAlgorithm::new(&format!("heuristic_{}", name_addon), straight_line_heuristic_general);
+  algo.multiplier = Some(1.5);
+  algo.episodes = Some(episode_amount);
+  algo.on_episode_end = Some(make_progress_bar(episode_amount, m));
+  let success_counter = run_heuristic(algo, config).iter().filter(|reward| **reward >= 2.0).count();
   m.println(format!("For strength {} success is {} / {} ({}%)", name_addon, success_counter, episode_amount, (success_counter as f32 / episode_amount as f32) * 100.0)).unwrap();
-  recording_env.finalize("uptest", "");
   success_counter
 }
-
@@ -0,0 +1,36 @@
+use std::collections::HashMap;
+use rusty_gym::{
+  EnvironmentMaker, EvolutionHyperparams, EvolutionMethod, EvolutionTarget, EvolutionaryAlgorithm,
+  RlExperimentHelper, SelfTrainingAlgo
+};
+use super::heuristic_log::make_linerider_env;
+
+/** Environment steps every evolutionary run may spend on evaluating candidates */
+pub const EVOLUTION_STEPS: usize = 200_000;
+pub const EVOLUTION_RUNS: usize = 5;
+pub const EVOLUTION_EVAL_EPISODES: usize = 10;
+
+/**
+ * Runs CMA-ES and the genetic algorithm as per-instance search and CMA-ES as open-loop policy on LineRider
+ * through the RlExperimentHelper, so that their .tlr files can be compared with the runs of the learned agents.
+ */
+pub fn run_evolution(result_dir_opt: &Option<&String>) {
+  let default_dir = "./trl-experiments".to_owned();
+  let result_dir = result_dir_opt.unwrap_or(&default_dir);
+  let action_space = make_linerider_env(&HashMap::new()).action_space();
+  let variants = [
+    (EvolutionMethod::CmaEs, EvolutionTarget::Instance),
+    (EvolutionMethod::Genetic, EvolutionTarget::Instance),
+    (EvolutionMethod::CmaEs, EvolutionTarget::OpenLoop)
+  ];
+  let algorithms: Vec<Box<dyn SelfTrainingAlgo>> = variants.iter().map(|(method, target)| {
+    let params = EvolutionHyperparams {method: *method, target: *target, ..Default::default()};
+    Box::new(EvolutionaryAlgorithm::new(action_space.clone(), params)) as Box<dyn SelfTrainingAlgo>
+  }).collect();
+  let environments: Vec<EnvironmentMaker> = vec![Box::new(make_linerider_env)];
+  let mut evaluator = RlExperimentHelper::with_config(result_dir.clone(), algorithms, environments);
+  evaluator.steps_per_env = EVOLUTION_STEPS;
+  evaluator.runs_per_env = EVOLUTION_RUNS;
+  evaluator.eval_episode_amount = EVOLUTION_EVAL_EPISODES;
+  evaluator.do_evaluation();
+}
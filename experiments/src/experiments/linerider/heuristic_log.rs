@@ -1,25 +1,25 @@
 use std::collections::HashMap;
-use rusty_gym::{GymEnvironment, GymRecorder, Observation, ReplayableGymEnvironment};
+use rusty_gym::{get_run_amount, EnvironmentMaker, ReplayableGymEnvironment, Reward, RlAlgorithm, RlExperimentHelper, SelfTrainingAlgo, ENV_REPLAY_PATH, RUNTYPE_TRAINING};
 use linerider::{
-  algo::heuristic::straight_line_heuristic_general,
+  algo::heuristic::{HeuristicAlgorithm, straight_line_heuristic_general},
   env::LineRider3DEnv, simulator::LineRiderSim,
-  util::consts::*
+  util::types::{ActionType, TargetType}
 };
+use xp_tools::get_env_variable;
 use rayon::prelude::*;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
 pub fn make_heuristic_logs() {
-  // TODO: runid is not correct
   let m = MultiProgress::new();
   m.println("Starting to generate Heuristic Logs").unwrap();
   let size = 10;
-  let target_types = vec![TARGET_RANDOM_START_AND_END, TARGET_SAME_HEIGHT_AS_START, TARGET_ABOVE_START];//TARGET_RANDOM_START_AND_END, TARGET_SAME_HEIGHT_AS_START, TARGET_ABOVE_START];
+  let target_types = vec![TargetType::RandomStartAndEnd, TargetType::SameHeightAsStart, TargetType::AboveStart];//TargetType::RandomStartAndEnd, TargetType::SameHeightAsStart, TargetType::AboveStart];
   target_types.par_iter().for_each(|target_type| {
     let sim = LineRiderSim::new(false);
     let mut env = LineRider3DEnv::new(sim, None);
     let mut config = env.get_config();
-    config.insert("action_type".to_owned(), ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE.to_string());
-    config.insert("reward_type".to_owned(), "[254]".to_owned());
+    config.insert("action_type".to_owned(), ActionType::FreePointsWithTpRelative.to_string());
+    config.insert("reward_type".to_owned(), "[]".to_owned());
     config.insert("target_type".to_owned(), target_type.to_string());
     config.insert("booster_strength".to_owned(), "0.26".to_string());
     config.insert("step_limit".to_owned(), size.to_string());
@@ -29,7 +29,6 @@ pub fn make_heuristic_logs() {
   });
 }
 pub fn make_heuristic_logs_size() {
-  // TODO: runid is not correct
   let m = MultiProgress::new();
   m.println("Starting to generate Heuristic Logs").unwrap();
   let sizes = vec![10, 20, 30, 40, 50];
@@ -37,9 +36,9 @@ pub fn make_heuristic_logs_size() {
     let sim = LineRiderSim::new(false);
     let mut env = LineRider3DEnv::new(sim, None);
     let mut config = env.get_config();
-    config.insert("action_type".to_owned(), ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE.to_string());
-    config.insert("reward_type".to_owned(), "[254]".to_owned());
-    config.insert("target_type".to_owned(), TARGET_RANDOM_START_AND_END.to_string());
+    config.insert("action_type".to_owned(), ActionType::FreePointsWithTpRelative.to_string());
+    config.insert("reward_type".to_owned(), "[]".to_owned());
+    config.insert("target_type".to_owned(), TargetType::RandomStartAndEnd.to_string());
     config.insert("step_limit".to_owned(), size.to_string());
     config.insert("max_width".to_owned(), size.to_string());
     config.insert("simulation_steps".to_owned(), ((1000/80)*(80*size)).to_string());
@@ -49,41 +48,62 @@ pub fn make_heuristic_logs_size() {
 
 pub fn make_episodes_for_heuristic(episode_amount: usize, config: &HashMap<String, String>, m: &MultiProgress, name_addon_opt: Option<&str>) {
   let target_type = config.get("target_type").expect("Target Type must be in Config");
-  let target_type_val = target_type.parse::<u8>().expect("Target Type is of u8 type");
+  let target_type_val = target_type.parse::<TargetType>().expect("Target Type is a known target type");
   let name_addon = if name_addon_opt.is_some() {name_addon_opt.unwrap()} else {
     match target_type_val {
-      TARGET_ABOVE_START => {"up"},
-      TARGET_SAME_HEIGHT_AS_START => {"same"},
-      TARGET_RANDOM_WITH_CHECKPOINT_ABOVE => {"chk_up"},
-      TARGET_RANDOM_WITH_CHECKPOINT_BELOW => {"chk_down"},
+      TargetType::AboveStart => {"up"},
+      TargetType::SameHeightAsStart => {"same"},
+      TargetType::RandomWithCheckpointAbove => {"chk_up"},
+      TargetType::RandomWithCheckpointBelow => {"chk_down"},
       _ => {"down"}
     }
   };
-  let name_to_use = format!("heuristic_{}", name_addon);
+  let mut algo = HeuristicAlgorithm::new(&format!("heuristic_{}", name_addon), straight_line_heuristic_general);
+  algo.episodes = Some(episode_amount);
+  algo.on_episode_end = Some(make_progress_bar(episode_amount, m));
+  run_heuristic(algo, config);
+}
+
+/** Progress bar in m that advances with every episode the heuristic plays */
+pub fn make_progress_bar(episode_amount: usize, m: &MultiProgress) -> Box<dyn FnMut(Reward)> {
   let sty = ProgressStyle::with_template(
     "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
   )
   .unwrap()
   .progress_chars("##-");
   let pb = m.add(ProgressBar::new(episode_amount as u64));
-  pb.set_style(sty.clone());
-  let sim = LineRiderSim::new(false);
-  let mut env = LineRider3DEnv::new(sim, None);
-  env.load_config(config);
-  let mut recording_env = GymRecorder::new(Box::new(env), Some(name_addon.to_owned()));
+  pb.set_style(sty);
+  Box::new(move |_| pb.inc(1))
+}
 
-  let mut obs: Observation = recording_env.reset();
-  let mut current_episode = 0;
-  while current_episode < episode_amount {
-    let step = recording_env.step(&straight_line_heuristic_general(&obs, config, None));
-    if step.is_done {
-      obs = recording_env.reset();
-      current_episode += 1;
-      pb.inc(1);
-    } else {
-      obs = step.obs;
-    }
-  }
-  recording_env.finalize("heuristic", "");
+/** EnvironmentMaker of LineRider without rendering, shared by the linerider experiments */
+pub fn make_linerider_env(config: &HashMap<String, String>) -> Box<dyn ReplayableGymEnvironment> {
+  let mut env = LineRider3DEnv::new(LineRiderSim::new(false), None);
+  env.load_config(config);
+  Box::new(env)
 }
 
+/**
+ * Trains and evaluates the heuristic on LineRider with the given config through the RlExperimentHelper,
+ * so that its runs land in TLF_REPLAY_PATH/<heuristic name> in the same layout as those of learned agents.
+ * Every heuristic has its own result dir, so that heuristics can run in parallel without sharing a run_data.bin.
+ * Returns the reward of the last step of every training episode.
+ */
+pub fn run_heuristic(algo: HeuristicAlgorithm, config: &HashMap<String, String>) -> Vec<Reward> {
+  let algo_name = algo.get_name();
+  let result_dir = format!("{}/{}", get_env_variable(ENV_REPLAY_PATH).unwrap_or("trl-experiments".to_owned()), algo_name);
+  let final_rewards = algo.final_rewards.clone();
+  let env_name = make_linerider_env(config).get_name();
+  std::fs::create_dir_all(format!("{}/{}/{}", result_dir, env_name, algo_name)).expect("Able to create the directory of the heuristic");
+  let algorithms: Vec<Box<dyn SelfTrainingAlgo>> = vec![Box::new(algo)];
+  let environments: Vec<EnvironmentMaker> = vec![Box::new(make_linerider_env)];
+  let mut evaluator = RlExperimentHelper::with_config(result_dir.clone(), algorithms, environments);
+  // runs of earlier calls are kept, one more run than those makes sure that the heuristic is played again
+  let runs = get_run_amount(&evaluator.run_data, &env_name, &algo_name, RUNTYPE_TRAINING, config, false) + 1;
+  RlExperimentHelper::run_experiment_for_config(
+    &mut evaluator.environments[0], &mut evaluator.algorithms[0], config, None, runs - 1, None,
+    &result_dir, &mut evaluator.run_data, runs, 0, evaluator.eval_episode_amount
+  );
+  let rewards = final_rewards.lock().unwrap().clone();
+  rewards
+}
@@ -0,0 +1,368 @@
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use linerider::{
+  env::LineRider3DEnv, simulator::{LineRiderSim, LineRiderSimulationResult},
+  util::metrics::{TrackMetrics, compute_track_metrics}
+};
+use rusty_gym::{Action, EpisodeData, GymEnvironment, ReplayableGymEnvironment, Reward, RunData, Space,
+  RUNTYPE_TRAINING, load_run_convert_python, sum};
+use xp_tools::{fs::save_cbor_and_flate_to_path, rng::from_seed};
+use compressed_vec::CompressedVec;
+use plotters::prelude::*;
+use rand::Rng;
+use walkdir::WalkDir;
+
+/** Fraction of the actions of a parent that are changed by mutate_actions */
+pub const MUTATION_RATE: f64 = 0.2;
+/** Continuous actions move by at most this fraction of the width of their action space */
+pub const MUTATION_STRENGTH: f64 = 0.1;
+pub const HEATMAP_CELL_PIXELS: u32 = 32;
+pub const DEFAULT_DESCRIPTORS: &str = "air_time_fraction:0:1:10,height_gain:0:10:10";
+
+/** Properties of a ride or track that span the dimensions of the archive */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BehaviourDescriptor {
+  AirTimeFraction,
+  HeightGain,
+  TrackLength,
+  Rotation,
+  VelocityAtEnd,
+  BoostFraction,
+  Curvature,
+  DirectionChanges
+}
+
+impl BehaviourDescriptor {
+  pub fn name(&self) -> &'static str {
+    match self {
+      BehaviourDescriptor::AirTimeFraction => "air_time_fraction",
+      BehaviourDescriptor::HeightGain => "height_gain",
+      BehaviourDescriptor::TrackLength => "track_length",
+      BehaviourDescriptor::Rotation => "rotation",
+      BehaviourDescriptor::VelocityAtEnd => "velocity_at_end",
+      BehaviourDescriptor::BoostFraction => "boost_fraction",
+      BehaviourDescriptor::Curvature => "curvature",
+      BehaviourDescriptor::DirectionChanges => "direction_changes"
+    }
+  }
+  pub fn value(&self, sim_res: &LineRiderSimulationResult, metrics: &TrackMetrics) -> f32 {
+    match self {
+      BehaviourDescriptor::AirTimeFraction => {
+        if sim_res.total_time == 0 {0.0} else {sim_res.time_rider_airborne as f32 / sim_res.total_time as f32}
+      },
+      BehaviourDescriptor::HeightGain => sim_res.overall_height_gain,
+      BehaviourDescriptor::TrackLength => metrics.total_length,
+      BehaviourDescriptor::Rotation => sim_res.overall_rotation,
+      BehaviourDescriptor::VelocityAtEnd => sim_res.velocity_at_end,
+      BehaviourDescriptor::BoostFraction => metrics.boost_fraction,
+      BehaviourDescriptor::Curvature => metrics.curvature_mean,
+      BehaviourDescriptor::DirectionChanges => metrics.direction_changes as f32
+    }
+  }
+}
+
+impl FromStr for BehaviourDescriptor {
+  type Err = String;
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    [
+      BehaviourDescriptor::AirTimeFraction, BehaviourDescriptor::HeightGain, BehaviourDescriptor::TrackLength,
+      BehaviourDescriptor::Rotation, BehaviourDescriptor::VelocityAtEnd, BehaviourDescriptor::BoostFraction,
+      BehaviourDescriptor::Curvature, BehaviourDescriptor::DirectionChanges
+    ].into_iter().find(|descriptor| descriptor.name() == name).ok_or_else(|| format!("unknown behaviour descriptor '{}'", name))
+  }
+}
+
+/** One dimension of the archive, values outside of min and max end up in the first or last bin */
+#[derive(Debug, Clone, PartialEq)]
+pub struct DescriptorAxis {
+  pub descriptor: BehaviourDescriptor,
+  pub min: f32,
+  pub max: f32,
+  pub bins: usize
+}
+
+impl DescriptorAxis {
+  pub fn bin_of(&self, value: f32) -> usize {
+    let relative = (value - self.min) / (self.max - self.min);
+    ((relative * self.bins as f32).floor().max(0.0) as usize).min(self.bins - 1)
+  }
+}
+
+impl FromStr for DescriptorAxis {
+  type Err = String;
+  /** Parses name:min:max:bins, e.g. air_time_fraction:0:1:10 */
+  fn from_str(axis: &str) -> Result<Self, Self::Err> {
+    let parts: Vec<&str> = axis.split(':').collect();
+    if parts.len() != 4 {
+      return Err(format!("descriptor axis '{}' is not of the form name:min:max:bins", axis));
+    }
+    let parse_bound = |part: &str| part.parse::<f32>().map_err(|e| format!("bound '{}' of '{}' is not a number: {}", part, axis, e));
+    let (min, max) = (parse_bound(parts[1])?, parse_bound(parts[2])?);
+    let bins = parts[3].parse::<usize>().map_err(|e| format!("bins '{}' of '{}' is not a number: {}", parts[3], axis, e))?;
+    if min >= max || bins == 0 {
+      return Err(format!("descriptor axis '{}' needs min < max and at least one bin", axis));
+    }
+    Ok(DescriptorAxis {descriptor: parts[0].parse()?, min, max, bins})
+  }
+}
+
+pub fn parse_descriptor_axes(axes: &str) -> Result<Vec<DescriptorAxis>, String> {
+  axes.split(',').map(|axis| axis.trim().parse()).collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct Elite {
+  /** Carries the env config in env_params so that it can be replayed on its own */
+  pub episode: EpisodeData,
+  pub fitness: Reward,
+  pub descriptors: Vec<f32>
+}
+
+/** Best episode per cell of the behaviour space spanned by the axes */
+#[derive(Debug, Clone)]
+pub struct MapElitesArchive {
+  pub axes: Vec<DescriptorAxis>,
+  pub cells: BTreeMap<Vec<usize>, Elite>
+}
+
+impl MapElitesArchive {
+  pub fn new(axes: Vec<DescriptorAxis>) -> MapElitesArchive {
+    MapElitesArchive {axes, cells: BTreeMap::new()}
+  }
+  pub fn cell_of(&self, descriptors: &[f32]) -> Vec<usize> {
+    self.axes.iter().zip(descriptors.iter()).map(|(axis, value)| axis.bin_of(*value)).collect()
+  }
+  /** Keeps the elite if its cell is empty or it beats the fitness of the current one, returns whether it was kept */
+  pub fn try_insert(&mut self, elite: Elite) -> bool {
+    let cell = self.cell_of(&elite.descriptors);
+    let is_better = self.cells.get(&cell).is_none_or(|current| elite.fitness > current.fitness);
+    if is_better {
+      self.cells.insert(cell, elite);
+    }
+    is_better
+  }
+  pub fn coverage(&self) -> f32 {
+    let cell_count: usize = self.axes.iter().map(|axis| axis.bins).product();
+    self.cells.len() as f32 / cell_count as f32
+  }
+  /** Elites as a run, the cell and descriptors of each episode are kept in its algorithm_hyperparams */
+  pub fn to_run_data(&self, env_name: &str, run_id: &str) -> RunData {
+    let mut episodes: CompressedVec<EpisodeData> = CompressedVec::new();
+    let mut rewards: Vec<Reward> = Vec::with_capacity(self.cells.len());
+    for (cell, elite) in self.cells.iter() {
+      let mut episode = elite.episode.clone();
+      let mut elite_info: HashMap<String, String> = HashMap::new();
+      elite_info.insert("elite_cell".to_owned(), format!("{:?}", cell));
+      for (axis, value) in self.axes.iter().zip(elite.descriptors.iter()) {
+        elite_info.insert(axis.descriptor.name().to_owned(), value.to_string());
+      }
+      episode.algorithm_hyperparams = Some(elite_info);
+      episodes.push(episode);
+      rewards.push(elite.fitness);
+    }
+    episodes.finalize();
+    let env_config = self.cells.values().next().and_then(|elite| elite.episode.env_params.clone()).unwrap_or_default();
+    let mut hyperparams: HashMap<String, String> = HashMap::new();
+    let axes: Vec<String> = self.axes.iter().map(|axis| format!("{}:{}:{}:{}", axis.descriptor.name(), axis.min, axis.max, axis.bins)).collect();
+    hyperparams.insert("descriptors".to_owned(), axes.join(","));
+    let mut run = RunData::new(RUNTYPE_TRAINING, env_name.to_owned(), "map_elites".to_owned(), episodes, env_config, Some(hyperparams), 0, Some(run_id.to_owned()), None);
+    run.reward_per_episode = Some(rewards);
+    run
+  }
+  /**
+   * Draws the fitness of the elites over the first two axes, blue is the worst and red the best elite, empty cells stay white.
+   * Further axes are collapsed to the best elite.
+   */
+  pub fn save_heatmap(&self, path: &str) {
+    let columns = self.axes.first().map_or(1, |axis| axis.bins);
+    let rows = self.axes.get(1).map_or(1, |axis| axis.bins);
+    let mut best: HashMap<(usize, usize), Reward> = HashMap::new();
+    for (cell, elite) in self.cells.iter() {
+      let position = (cell.first().cloned().unwrap_or(0), cell.get(1).cloned().unwrap_or(0));
+      let current = best.entry(position).or_insert(elite.fitness);
+      *current = current.max(elite.fitness);
+    }
+    let lowest = best.values().cloned().fold(f64::INFINITY, f64::min);
+    let highest = best.values().cloned().fold(f64::NEG_INFINITY, f64::max);
+    let root = BitMapBackend::new(path, (columns as u32 * HEATMAP_CELL_PIXELS, rows as u32 * HEATMAP_CELL_PIXELS)).into_drawing_area();
+    root.fill(&WHITE).unwrap();
+    for ((column, row), fitness) in best {
+      let relative = if highest > lowest {(fitness - lowest) / (highest - lowest)} else {1.0};
+      let color = RGBColor((255.0 * relative) as u8, 0, (255.0 * (1.0 - relative)) as u8);
+      // the lowest bin of the second axis is at the bottom
+      let top = (rows - 1 - row) as i32 * HEATMAP_CELL_PIXELS as i32;
+      let left = column as i32 * HEATMAP_CELL_PIXELS as i32;
+      root.draw(&Rectangle::new([(left, top), (left + HEATMAP_CELL_PIXELS as i32, top + HEATMAP_CELL_PIXELS as i32)], color.filled())).unwrap();
+    }
+    root.present().unwrap();
+  }
+  pub fn save(&self, result_dir: &str, run_id: &str) {
+    std::fs::create_dir_all(result_dir).expect("Able to create the directory for the archive");
+    save_cbor_and_flate_to_path(format!("{}/{}.tlr", result_dir, run_id).as_str(), &self.to_run_data("LineRider3D-Env-v0", run_id));
+    self.save_heatmap(&format!("{}/{}_coverage.png", result_dir, run_id));
+  }
+}
+
+/**
+ * Replays the episode like post_analysis does and simulates the finished track.
+ * Actions after the end of the build phase are dropped from the returned episode.
+ */
+pub fn evaluate_episode(env: &mut LineRider3DEnv, episode: &EpisodeData, axes: &[DescriptorAxis]) -> Elite {
+  if let Some(env_config) = &episode.env_params {
+    env.load_config(env_config);
+  }
+  env.skip_simulation = true;
+  env.use_seed(episode.seed);
+  env.reset();
+  let mut rewards: Vec<Reward> = Vec::with_capacity(episode.log.len());
+  let mut log: Vec<Action> = Vec::with_capacity(episode.log.len());
+  for action in episode.log.iter() {
+    let step = env.step(action);
+    rewards.push(step.reward);
+    log.push(action.clone());
+    if step.is_done {
+      break;
+    }
+  }
+  rewards.pop();
+  env.skip_simulation = false;
+  if env.sim.config.action_type.is_free_points() {
+    env.add_lines_freeroam();
+  } else {
+    env.add_lines();
+  }
+  let sim_res = env.sim.simulate_till_end(env.sim.config.simulation_steps);
+  let metrics = compute_track_metrics(&env.lines, &sim_res.trajectory, &env.sim.build_range, &env.sim.config);
+  let descriptors = axes.iter().map(|axis| axis.descriptor.value(&sim_res, &metrics)).collect();
+  rewards.push(env.get_reward_from_simulation_result(sim_res));
+  let mut evaluated = EpisodeData::new(episode.seed, log);
+  evaluated.env_params = Some(env.get_config());
+  Elite {episode: evaluated, fitness: sum(&rewards), descriptors}
+}
+
+/** Changes every action with probability rate, discrete ones to a random action and continuous ones by a small step within the bounds */
+pub fn mutate_actions<R: Rng>(log: &[Action], action_space: &Space, rate: f64, rng: &mut R) -> Vec<Action> {
+  log.iter().map(|action| {
+    if !rng.gen_bool(rate) {
+      return action.clone();
+    }
+    let mut mutated = action.clone();
+    match action_space {
+      Space::Discrete(actions) => {
+        mutated[0] = rng.gen_range(0..*actions) as f64;
+      },
+      Space::BoxedWithRange(_, low, high) => {
+        for (i, value) in mutated.iter_mut().enumerate() {
+          let step = (high[i] - low[i]) * MUTATION_STRENGTH;
+          *value = (*value + rng.gen_range(-step..=step)).clamp(low[i], high[i]);
+        }
+      },
+      Space::BoxedWithoutRange(_) => {
+        for value in mutated.iter_mut() {
+          *value += rng.gen_range(-MUTATION_STRENGTH..=MUTATION_STRENGTH);
+        }
+      }
+    }
+    mutated
+  }).collect()
+}
+
+/** Mutates the action logs of randomly picked elites and offers the results to the archive, returns how many were kept */
+pub fn improve_archive(archive: &mut MapElitesArchive, env: &mut LineRider3DEnv, iterations: usize, seed: u64) -> usize {
+  let (mut rng, _) = from_seed(Some(seed));
+  let mut kept = 0;
+  for _ in 0..iterations {
+    if archive.cells.is_empty() {
+      break;
+    }
+    let parent_index = rng.gen_range(0..archive.cells.len());
+    let parent = archive.cells.values().nth(parent_index).expect("index is within the archive").episode.clone();
+    if let Some(env_config) = &parent.env_params {
+      env.load_config(env_config);
+    }
+    let mut child = parent.clone();
+    child.log = mutate_actions(&parent.log, &env.action_space(), MUTATION_RATE, &mut rng);
+    let axes = archive.axes.clone();
+    if archive.try_insert(evaluate_episode(env, &child, &axes)) {
+      kept += 1;
+    }
+  }
+  kept
+}
+
+/**
+ * Fills an archive with the episodes of every .tlr run in result_dir, improves it through mutation
+ * and saves it as map_elites.tlr together with a coverage heatmap.
+ */
+pub fn run_map_elites(result_dir_opt: &Option<&String>, descriptors: &str, iterations: usize) {
+  let axes = match parse_descriptor_axes(descriptors) {
+    Ok(axes) => axes,
+    Err(message) => {
+      println!("Could not parse descriptors: {}", message);
+      return;
+    }
+  };
+  let default_dir = "./trl-experiments".to_owned();
+  let result_dir = result_dir_opt.unwrap_or(&default_dir);
+  let mut archive = MapElitesArchive::new(axes);
+  let mut env = LineRider3DEnv::new(LineRiderSim::new(false), None);
+  for entry in WalkDir::new(result_dir).into_iter().filter_map(|entry| entry.ok()) {
+    let file_name = entry.path().to_str().unwrap_or_default().to_owned();
+    if !file_name.ends_with(".tlr") || file_name.contains("highlights_") || file_name.contains("map_elites") {
+      continue;
+    }
+    let mut run: RunData = load_run_convert_python(&file_name);
+    for mut episode in run.episodes.clone_to_vec() {
+      if episode.env_params.is_none() {
+        episode.env_params = Some(run.env_config.clone());
+      }
+      archive.try_insert(evaluate_episode(&mut env, &episode, &archive.axes.clone()));
+    }
+    println!("Archive covers {}% after {}", archive.coverage() * 100.0, file_name);
+  }
+  let kept = improve_archive(&mut archive, &mut env, iterations, 0);
+  println!("Mutation kept {} of {} children, archive covers {}%", kept, iterations, archive.coverage() * 100.0);
+  archive.save(result_dir, "map_elites");
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ndarray::ArrayBase;
+
+  fn elite(fitness: Reward, descriptors: Vec<f32>) -> Elite {
+    Elite {episode: EpisodeData::new(0, vec![]), fitness, descriptors}
+  }
+
+  #[test]
+  fn keeps_best_elite_per_cell() {
+    let axes = parse_descriptor_axes("air_time_fraction:0:1:4, track_length:0:10:2").unwrap();
+    assert_eq!(axes[1].descriptor, BehaviourDescriptor::TrackLength);
+    let mut archive = MapElitesArchive::new(axes);
+    assert_eq!(archive.cell_of(&[-1.0, 20.0]), vec![0, 1]);
+    assert_eq!(archive.cell_of(&[0.3, 5.0]), vec![1, 1]);
+    assert!(archive.try_insert(elite(1.0, vec![0.3, 6.0])));
+    assert!(!archive.try_insert(elite(0.5, vec![0.4, 9.0])));
+    assert!(archive.try_insert(elite(2.0, vec![0.26, 5.0])));
+    assert!(archive.try_insert(elite(0.0, vec![0.9, 1.0])));
+    assert_eq!(archive.cells[&vec![1, 1]].fitness, 2.0);
+    assert_eq!(archive.coverage(), 0.25);
+    let run = archive.to_run_data("LineRider3D-Env-v0", "test");
+    assert_eq!(run.reward_per_episode, Some(vec![2.0, 0.0]));
+    assert!(parse_descriptor_axes("air_time_fraction:1:0:4").is_err());
+    assert!(parse_descriptor_axes("fun:0:1:4").is_err());
+  }
+
+  #[test]
+  fn mutation_stays_in_action_space() {
+    let (mut rng, _) = from_seed(Some(3));
+    let log: Vec<Action> = (0..50).map(|_| ArrayBase::from(vec![0.5, 0.5]).into_dyn()).collect();
+    let space = Space::BoxedWithRange(vec![2], vec![0.0, 0.0], vec![1.0, 1.0]);
+    let mutated = mutate_actions(&log, &space, 1.0, &mut rng);
+    assert_eq!(mutated.len(), log.len());
+    assert!(mutated.iter().all(|action| action.iter().all(|value| (0.4..=0.6).contains(value))));
+    assert_ne!(mutated, log);
+    let discrete = mutate_actions(&log, &Space::Discrete(6), 0.0, &mut rng);
+    assert_eq!(discrete, log);
+  }
+}
@@ -3,4 +3,12 @@ pub mod post_analysis;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod heuristic_log;
 #[cfg(not(target_arch = "wasm32"))]
-pub mod booster_strength_exp;
\ No newline at end of file
+pub mod booster_strength_exp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod verify;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod map_elites;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod evolution;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod beam_search_exp;
\ No newline at end of file
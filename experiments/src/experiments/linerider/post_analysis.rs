@@ -1,5 +1,5 @@
 
-use linerider::{env::LineRider3DEnv, util::consts::*};
+use linerider::{env::LineRider3DEnv, util::metrics::compute_track_metrics};
 use rusty_gym::{ReplayableGymEnvironment,
   EnrichedEpisodeData, RewardVector, GymEnvironment,
   RunData, RunDataEnriched, load_run_convert_python, sum};
@@ -8,6 +8,11 @@ use compressed_vec::CompressedVec;
 use std::collections::HashMap;
 
 
+/** Rider samples per replayed simulation, so that the enriched metrics include the g-forces */
+#[cfg(not(target_arch = "wasm32"))]
+const ENRICHMENT_TRAJECTORY_STRIDE: usize = 10;
+
+
 pub fn extract_linerider_successes(result_dir_opt: &Option<&String>) {
   read_replays_and_extract_success(result_dir_opt)
 }
@@ -84,15 +89,14 @@ pub fn read_replays_and_extract_success(result_dir_opt: &Option<&String>) {
               }
               rewards.pop();
               env.skip_simulation = false;
-              match env.sim.config.action_type {
-                ACTION_TYPE_FREE_POINTS | ACTION_TYPE_FREE_POINTS_WITH_TP |
-                  ACTION_TYPE_FREE_POINTS_RELATIVE | ACTION_TYPE_FREE_POINTS_WITH_TP_RELATIVE => {
-                    env.add_lines_freeroam();
-                }
-                _ => {
-                  env.add_lines();
-                }
-              };
+              if env.sim.config.action_type.is_free_points() {
+                env.add_lines_freeroam();
+              } else {
+                env.add_lines();
+              }
+              if env.sim.config.trajectory_stride == 0 {
+                env.sim.config.trajectory_stride = ENRICHMENT_TRAJECTORY_STRIDE;
+              }
               let sim_res = env.sim.simulate_till_end(env.sim.config.simulation_steps);
               let reward = env.get_reward_from_simulation_result(sim_res.clone());
               let task_completion = if sim_res.goal_reached && env.track_reached_goal {
@@ -103,12 +107,14 @@ pub fn read_replays_and_extract_success(result_dir_opt: &Option<&String>) {
                 1.0
               } else {0.0};
               rewards.push(reward);
+              let mut additional_info = sim_res.to_map();
+              additional_info.extend(compute_track_metrics(&env.lines, &sim_res.trajectory, &env.sim.build_range, &env.sim.config).to_map());
               let enriched_episode = EnrichedEpisodeData {
                 log: episode_data.log.clone(),
                 seed: episode_data.seed,
                 rewards, algorithm_hyperparams: episode_data.algorithm_hyperparams.clone(),
                 env_params: episode_data.env_params.clone(),
-                task_completion, episode_nr: episode, additional_info: Some(sim_res.to_map())
+                task_completion, episode_nr: episode, additional_info: Some(additional_info)
               };
               all_episodes.push(enriched_episode.clone());
               let to_push: Option<EnrichedEpisodeData> = if task_completion >= 1.0 {Some(enriched_episode.clone())} else {None};
@@ -0,0 +1,153 @@
+use linerider::{env::LineRider3DEnv, simulator::LineRiderSim};
+use rusty_gym::{EpisodeData, GymEnvironment, ReplayableGymEnvironment,
+  Reward, RewardVector, RunData, RunDataEnriched, load_run_convert_python, sum};
+use xp_tools::fs::load_cbor_and_flate_file;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/** Runs recorded from python may have passed their rewards through f32 */
+pub const REWARD_TOLERANCE: f64 = 1e-6;
+
+/** Rewards stored in a run. Enriched runs (.tlrx) keep the reward of every step, regular runs only the sum */
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordedRewards {
+  Total(Reward),
+  PerStep(RewardVector)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EpisodeDivergence {
+  pub episode_nr: usize,
+  pub recorded: Reward,
+  pub replayed: Reward,
+  /** Only known if the rewards of every step were recorded */
+  pub first_differing_step: Option<usize>
+}
+
+fn rewards_differ(a: Reward, b: Reward) -> bool {
+  (a - b).abs() > REWARD_TOLERANCE * a.abs().max(b.abs()).max(1.0)
+}
+
+pub fn compare_rewards(episode_nr: usize, recorded: &RecordedRewards, replayed: &[Reward]) -> Option<EpisodeDivergence> {
+  match recorded {
+    RecordedRewards::Total(total) => {
+      if rewards_differ(*total, sum(replayed)) {
+        Some(EpisodeDivergence {episode_nr, recorded: *total, replayed: sum(replayed), first_differing_step: None})
+      } else {None}
+    },
+    RecordedRewards::PerStep(steps) => {
+      let first_differing_step = steps.iter().zip(replayed.iter())
+        .position(|(a, b)| rewards_differ(*a, *b))
+        .or(if steps.len() != replayed.len() {Some(steps.len().min(replayed.len()))} else {None});
+      first_differing_step.map(|step| EpisodeDivergence {
+        episode_nr, recorded: sum(steps), replayed: sum(replayed), first_differing_step: Some(step)
+      })
+    }
+  }
+}
+
+/** The config of the run is loaded for every episode, so an episode without env_params does not inherit those of the previous one */
+pub fn replay_episode(env: &mut LineRider3DEnv, run_config: &HashMap<String, String>, episode: &EpisodeData) -> RewardVector {
+  env.load_config(run_config);
+  if let Some(env_config) = &episode.env_params {
+    env.load_config(env_config);
+  }
+  env.use_seed(episode.seed);
+  env.reset();
+  episode.log.iter().map(|action| env.step(action).reward).collect()
+}
+
+fn load_episodes_with_rewards(path: &str) -> Result<(RunData, Vec<RecordedRewards>), String> {
+  if path.ends_with(".tlrx") {
+    let mut enriched_run: RunDataEnriched = load_cbor_and_flate_file(path);
+    let mut run = RunData::new(enriched_run.run_type, enriched_run.env.clone(), enriched_run.algo.clone(), Default::default(),
+      enriched_run.env_config.clone(), enriched_run.hyperparams.clone(), enriched_run.time_needed, Some(enriched_run.uid.clone()), enriched_run.is_eval_of.clone());
+    let mut recorded = Vec::with_capacity(enriched_run.episodes.len());
+    for episode in enriched_run.episodes.clone_to_vec() {
+      run.episodes.push(episode.to_regular_episode());
+      recorded.push(RecordedRewards::PerStep(episode.rewards));
+    }
+    Ok((run, recorded))
+  } else {
+    let run = load_run_convert_python(path);
+    let rewards = run.reward_per_episode.clone().ok_or_else(|| format!("{} has no reward_per_episode to compare against", path))?;
+    if rewards.len() != run.episodes.len() {
+      return Err(format!("{} has {} episodes but {} rewards", path, run.episodes.len(), rewards.len()));
+    }
+    Ok((run, rewards.into_iter().map(RecordedRewards::Total).collect()))
+  }
+}
+
+/**
+ * Replays every episode of the run at path through LineRider3DEnv and returns the episodes
+ * whose rewards differ from the recorded ones, e.g. because the physics are no longer deterministic.
+ */
+pub fn verify_run(path: &str) -> Result<Vec<EpisodeDivergence>, String> {
+  let (mut run, recorded) = load_episodes_with_rewards(path)?;
+  let episodes = run.episodes.clone_to_vec();
+  let chunk_size = (episodes.len() / rayon::current_num_threads()).max(1);
+  let mut divergences: Vec<EpisodeDivergence> = episodes.par_chunks(chunk_size).enumerate().flat_map_iter(|(chunk_nr, chunk)| {
+    let mut env = LineRider3DEnv::new(LineRiderSim::new(false), None);
+    chunk.iter().enumerate().filter_map(|(i, episode)| {
+      let episode_nr = chunk_nr * chunk_size + i;
+      compare_rewards(episode_nr, &recorded[episode_nr], &replay_episode(&mut env, &run.env_config, episode))
+    }).collect::<Vec<EpisodeDivergence>>()
+  }).collect();
+  divergences.sort_by_key(|divergence| divergence.episode_nr);
+  Ok(divergences)
+}
+
+/** Prints the divergent episodes of the run and returns whether it replayed exactly */
+pub fn verify_and_report(path: &str) -> bool {
+  match verify_run(path) {
+    Ok(divergences) if divergences.is_empty() => {
+      println!("All episodes of {} replay to the recorded rewards", path);
+      true
+    },
+    Ok(divergences) => {
+      for divergence in &divergences {
+        let step = divergence.first_differing_step.map(|s| s.to_string()).unwrap_or_else(|| "unknown".to_owned());
+        println!("Episode {} diverges: recorded {} replayed {} first differing step {}",
+          divergence.episode_nr, divergence.recorded, divergence.replayed, step);
+      }
+      println!("{} episodes of {} diverge", divergences.len(), path);
+      false
+    },
+    Err(message) => {
+      println!("Could not verify: {}", message);
+      false
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn finds_first_differing_step() {
+    let recorded = RecordedRewards::PerStep(vec![0.1, 0.2, 1.0]);
+    assert_eq!(compare_rewards(0, &recorded, &[0.1, 0.2, 1.0]), None);
+    assert_eq!(compare_rewards(3, &recorded, &[0.1, 0.25, 1.0]).unwrap().first_differing_step, Some(1));
+    assert_eq!(compare_rewards(3, &recorded, &[0.1, 0.2]).unwrap().first_differing_step, Some(2));
+    let total = RecordedRewards::Total(1.3);
+    assert_eq!(compare_rewards(0, &total, &[0.1, 0.2, 1.0]), None);
+    let divergence = compare_rewards(7, &total, &[0.1, 0.2, 2.0]).unwrap();
+    assert_eq!((divergence.episode_nr, divergence.first_differing_step), (7, None));
+  }
+
+  #[test]
+  fn episode_without_env_params_uses_run_config() {
+    let mut env = LineRider3DEnv::new(LineRiderSim::new(false), None);
+    let run_config = env.get_config();
+    let mut wide_config = run_config.clone();
+    wide_config.insert("max_width".to_owned(), "30".to_owned());
+    let action = ndarray::ArrayBase::from(vec![0.0]).into_dyn();
+    let mut changed_episode = EpisodeData::new(1, vec![action.clone()]);
+    changed_episode.env_params = Some(wide_config);
+    replay_episode(&mut env, &run_config, &changed_episode);
+    assert_eq!(env.sim.config.max_width, 30.0);
+    replay_episode(&mut env, &run_config, &EpisodeData::new(2, vec![action]));
+    assert_eq!(env.get_config(), run_config);
+  }
+}
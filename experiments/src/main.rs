@@ -20,9 +20,28 @@ fn main() {
   ).arg(
     Arg::new("experiment_name").short('e').long("experiment").help("Specifiy the name of the experiment that should be run"))
   .arg(
-    Arg::new("folder").short('r').long("results").help("Specifiy the folder in which to save the result CSV files. Default: Current working directory"));
+    Arg::new("folder").short('r').long("results").help("Specifiy the folder in which to save the result CSV files. Default: Current working directory"))
+  .arg(
+    Arg::new("descriptors").long("descriptors").help("Comma separated name:min:max:bins behaviour descriptors of linerider_map_elites"))
+  .arg(
+    Arg::new("iterations").long("iterations").value_parser(clap::value_parser!(usize)).help("Mutations tried by linerider_map_elites. Default: 1000"));
+  #[cfg(feature = "lrpcg")]
+  {
+    cli_app = cli_app.subcommand(
+      Command::new("verify").about("Replays every episode of a .tlr or .tlrx run and reports episodes whose rewards diverge from the recorded ones")
+      .arg(Arg::new("run").required(true).help("Path to the run file"))
+    );
+  }
   let matches = cli_app.clone().get_matches();
   
+  #[cfg(feature = "lrpcg")]
+  if let Some(verify_matches) = matches.subcommand_matches("verify") {
+    let run_path = verify_matches.get_one::<String>("run").unwrap();
+    if !crate::experiments::linerider::verify::verify_and_report(run_path) {
+      std::process::exit(1);
+    }
+    return;
+  }
   if let Some(merge_path) = matches.get_one::<String>("csv_dir") {
     println!("About to merge CSV result files in directory: {}", merge_path);
     merge_csv_files_to(merge_path, &format!("{}/merged.csv", merge_path))
@@ -54,6 +73,20 @@ fn main() {
       "linerider_extract" => {
         crate::experiments::linerider::post_analysis::extract_linerider_successes(&matches.get_one::<String>("folder"));
       },
+      #[cfg(feature = "lrpcg")]
+      "linerider_map_elites" => {
+        let descriptors = matches.get_one::<String>("descriptors").map(|d| d.as_str()).unwrap_or(crate::experiments::linerider::map_elites::DEFAULT_DESCRIPTORS);
+        let iterations = matches.get_one::<usize>("iterations").cloned().unwrap_or(1000);
+        crate::experiments::linerider::map_elites::run_map_elites(&matches.get_one::<String>("folder"), descriptors, iterations);
+      },
+      #[cfg(feature = "lrpcg")]
+      "linerider_evolution" => {
+        crate::experiments::linerider::evolution::run_evolution(&matches.get_one::<String>("folder"));
+      },
+      #[cfg(feature = "lrpcg")]
+      "linerider_beam_search" => {
+        crate::experiments::linerider::beam_search_exp::run_beam_search_demonstrations(&matches.get_one::<String>("folder"));
+      },
       _ => {println!("Experiment \"{}\" is not known to me. It might be disabled due to a feature flag.", experiment_name)}
     }
   } else {
@@ -8,6 +8,14 @@ use std::collections::HashMap;
  **/
  pub trait RlAlgorithm {
   fn act(&mut self, obs: Observation) -> Action;
+  /** Like act, for algorithms that need more of the environment than the observation, e.g. heuristics on the expert state */
+  fn act_in_env(&mut self, obs: Observation, _env: &mut dyn ReplayableGymEnvironment) -> Action {
+    self.act(obs)
+  }
+  /** Called after the last step of an episode, e.g. to restart an open-loop action sequence */
+  fn end_episode(&mut self) {}
+  /** Seed every evaluation episode is started with, e.g. the instance an action sequence was optimised for */
+  fn evaluation_seed(&self) -> Option<u64> {None}
   fn set_observation_shape(&mut self, space: Space);
   fn save(&mut self, save_path: &str);
   fn load(&mut self, load_path: &str);
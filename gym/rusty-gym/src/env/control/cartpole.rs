@@ -1,5 +1,5 @@
 use crate::{
-  gym::{GymEnvironment, Step, Observation, Action},
+  gym::{GymEnvironment, Step, StepInfo, Observation, Action},
   space::Space,
   util::rng::{UniRng, RngType, get_rng_for_type, uni_gen_range_f64}
 };
@@ -132,7 +132,9 @@ impl GymEnvironment for CartpoleEnv {
     self.step += 1;
     let cart_is_outside_of_bounds = self.x < - self.x_threshold || self.x > self.x_threshold;
     let pole_is_tipped_over = self.theta < -self.theta_threshold_radians || self.theta > self.theta_threshold_radians;
-    let is_done = self.step >= self.step_limit || cart_is_outside_of_bounds || pole_is_tipped_over;
+    let is_terminated = cart_is_outside_of_bounds || pole_is_tipped_over;
+    let is_truncated = !is_terminated && self.step >= self.step_limit;
+    let is_done = is_terminated || is_truncated;
     // println!("Episode is done because limit {}, outside bounds {} tipped over {}", self.step >= self.step_limit, cart_is_outside_of_bounds, pole_is_tipped_over);
     let reward = if !is_done {1.0} else if !self.was_done {self.was_done = true; 1.0} else {0.0};
 
@@ -140,7 +142,9 @@ impl GymEnvironment for CartpoleEnv {
       obs: self.make_obs(),
       reward,
       is_done,
-      action: action.clone()
+      is_truncated,
+      action: action.clone(),
+      info: StepInfo::new()
     }
   }
 
@@ -426,4 +430,26 @@ pub mod test {
 
     Ok(())
   }
+
+  #[test]
+  fn truncation_is_distinguished_from_termination() {
+    let push_right = ArrayBase::from(vec![1.0]).into_dyn();
+    let mut env = CartpoleEnv::new(Some(42), None);
+    env.reset();
+    let mut step = env.step(&push_right);
+    while !step.is_done {
+      step = env.step(&push_right);
+    }
+    assert!(step.is_terminated());
+    assert!(!step.is_truncated);
+
+    env.step_limit = 3;
+    env.reset();
+    for _ in 0..2 {
+      assert!(!env.step(&push_right).is_done);
+    }
+    let step = env.step(&push_right);
+    assert!(step.is_done && step.is_truncated);
+    assert!(!step.is_terminated());
+  }
 }
\ No newline at end of file
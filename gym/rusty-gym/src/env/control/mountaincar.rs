@@ -1,5 +1,5 @@
 use crate::{
-  gym::{GymEnvironment, Step, Observation, Action},
+  gym::{GymEnvironment, Step, StepInfo, Observation, Action},
   space::Space,
   util::rng::{UniRng, get_rng_for_type, uni_gen_range_f64}
 };
@@ -105,7 +105,9 @@ impl GymEnvironment for MountainCar {
     }
 
     self.step += 1;
-    let is_done = self.step >= self.max_steps || (self.position >= self.goal_position && self.velocity >= self.goal_velocity);
+    let is_terminated = self.position >= self.goal_position && self.velocity >= self.goal_velocity;
+    let is_truncated = !is_terminated && self.step >= self.max_steps;
+    let is_done = is_terminated || is_truncated;
     // println!("Episode is done because limit {}, outside bounds {} tipped over {}", self.step >= self.step_limit, cart_is_outside_of_bounds, pole_is_tipped_over);
     let reward = -1.0;
 
@@ -113,7 +115,9 @@ impl GymEnvironment for MountainCar {
       obs: self.make_obs(),
       reward,
       is_done,
-      action: action.clone()
+      is_truncated,
+      action: action.clone(),
+      info: StepInfo::new()
     }
   }
 
@@ -1,5 +1,5 @@
 use crate::{
-  gym::{GymEnvironment, Step, Observation, Action},
+  gym::{GymEnvironment, Step, StepInfo, Observation, Action},
   space::Space,
   util::rng::{UniRng, RngType, get_rng_for_type, uni_gen_range_f64}
 };
@@ -108,7 +108,9 @@ impl GymEnvironment for PendulumEnv {
       obs: self.make_obs(),
       reward,
       is_done,
-      action: action.clone()
+      is_truncated: is_done,
+      action: action.clone(),
+      info: StepInfo::new()
     }
   }
 
@@ -1,6 +1,10 @@
 pub mod util;
 pub mod rust_to_python;
 pub use rust_to_python::*;
+#[cfg(feature = "replay")]
+pub mod vec_rust_to_python;
+#[cfg(feature = "replay")]
+pub use vec_rust_to_python::*;
 
 pub mod python_to_rust;
 pub use python_to_rust::*;
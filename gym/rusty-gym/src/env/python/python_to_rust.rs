@@ -1,9 +1,10 @@
-use crate::{GymEnvironment, Space, Action, Observation, Step};
-use pyo3::{prelude::*, types::{IntoPyDict, PyTuple, PyFloat}};
+use crate::{GymEnvironment, Space, Action, Observation, Step, StepInfo};
+use pyo3::{prelude::*, types::{IntoPyDict, PyTuple, PyFloat, PyDict}};
 use ndarray::ArrayBase;
 use xp_tools::rng::from_seed;
 use rand::RngCore;
 use rand_pcg::Pcg64Mcg;
+use super::util::py_dict_to_step_info;
 
 #[derive(Debug)]
 pub enum ActionConversion {
@@ -128,13 +129,26 @@ impl GymEnvironment for PythonToRustGym {
       let py_obs = py.eval("step_res[0].tolist()", Some(obs_dict), None)?;
       let obs: Vec<f64> = py_obs.extract()?;
       let reward: f64 = py_step_tuple.get_item(1)?.extract()?;
-      let is_done: bool = py_step_tuple.get_item(2)?.extract()?;
+      // gymnasium returns (obs, reward, terminated, truncated, info), gym < 0.26 (obs, reward, done, info)
+      let (is_done, is_truncated) = if py_step_tuple.len() >= 5 {
+        let terminated: bool = py_step_tuple.get_item(2)?.extract()?;
+        let truncated: bool = py_step_tuple.get_item(3)?.extract()?;
+        (terminated || truncated, truncated)
+      } else {
+        (py_step_tuple.get_item(2)?.extract()?, false)
+      };
+      let info = match py_step_tuple.get_item(py_step_tuple.len() - 1)?.downcast::<PyDict>() {
+        Ok(info_dict) => {py_dict_to_step_info(info_dict)?},
+        Err(_) => {StepInfo::new()}
+      };
 
       Ok(Step {
         obs: ArrayBase::from(obs).into_dyn(),
         reward,
         is_done,
-        action: action.clone()
+        is_truncated,
+        action: action.clone(),
+        info
       })
     });
     res.expect("Can run env.step() and extract the python values to Rust")
@@ -51,7 +51,7 @@ pub struct DummyPos {
 #[pymethods]
 impl DummyPos {
   #[new]
-  #[args(level = "0", room = "0", score = "0", x = "0", y = "0")]
+  #[pyo3(signature = (level=0, room=0, score=0, x=0, y=0))]
   fn new(_py: Python, level: i64, room: i64, score: i64, x: i64, y: i64) -> Self {
     DummyPos {level, room, score, x, y }
   }
@@ -65,9 +65,8 @@ impl DummyPos {
     // let item_arg = str_arg.extract()?;
     Ok(self.x.into_py(py))
   }
-  pub fn __setitem__(&self, py: Python, _str_arg: &PyAny) -> PyResult<PyObject> {
-    // let item_arg = str_arg.extract()?;
-    Ok(self.x.into_py(py))
+  pub fn __setitem__(&self, _py: Python, _str_arg: &PyAny, _value: &PyAny) -> PyResult<()> {
+    Ok(())
   }
   // pub fn __hash__(&self) -> PyResult<i64> {
   //   let mut s = DefaultHasher::new();
@@ -96,8 +95,7 @@ pub fn rust_space_to_gym_space(py: Python, space: Space) -> PyResult<&PyAny> {
       let discrete_space = spaces_module.getattr("Discrete")?;
       discrete_space.call(PyTuple::new(py, &[size]), None)
     },
-    Space::BoxedWithRange(ranged_shape) => {
-      let shape: Vec<i64> = ranged_shape.iter().map(|a|a.0).collect();
+    Space::BoxedWithRange(shape, _, _) | Space::BoxedWithoutRange(shape) => {
       let box_space = spaces_module.getattr("Box")?;
       let box_kwargs = [("shape", shape),].into_py_dict(py);
       box_space.call(PyTuple::new(py, &[-1, 1]), Some(box_kwargs))
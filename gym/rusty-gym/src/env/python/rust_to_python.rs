@@ -1,5 +1,6 @@
 use pyo3::prelude::*;
-use pyo3::types::{PyTuple, PyList, IntoPyDict, PyDict};
+use pyo3::exceptions::PyValueError;
+use pyo3::types::{PyTuple, PyList, PyDict};
 use crate::{Space, ReplayableGymEnvironment, GymRecorder};
 use super::util::*;
 use ndarray::ArrayBase;
@@ -40,8 +41,24 @@ pub struct RustToPyGym {
 
 #[pymethods]
 impl RustToPyGym {
-  pub fn reset(&mut self, py: Python, seed: Option<i64>, additional_options: Option<HashMap<String, String>>) -> PyResult<PyObject> {
+  /**
+   * options are applied as a partial config before resetting, which allows e.g. a curriculum over max_width.
+   * The seed that was used for the episode is returned in the info dict so that it can be replayed.
+   */
+  #[pyo3(signature = (seed=None, options=None))]
+  pub fn reset(&mut self, py: Python, seed: Option<i64>, options: Option<&PyDict>) -> PyResult<PyObject> {
     let start = Instant::now();
+    if let Some(options) = options {
+      let config = py_dict_to_config(py, options)?;
+      self.rust_env.update_config(&config).map_err(PyValueError::new_err)?;
+      self.obs_shape = get_obs_shape(self.rust_env.observation_space());
+      self.action_space = rust_space_to_gym_space(py, self.rust_env.action_space())?.to_object(py);
+      self.observation_space = rust_space_to_gym_space(py, self.rust_env.observation_space())?.to_object(py);
+    }
+    if let Some(seed) = seed {
+      let seed = u64::try_from(seed).map_err(|_| PyValueError::new_err(format!("seed has to be positive, got {}", seed)))?;
+      self.rust_env.use_seed(seed);
+    }
     let reset_result = self.rust_env.reset();
     let observation = match &self.obs_shape {
       Some(shape) => {
@@ -54,7 +71,9 @@ impl RustToPyGym {
       None => {PyList::new(py, reset_result.into_raw_vec()).as_ref()}
     };
     let res = observation.to_object(py);
-    let info = PyDict::new(py).into();
+    let info_dict = PyDict::new(py);
+    info_dict.set_item("seed", self.rust_env.get_used_seed())?;
+    let info = info_dict.into();
     self.time_in_env += start.elapsed().as_nanos();
     let result_tuple = PyTuple::new(py, vec![res, info]);
     let obj: PyObject = result_tuple.to_object(py);
@@ -80,6 +99,7 @@ impl RustToPyGym {
       }
     };
     let step_res = self.rust_env.step(&action);
+    let is_terminated = step_res.is_terminated();
     let observation = match &self.obs_shape {
       Some(shape) => {
         let numpy_module = py.import("numpy")?;
@@ -91,12 +111,12 @@ impl RustToPyGym {
       None => {PyList::new(py, step_res.obs.into_raw_vec()).as_ref()}
     };
     let reward_tuple = PyTuple::new(py, &[step_res.reward]);
-    let done_tuple = PyTuple::new(py, &[step_res.is_done]);
+    let done_tuple = PyTuple::new(py, &[is_terminated]);
     let reward = reward_tuple.get_item(0).unwrap();
     let done = done_tuple.get_item(0).unwrap();
-    let truncated_tuple = PyTuple::new(py, &[step_res.is_done]);
+    let truncated_tuple = PyTuple::new(py, &[step_res.is_truncated]);
     let truncated = truncated_tuple.get_item(0).unwrap();
-    let additional_info: &PyDict = [("empty", "info")].into_py_dict(py);
+    let additional_info: &PyDict = step_info_to_py_dict(py, &step_res.info)?;
     let result = PyTuple::new(py, &[observation, reward, done, truncated, additional_info.as_ref()]);
     let res = PyResult::Ok(result.to_object(py));
     self.time_in_env += start.elapsed().as_nanos();
@@ -111,6 +131,14 @@ impl RustToPyGym {
   pub fn get_used_seed(&mut self) -> u64 {
     self.rust_env.get_used_seed()
   }
+  /** Valid discrete actions as expected by MaskablePPO of sb3-contrib */
+  pub fn action_masks(&mut self) -> PyResult<Vec<bool>> {
+    self.rust_env.action_masks().ok_or_else(|| PyValueError::new_err(format!("{} does not support action masks", self.rust_env.get_name())))
+  }
+  /** Action of the privileged expert of the environment, e.g. as label for imitation learning */
+  pub fn expert_action(&mut self) -> PyResult<Vec<f64>> {
+    self.rust_env.expert_action().map(|action| action.into_raw_vec()).ok_or_else(|| PyValueError::new_err(format!("{} does not provide an expert", self.rust_env.get_name())))
+  }
   pub fn finalize(&mut self, algo_name: &str, eval_run_id: &str) {
     self.rust_env.finalize(algo_name, eval_run_id);
   }
@@ -1,7 +1,8 @@
 use pyo3::prelude::*;
-use pyo3::types::{PyTuple, PyList, PyDict};
+use pyo3::types::{PyTuple, PyList, PyDict, PyBool, PyInt, PyFloat, PyString};
 
-use crate::{Space};
+use crate::{Space, StepInfo, InfoValue};
+use std::collections::HashMap;
 
 pub fn make_numpy_array(py: Python, to_convert: Vec<f64>) -> PyResult<&PyAny> {
   let numpy_module = py.import("numpy")?;
@@ -56,4 +57,56 @@ pub fn rust_space_to_gym_space(py: Python, space: Space) -> PyResult<&PyAny> {
       box_space.call(PyTuple::empty(py), Some(box_kwargs))
     }
   }
-}
\ No newline at end of file
+}
+
+pub fn step_info_to_py_dict<'py>(py: Python<'py>, info: &StepInfo) -> PyResult<&'py PyDict> {
+  let dict = PyDict::new(py);
+  for (key, value) in info.iter() {
+    let py_value: PyObject = match value {
+      InfoValue::Bool(b) => {b.to_object(py)},
+      InfoValue::Int(i) => {i.to_object(py)},
+      InfoValue::Float(f) => {f.to_object(py)},
+      InfoValue::Text(t) => {t.to_object(py)},
+      InfoValue::Map(m) => {step_info_to_py_dict(py, m)?.to_object(py)}
+    };
+    dict.set_item(key, py_value)?;
+  }
+  Ok(dict)
+}
+
+/** Values that have no counterpart in InfoValue are stored as their python string representation */
+pub fn py_dict_to_step_info(dict: &PyDict) -> PyResult<StepInfo> {
+  let mut info = StepInfo::new();
+  for (key, value) in dict.iter() {
+    let info_value = if value.is_instance_of::<PyBool>() {
+      InfoValue::Bool(value.extract()?)
+    } else if value.is_instance_of::<PyInt>() {
+      InfoValue::Int(value.extract()?)
+    } else if value.is_instance_of::<PyFloat>() {
+      InfoValue::Float(value.extract()?)
+    } else if value.is_instance_of::<PyString>() {
+      InfoValue::Text(value.extract()?)
+    } else if let Ok(nested) = value.downcast::<PyDict>() {
+      InfoValue::Map(py_dict_to_step_info(nested)?)
+    } else {
+      InfoValue::Text(value.str()?.to_string())
+    };
+    info.insert(key.str()?.to_string(), info_value);
+  }
+  Ok(info)
+}
+
+/** Strings are taken as they are, everything else is JSON encoded so that e.g. lists and booleans parse on the rust side */
+pub fn py_dict_to_config(py: Python, dict: &PyDict) -> PyResult<HashMap<String, String>> {
+  let dumps = py.import("json")?.getattr("dumps")?;
+  let mut config = HashMap::new();
+  for (key, value) in dict.iter() {
+    let config_value: String = if value.is_instance_of::<PyString>() {
+      value.extract()?
+    } else {
+      dumps.call1((value,))?.extract()?
+    };
+    config.insert(key.str()?.to_string(), config_value);
+  }
+  Ok(config)
+}
@@ -0,0 +1,186 @@
+use pyo3::prelude::*;
+use pyo3::exceptions::{PyValueError, PyRuntimeError};
+use pyo3::types::{PyTuple, PyList, PyDict};
+use crate::{Space, Action, Observation, VecGymEnvironment, VecEnvironmentMaker, VecStep};
+use super::util::*;
+use ndarray::ArrayBase;
+use std::collections::HashMap;
+use std::time::Instant;
+
+/**
+ * Follows the VecEnv interface of stable-baselines3
+ * reset
+ * step_async
+ * step_wait
+ * step
+ * seed
+ * close
+ * get_attr
+ * env_is_wrapped
+ * action_masks
+ *
+ * observation_space and action_space describe a single environment,
+ * observations, rewards and dones are returned stacked along the first axis.
+ **/
+#[pyclass]
+pub struct RustToPyVecGym {
+  #[pyo3(get)]
+  metadata: PyObject,
+  #[pyo3(get)]
+  action_space: PyObject,
+  #[pyo3(get)]
+  observation_space: PyObject,
+  #[pyo3(get)]
+  reward_range: PyObject,
+  #[pyo3(get)]
+  num_envs: usize,
+  #[pyo3(get)]
+  time_in_env: u128,
+  vec_env: VecGymEnvironment,
+  obs_shape: Option<Vec<i64>>,
+  pending_actions: Option<Vec<Action>>
+}
+
+#[pymethods]
+impl RustToPyVecGym {
+  pub fn reset(&mut self, py: Python, seed: Option<u64>) -> PyResult<PyObject> {
+    let start = Instant::now();
+    if let Some(actual_seed) = seed {
+      self.vec_env.use_seed(actual_seed).map_err(PyRuntimeError::new_err)?;
+    }
+    let vec_env = &mut self.vec_env;
+    let observations = py.allow_threads(|| vec_env.reset()).map_err(PyRuntimeError::new_err)?;
+    let res = self.stack_observations(py, &observations)?;
+    self.time_in_env += start.elapsed().as_nanos();
+    PyResult::Ok(res)
+  }
+  pub fn step_async(&mut self, actions: &PyAny) -> PyResult<()> {
+    self.pending_actions = Some(self.extract_actions(actions)?);
+    Ok(())
+  }
+  pub fn step_wait(&mut self, py: Python) -> PyResult<PyObject> {
+    let start = Instant::now();
+    let actions = self.pending_actions.take().ok_or_else(|| PyRuntimeError::new_err("step_async has to be called before step_wait"))?;
+    let vec_env = &mut self.vec_env;
+    let vec_step: VecStep = py.allow_threads(|| vec_env.step(&actions)).map_err(PyRuntimeError::new_err)?;
+    let observations = self.stack_observations(py, &vec_step.obs)?;
+    let rewards = make_numpy_array(py, vec_step.rewards)?;
+    let numpy_module = py.import("numpy")?;
+    let dones = numpy_module.getattr("array")?.call1((PyList::new(py, &vec_step.dones),))?;
+    let infos = PyList::empty(py);
+    for ((terminal_obs_opt, step_info), truncated) in vec_step.terminal_obs.into_iter().zip(vec_step.infos.iter()).zip(vec_step.truncateds.iter()) {
+      let info = step_info_to_py_dict(py, step_info)?;
+      if let Some(terminal_obs) = terminal_obs_opt {
+        info.set_item("terminal_observation", self.single_observation(py, terminal_obs)?)?;
+        info.set_item("TimeLimit.truncated", *truncated)?;
+      }
+      infos.append(info)?;
+    }
+    let result = PyTuple::new(py, &[observations, rewards.to_object(py), dones.to_object(py), infos.to_object(py)]);
+    self.time_in_env += start.elapsed().as_nanos();
+    PyResult::Ok(result.to_object(py))
+  }
+  pub fn step(&mut self, py: Python, actions: &PyAny) -> PyResult<PyObject> {
+    self.step_async(actions)?;
+    self.step_wait(py)
+  }
+  pub fn seed(&mut self, seed: Option<u64>) -> PyResult<Vec<u64>> {
+    match seed {
+      Some(actual_seed) => {self.vec_env.use_seed(actual_seed).map_err(PyRuntimeError::new_err)},
+      None => {Ok(vec![])}
+    }
+  }
+  pub fn close(&mut self) {
+    self.vec_env.close();
+  }
+  pub fn get_attr(&mut self, attr_name: &str, indices: Option<Vec<usize>>) -> PyResult<Vec<Option<String>>> {
+    let configs = self.vec_env.get_config().map_err(PyRuntimeError::new_err)?;
+    let indices = indices.unwrap_or((0..self.num_envs).collect());
+    Ok(indices.iter().map(|i| configs[*i].get(attr_name).cloned()).collect())
+  }
+  pub fn env_is_wrapped(&self, _wrapper_class: &PyAny, indices: Option<Vec<usize>>) -> Vec<bool> {
+    let amount = indices.map(|i| i.len()).unwrap_or(self.num_envs);
+    vec![false; amount]
+  }
+  pub fn get_config(&mut self) -> PyResult<Vec<HashMap<String, String>>> {
+    self.vec_env.get_config().map_err(PyRuntimeError::new_err)
+  }
+  /** Valid discrete actions of every environment stacked along the first axis, as expected by MaskablePPO of sb3-contrib */
+  pub fn action_masks(&mut self, py: Python) -> PyResult<PyObject> {
+    let masks: Option<Vec<Vec<bool>>> = self.vec_env.action_masks().map_err(PyRuntimeError::new_err)?.into_iter().collect();
+    let masks = masks.ok_or_else(|| PyValueError::new_err("Environments do not support action masks"))?;
+    let numpy_module = py.import("numpy")?;
+    let stacked = numpy_module.getattr("array")?.call1((PyList::new(py, masks.iter().map(|mask| PyList::new(py, mask))),))?;
+    PyResult::Ok(stacked.to_object(py))
+  }
+  pub fn load_config(&mut self, config: HashMap<String, String>) -> PyResult<()> {
+    self.vec_env.load_config(&config).map_err(PyRuntimeError::new_err)
+  }
+  pub fn finalize(&mut self, algo_name: &str, eval_run_id: &str) -> PyResult<()> {
+    self.vec_env.finalize(algo_name, eval_run_id).map_err(PyRuntimeError::new_err)
+  }
+}
+
+impl RustToPyVecGym {
+  /** Raises a ValueError if the environments could not be created, e.g. because of an invalid config */
+  pub fn new(py: Python, num_envs: usize, num_threads: Option<usize>, maker: VecEnvironmentMaker) -> PyResult<Self> {
+    let start = Instant::now();
+    let vec_env = py.allow_threads(|| VecGymEnvironment::new(num_envs, num_threads, maker)).map_err(PyValueError::new_err)?;
+    let action_space = rust_space_to_gym_space(py, vec_env.action_space()).unwrap();
+    let action_space = action_space.to_object(py);
+    let observation_space = rust_space_to_gym_space(py, vec_env.observation_space()).unwrap();
+    let observation_space = observation_space.to_object(py);
+    //TODO: make the reward range defineable
+    let reward_range = PyList::new(py, [-2, 2]).to_object(py);
+    let metadata = PyDict::new(py).to_object(py);
+    let obs_shape: Option<Vec<i64>> = get_obs_shape(vec_env.observation_space());
+    let mut res = RustToPyVecGym {
+      action_space, observation_space, obs_shape, reward_range, metadata,
+      num_envs: vec_env.num_envs(), vec_env, time_in_env: 0, pending_actions: None
+    };
+    res.time_in_env = start.elapsed().as_nanos();
+    Ok(res)
+  }
+
+  fn extract_actions(&self, actions: &PyAny) -> PyResult<Vec<Action>> {
+    let actions = if actions.hasattr("tolist")? {actions.call_method0("tolist")?} else {actions};
+    let converted: Vec<Action> = match self.vec_env.action_space() {
+      Space::Discrete(_) => {
+        let flat_actions_opt: PyResult<Vec<f64>> = actions.extract();
+        if let Ok(flat_actions) = flat_actions_opt {
+          flat_actions.into_iter().map(|a| ArrayBase::from(vec![a]).into_dyn()).collect()
+        } else {
+          let nested_actions: Vec<Vec<f64>> = actions.extract()?;
+          nested_actions.into_iter().map(|a| ArrayBase::from(a).into_dyn()).collect()
+        }
+      }
+      _ => {
+        let nested_actions: Vec<Vec<f64>> = actions.extract()?;
+        nested_actions.into_iter().map(|a| ArrayBase::from(a).into_dyn()).collect()
+      }
+    };
+    if converted.len() != self.num_envs {
+      return Err(pyo3::exceptions::PyValueError::new_err(format!("Expected {} actions but got {}", self.num_envs, converted.len())));
+    }
+    Ok(converted)
+  }
+
+  fn stack_observations(&self, py: Python, observations: &[Observation]) -> PyResult<PyObject> {
+    let single_size = observations.first().map(|o| o.len()).unwrap_or(0);
+    let mut shape: Vec<i64> = vec![observations.len() as i64];
+    match &self.obs_shape {
+      Some(obs_shape) => {shape.extend(obs_shape.iter())},
+      None => {shape.push(single_size as i64)}
+    };
+    let mut flat: Vec<f64> = Vec::with_capacity(observations.len() * single_size);
+    for obs in observations.iter() {
+      flat.extend(obs.iter());
+    }
+    Ok(reshape_numpy_array(py, flat, shape)?.to_object(py))
+  }
+
+  fn single_observation(&self, py: Python, observation: Observation) -> PyResult<PyObject> {
+    let shape = self.obs_shape.clone().unwrap_or(vec![observation.len() as i64]);
+    Ok(reshape_numpy_array(py, observation.into_raw_vec(), shape)?.to_object(py))
+  }
+}
@@ -1,5 +1,5 @@
 use crate::space::Space;
-use crate::gym::{GymEnvironment, Step, Observation, Action};
+use crate::gym::{GymEnvironment, Step, StepInfo, Observation, Action};
 use ndarray::ArrayBase;
 
 #[derive(Debug, Clone)]
@@ -49,7 +49,9 @@ impl GymEnvironment for EnvZeroOrOne {
       obs,
       reward: if (action_value - expectation).abs() < f64::EPSILON {1.0} else {-1.0},
       is_done: self.tick > 10,
-      action: action.clone()
+      is_truncated: self.tick > 10,
+      action: action.clone(),
+      info: StepInfo::new()
     }
   }
 
@@ -333,15 +333,23 @@ impl RlExperimentHelper {
       let boxed_env: Box<dyn ReplayableGymEnvironment> = env_maker(&env_config);
       let mut recording_env = GymRecorder::new(boxed_env, None);
       let episode_data = recording_env.data.clone();
+      let evaluation_seed = algo.evaluation_seed();
+      if let Some(seed) = evaluation_seed {
+        recording_env.use_seed(seed);
+      }
       let mut obs = recording_env.reset();
       let mut current_episode = 0;
       let start = Instant::now();
       algo.set_observation_shape(recording_env.observation_space());
       while current_episode < eval_episode_amount {
-        let action = algo.act(obs);
+        let action = algo.act_in_env(obs, &mut recording_env);
         let step = recording_env.step(&action);
         obs = if step.is_done {
           current_episode += 1;
+          algo.end_episode();
+          if let Some(seed) = evaluation_seed {
+            recording_env.use_seed(seed);
+          }
           recording_env.reset()
         } else {
           step.obs
@@ -997,6 +1005,7 @@ pub fn get_ipfs_url() -> String {
 #[cfg(test)]
 pub mod test {
   use super::{RlExperimentHelper, prepare_analyzer_data, enrich_episode, EnrichedEpisodeData, avg, EnvironmentMaker};
+  use super::ReplayableGymEnvironment;
   use std::fs::metadata;
   use compressed_vec::CompressedVec;
   use rand::{Rng, RngCore};
@@ -0,0 +1,468 @@
+use crate::{gym::{Action, Observation, Reward, Space}, algo::{RlAlgorithm, SelfTrainingAlgo}, replay::ReplayableGymEnvironment};
+use rand::{Rng, RngCore};
+use rand_pcg::Pcg64Mcg;
+use serde::{Serialize, Deserialize};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::str::FromStr;
+use ndarray::ArrayBase;
+use xp_tools::rng::from_seed;
+
+/** How new candidate action sequences are created */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvolutionMethod {
+  /** CMA-ES with a diagonal covariance (sep-CMA-ES), which scales to long action sequences */
+  CmaEs,
+  /** Tournament selection, uniform crossover and gaussian mutation with elitism */
+  Genetic
+}
+
+/** What the action sequence is optimised for */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EvolutionTarget {
+  /** One sequence for the instance of a single seed */
+  Instance,
+  /** One open-loop sequence that does well on average over freshly drawn seeds */
+  OpenLoop
+}
+
+impl FromStr for EvolutionMethod {
+  type Err = String;
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name {
+      "cma_es" => Ok(EvolutionMethod::CmaEs),
+      "ga" => Ok(EvolutionMethod::Genetic),
+      _ => Err(format!("unknown evolution method '{}', expected cma_es or ga", name))
+    }
+  }
+}
+
+impl FromStr for EvolutionTarget {
+  type Err = String;
+  fn from_str(name: &str) -> Result<Self, Self::Err> {
+    match name {
+      "instance" => Ok(EvolutionTarget::Instance),
+      "open_loop" => Ok(EvolutionTarget::OpenLoop),
+      _ => Err(format!("unknown evolution target '{}', expected instance or open_loop", name))
+    }
+  }
+}
+
+impl EvolutionMethod {
+  pub fn name(&self) -> &'static str {
+    match self {
+      EvolutionMethod::CmaEs => "cma_es",
+      EvolutionMethod::Genetic => "ga"
+    }
+  }
+}
+
+impl EvolutionTarget {
+  pub fn name(&self) -> &'static str {
+    match self {
+      EvolutionTarget::Instance => "instance",
+      EvolutionTarget::OpenLoop => "open_loop"
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EvolutionHyperparams {
+  pub method: EvolutionMethod,
+  pub target: EvolutionTarget,
+  /** Actions in the sequence, episodes that take longer start over at the first action */
+  pub sequence_length: usize,
+  /** Candidates per generation, 0 uses 4 + 3 ln(genes) as recommended for CMA-ES */
+  pub population_size: usize,
+  /** Seeds every candidate is evaluated on with EvolutionTarget::OpenLoop */
+  pub seeds_per_evaluation: usize,
+  /** Initial step size of CMA-ES and mutation strength of the genetic algorithm, relative to the width of the action space */
+  pub sigma: f64,
+  pub mutation_rate: f64,
+  /** Best candidates the genetic algorithm keeps unchanged */
+  pub elite_count: usize,
+  /** Cuts off episodes that would not end on their own */
+  pub max_episode_steps: usize
+}
+
+impl Default for EvolutionHyperparams {
+  fn default() -> Self {
+    EvolutionHyperparams {
+      method: EvolutionMethod::CmaEs,
+      target: EvolutionTarget::Instance,
+      sequence_length: 10,
+      population_size: 0,
+      seeds_per_evaluation: 3,
+      sigma: 0.3,
+      mutation_rate: 0.1,
+      elite_count: 2,
+      max_episode_steps: 1000
+    }
+  }
+}
+
+/** Standard normal sample through the Box-Muller transform */
+fn sample_normal(rng: &mut Pcg64Mcg) -> f64 {
+  let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+  let u2: f64 = rng.gen();
+  (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+/**
+ * Optimises a fixed-length action sequence with CMA-ES or a genetic algorithm.
+ * Genes of bounded action spaces are searched in [0, 1] and scaled to the bounds, discrete actions are the floor of gene * amount of actions.
+ * act plays back the best sequence found, end_episode restarts it.
+ */
+pub struct EvolutionaryAlgorithm {
+  pub params: EvolutionHyperparams,
+  pub action_space: Space,
+  pub best_genome: Vec<f64>,
+  pub best_fitness: Reward,
+  /** Seed of the instance with EvolutionTarget::Instance */
+  pub instance_seed: Option<u64>,
+  current_step: usize
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedEvolution {
+  params: EvolutionHyperparams,
+  best_genome: Vec<f64>,
+  best_fitness: Reward,
+  instance_seed: Option<u64>
+}
+
+impl EvolutionaryAlgorithm {
+  pub fn new(action_space: Space, params: EvolutionHyperparams) -> EvolutionaryAlgorithm {
+    EvolutionaryAlgorithm {params, action_space, best_genome: Vec::new(), best_fitness: f64::NEG_INFINITY, instance_seed: None, current_step: 0}
+  }
+  fn action_dims(&self) -> usize {
+    match &self.action_space {
+      Space::Discrete(_) => 1,
+      Space::BoxedWithRange(shape, _, _) | Space::BoxedWithoutRange(shape) => shape.iter().product::<i64>() as usize
+    }
+  }
+  fn genome_length(&self) -> usize {
+    self.params.sequence_length * self.action_dims()
+  }
+  /** Center of the search space, genes of unbounded spaces are not normalised */
+  fn genome_center(&self) -> f64 {
+    if matches!(self.action_space, Space::BoxedWithoutRange(_)) {0.0} else {0.5}
+  }
+  pub fn decode_action(&self, genome: &[f64], step: usize) -> Action {
+    let dims = self.action_dims();
+    let start = (step % self.params.sequence_length) * dims;
+    let genes = &genome[start..start + dims];
+    let action: Vec<f64> = match &self.action_space {
+      Space::Discrete(actions) => {
+        vec![((genes[0].clamp(0.0, 1.0) * *actions as f64).floor() as i64).min(actions - 1) as f64]
+      },
+      Space::BoxedWithRange(_, low, high) => {
+        genes.iter().enumerate().map(|(i, gene)| low[i] + gene.clamp(0.0, 1.0) * (high[i] - low[i])).collect()
+      },
+      Space::BoxedWithoutRange(_) => genes.to_vec()
+    };
+    ArrayBase::from(action).into_dyn()
+  }
+  /** Sum of rewards of the genome, averaged over the seeds */
+  fn evaluate(&self, env: &mut Box<dyn ReplayableGymEnvironment>, genome: &[f64], seeds: &[u64], steps_used: &mut usize) -> Reward {
+    let mut total = 0.0;
+    for seed in seeds {
+      env.use_seed(*seed);
+      env.reset();
+      for step in 0..self.params.max_episode_steps {
+        let result = env.step(&self.decode_action(genome, step));
+        *steps_used += 1;
+        total += result.reward;
+        if result.is_done {
+          break;
+        }
+      }
+    }
+    total / seeds.len() as f64
+  }
+  fn evaluation_seeds(&self, rng: &mut Pcg64Mcg) -> Vec<u64> {
+    match self.params.target {
+      EvolutionTarget::Instance => vec![self.instance_seed.expect("instance seed is chosen before training")],
+      EvolutionTarget::OpenLoop => (0..self.params.seeds_per_evaluation.max(1)).map(|_| rng.next_u64()).collect()
+    }
+  }
+  fn remember_best(&mut self, population: &[Vec<f64>], fitness: &[Reward]) {
+    for (genome, value) in population.iter().zip(fitness.iter()) {
+      if *value > self.best_fitness {
+        self.best_fitness = *value;
+        self.best_genome = genome.clone();
+      }
+    }
+  }
+  fn train_cma_es(&mut self, env: &mut Box<dyn ReplayableGymEnvironment>, rng: &mut Pcg64Mcg, steps: usize, population_size: usize) {
+    let n = self.genome_length();
+    let n_f = n as f64;
+    let mu = population_size / 2;
+    let raw_weights: Vec<f64> = (0..mu).map(|i| (mu as f64 + 0.5).ln() - ((i + 1) as f64).ln()).collect();
+    let weight_sum: f64 = raw_weights.iter().sum();
+    let weights: Vec<f64> = raw_weights.iter().map(|w| w / weight_sum).collect();
+    let mu_eff = 1.0 / weights.iter().map(|w| w * w).sum::<f64>();
+    let c_sigma = (mu_eff + 2.0) / (n_f + mu_eff + 5.0);
+    let d_sigma = 1.0 + 2.0 * (((mu_eff - 1.0) / (n_f + 1.0)).sqrt() - 1.0).max(0.0) + c_sigma;
+    let c_c = (4.0 + mu_eff / n_f) / (n_f + 4.0 + 2.0 * mu_eff / n_f);
+    // the learning rates of the full covariance are raised by (n + 2) / 3 for a diagonal one
+    let c_1 = (2.0 / ((n_f + 1.3).powi(2) + mu_eff) * (n_f + 2.0) / 3.0).min(1.0);
+    let c_mu = (2.0 * (mu_eff - 2.0 + 1.0 / mu_eff) / ((n_f + 2.0).powi(2) + mu_eff) * (n_f + 2.0) / 3.0).min(1.0 - c_1);
+    let chi_n = n_f.sqrt() * (1.0 - 1.0 / (4.0 * n_f) + 1.0 / (21.0 * n_f * n_f));
+
+    let mut mean = vec![self.genome_center(); n];
+    let mut sigma = self.params.sigma;
+    let mut diagonal: Vec<f64> = vec![1.0; n];
+    let mut path_sigma = vec![0.0; n];
+    let mut path_c = vec![0.0; n];
+    let mut steps_used = 0;
+    let mut generation = 0;
+    while steps_used < steps {
+      let offsets: Vec<Vec<f64>> = (0..population_size).map(|_| {
+        (0..n).map(|i| diagonal[i].sqrt() * sample_normal(rng)).collect()
+      }).collect();
+      let population: Vec<Vec<f64>> = offsets.iter().map(|y| (0..n).map(|i| mean[i] + sigma * y[i]).collect()).collect();
+      let seeds = self.evaluation_seeds(rng);
+      let fitness: Vec<Reward> = population.iter().map(|genome| self.evaluate(env, genome, &seeds, &mut steps_used)).collect();
+      self.remember_best(&population, &fitness);
+      let mut ranking: Vec<usize> = (0..population_size).collect();
+      ranking.sort_by(|a, b| fitness[*b].total_cmp(&fitness[*a]));
+
+      let weighted_offset: Vec<f64> = (0..n).map(|i| {
+        ranking.iter().take(mu).zip(weights.iter()).map(|(index, w)| w * offsets[*index][i]).sum()
+      }).collect();
+      for i in 0..n {
+        mean[i] += sigma * weighted_offset[i];
+        path_sigma[i] = (1.0 - c_sigma) * path_sigma[i] + (c_sigma * (2.0 - c_sigma) * mu_eff).sqrt() * weighted_offset[i] / diagonal[i].sqrt();
+      }
+      generation += 1;
+      let path_sigma_norm = path_sigma.iter().map(|p| p * p).sum::<f64>().sqrt();
+      let keeps_direction = path_sigma_norm / (1.0 - (1.0 - c_sigma).powi(2 * generation)).sqrt() < (1.4 + 2.0 / (n_f + 1.0)) * chi_n;
+      let h_sigma = if keeps_direction {1.0} else {0.0};
+      for i in 0..n {
+        path_c[i] = (1.0 - c_c) * path_c[i] + h_sigma * (c_c * (2.0 - c_c) * mu_eff).sqrt() * weighted_offset[i];
+        let rank_mu: f64 = ranking.iter().take(mu).zip(weights.iter()).map(|(index, w)| w * offsets[*index][i].powi(2)).sum();
+        diagonal[i] = (1.0 - c_1 - c_mu) * diagonal[i]
+          + c_1 * (path_c[i].powi(2) + (1.0 - h_sigma) * c_c * (2.0 - c_c) * diagonal[i])
+          + c_mu * rank_mu;
+      }
+      sigma *= ((c_sigma / d_sigma) * (path_sigma_norm / chi_n - 1.0)).exp();
+    }
+  }
+  fn train_genetic(&mut self, env: &mut Box<dyn ReplayableGymEnvironment>, rng: &mut Pcg64Mcg, steps: usize, population_size: usize) {
+    let n = self.genome_length();
+    let center = self.genome_center();
+    let mut population: Vec<Vec<f64>> = (0..population_size).map(|_| {
+      (0..n).map(|_| center + rng.gen_range(-0.5..0.5)).collect()
+    }).collect();
+    let mut steps_used = 0;
+    while steps_used < steps {
+      let seeds = self.evaluation_seeds(rng);
+      let fitness: Vec<Reward> = population.iter().map(|genome| self.evaluate(env, genome, &seeds, &mut steps_used)).collect();
+      self.remember_best(&population, &fitness);
+      let mut ranking: Vec<usize> = (0..population_size).collect();
+      ranking.sort_by(|a, b| fitness[*b].total_cmp(&fitness[*a]));
+      let tournament = |rng: &mut Pcg64Mcg| -> usize {
+        (0..3).map(|_| rng.gen_range(0..population_size)).max_by(|a, b| fitness[*a].total_cmp(&fitness[*b])).expect("tournament has contestants")
+      };
+      let mut next_population: Vec<Vec<f64>> = ranking.iter().take(self.params.elite_count.min(population_size)).map(|i| population[*i].clone()).collect();
+      while next_population.len() < population_size {
+        let (first, second) = (tournament(rng), tournament(rng));
+        let child: Vec<f64> = (0..n).map(|i| {
+          let gene = if rng.gen_bool(0.5) {population[first][i]} else {population[second][i]};
+          if rng.gen_bool(self.params.mutation_rate) {gene + self.params.sigma * sample_normal(rng)} else {gene}
+        }).collect();
+        next_population.push(child);
+      }
+      population = next_population;
+    }
+  }
+}
+
+impl RlAlgorithm for EvolutionaryAlgorithm {
+  fn act(&mut self, _obs: Observation) -> Action {
+    if self.best_genome.is_empty() {
+      self.best_genome = vec![self.genome_center(); self.genome_length()];
+    }
+    let action = self.decode_action(&self.best_genome, self.current_step);
+    self.current_step += 1;
+    action
+  }
+  fn end_episode(&mut self) {
+    self.current_step = 0;
+  }
+  fn evaluation_seed(&self) -> Option<u64> {
+    match self.params.target {
+      EvolutionTarget::Instance => self.instance_seed,
+      EvolutionTarget::OpenLoop => None
+    }
+  }
+  fn set_observation_shape(&mut self, _space: Space) {}
+  fn save(&mut self, save_path: &str) {
+    let saved = SavedEvolution {params: self.params.clone(), best_genome: self.best_genome.clone(), best_fitness: self.best_fitness, instance_seed: self.instance_seed};
+    std::fs::write(save_path, serde_json::to_string(&saved).expect("evolution state is serializable")).expect("Able to write the evolution state");
+  }
+  fn load(&mut self, load_path: &str) {
+    let content = std::fs::read_to_string(load_path).expect("Able to read the evolution state");
+    let saved: SavedEvolution = serde_json::from_str(&content).expect("evolution state can be parsed");
+    self.params = saved.params;
+    self.best_genome = saved.best_genome;
+    self.best_fitness = saved.best_fitness;
+    self.instance_seed = saved.instance_seed;
+    self.current_step = 0;
+  }
+  fn get_hyperparams(&mut self) -> HashMap<String, String> {
+    let mut hyperparams = HashMap::new();
+    hyperparams.insert("name".to_owned(), self.get_name());
+    hyperparams.insert("method".to_owned(), self.params.method.name().to_owned());
+    hyperparams.insert("target".to_owned(), self.params.target.name().to_owned());
+    hyperparams.insert("sequence_length".to_owned(), self.params.sequence_length.to_string());
+    hyperparams.insert("population_size".to_owned(), self.params.population_size.to_string());
+    hyperparams.insert("seeds_per_evaluation".to_owned(), self.params.seeds_per_evaluation.to_string());
+    hyperparams.insert("sigma".to_owned(), self.params.sigma.to_string());
+    hyperparams.insert("mutation_rate".to_owned(), self.params.mutation_rate.to_string());
+    hyperparams.insert("elite_count".to_owned(), self.params.elite_count.to_string());
+    hyperparams.insert("max_episode_steps".to_owned(), self.params.max_episode_steps.to_string());
+    if let Some(instance_seed) = self.instance_seed {
+      hyperparams.insert("instance_seed".to_owned(), instance_seed.to_string());
+    }
+    hyperparams
+  }
+  fn load_hyperparams(&mut self, config: &HashMap<String, String>) {
+    let params = &mut self.params;
+    for (key, value) in config {
+      let parsed = match key.as_str() {
+        "method" => value.parse().map(|method| params.method = method),
+        "target" => value.parse().map(|target| params.target = target),
+        "sequence_length" => value.parse().map(|v| params.sequence_length = v).map_err(|e| format!("{}", e)),
+        "population_size" => value.parse().map(|v| params.population_size = v).map_err(|e| format!("{}", e)),
+        "seeds_per_evaluation" => value.parse().map(|v| params.seeds_per_evaluation = v).map_err(|e| format!("{}", e)),
+        "sigma" => value.parse().map(|v| params.sigma = v).map_err(|e| format!("{}", e)),
+        "mutation_rate" => value.parse().map(|v| params.mutation_rate = v).map_err(|e| format!("{}", e)),
+        "elite_count" => value.parse().map(|v| params.elite_count = v).map_err(|e| format!("{}", e)),
+        "max_episode_steps" => value.parse().map(|v| params.max_episode_steps = v).map_err(|e| format!("{}", e)),
+        "instance_seed" => value.parse().map(|v| self.instance_seed = Some(v)).map_err(|e| format!("{}", e)),
+        _ => Ok(())
+      };
+      if let Err(message) = parsed {
+        panic!("Invalid value '{}' for evolution hyperparameter '{}': {}", value, key, message);
+      }
+    }
+  }
+  fn get_name(&self) -> String {
+    format!("{}_{}", self.params.method.name(), self.params.target.name())
+  }
+  fn reset(&mut self) {
+    self.best_genome.clear();
+    self.best_fitness = f64::NEG_INFINITY;
+    self.instance_seed = None;
+    self.current_step = 0;
+  }
+}
+
+impl SelfTrainingAlgo for EvolutionaryAlgorithm {
+  /**
+   * steps is the budget of environment steps over all evaluations, seed fixes the instance of EvolutionTarget::Instance.
+   * Without a seed the instance_seed hyperparameter is used, a run after reset() draws a new instance.
+   */
+  fn train_on_env(&mut self, mut rust_env: Box<dyn ReplayableGymEnvironment>, seed: Option<u64>, steps: usize) {
+    let (mut rng, used_seed) = from_seed(seed.or(self.instance_seed));
+    self.action_space = rust_env.action_space();
+    self.reset();
+    self.instance_seed = Some(used_seed);
+    let genes = self.genome_length() as f64;
+    let population_size = if self.params.population_size > 0 {self.params.population_size} else {4 + (3.0 * genes.ln()).floor() as usize};
+    match self.params.method {
+      EvolutionMethod::CmaEs => self.train_cma_es(&mut rust_env, &mut rng, steps, population_size.max(2)),
+      EvolutionMethod::Genetic => self.train_genetic(&mut rust_env, &mut rng, steps, population_size.max(2))
+    }
+  }
+}
+
+#[cfg(test)]
+pub mod test {
+  use super::*;
+  use crate::gym::{GymEnvironment, Step, StepInfo};
+  use std::collections::HashSet;
+
+  /** Rewards every step by how close the action is to a target that changes with the step, ends after as many steps as there are targets */
+  struct SequenceEnv {targets: Vec<f64>, step: usize}
+
+  impl GymEnvironment for SequenceEnv {
+    fn action_space(&self) -> Space {Space::BoxedWithRange(vec![1], vec![-1.0], vec![1.0])}
+    fn observation_space(&self) -> Space {Space::BoxedWithoutRange(vec![1])}
+    fn step(&mut self, action: &Action) -> Step {
+      let reward = -(action[0] - self.targets[self.step]).abs();
+      self.step += 1;
+      let obs = ArrayBase::from(vec![self.step as f64]).into_dyn();
+      Step {obs, reward, is_done: self.step == self.targets.len(), is_truncated: false, action: action.clone(), info: StepInfo::new()}
+    }
+    fn reset(&mut self) -> Observation {
+      self.step = 0;
+      ArrayBase::from(vec![0.0]).into_dyn()
+    }
+    fn use_seed(&mut self, _seed: u64) {}
+  }
+
+  impl ReplayableGymEnvironment for SequenceEnv {
+    fn get_used_seed(&mut self) -> u64 {0}
+    fn get_config(&mut self) -> HashMap<String, String> {HashMap::new()}
+    fn load_config(&mut self, _config: &HashMap<String, String>) {}
+    fn get_name(&self) -> String {"sequence".to_owned()}
+    fn finalize(&mut self, _algo_name: &str, _eval_run_id: &str) {}
+  }
+
+  fn optimise(method: &str) -> EvolutionaryAlgorithm {
+    let env = SequenceEnv {targets: vec![0.5, -0.5, 0.9, 0.0], step: 0};
+    let mut algo = EvolutionaryAlgorithm::new(env.action_space(), EvolutionHyperparams::default());
+    let mut config = HashMap::new();
+    config.insert("method".to_owned(), method.to_owned());
+    config.insert("sequence_length".to_owned(), "4".to_owned());
+    algo.load_hyperparams(&config);
+    algo.train_on_env(Box::new(env), Some(1), 20000);
+    algo
+  }
+
+  #[test]
+  fn both_methods_find_the_target_sequence() {
+    for method in ["cma_es", "ga"] {
+      let mut algo = optimise(method);
+      assert_eq!(algo.get_name(), format!("{}_instance", method));
+      assert!(algo.best_fitness > -0.1, "{} only reached {}", method, algo.best_fitness);
+      let first = algo.act(ArrayBase::from(vec![0.0]).into_dyn());
+      assert!((first[0] - 0.5).abs() < 0.1);
+      algo.act(ArrayBase::from(vec![1.0]).into_dyn());
+      algo.end_episode();
+      assert_eq!(algo.act(ArrayBase::from(vec![0.0]).into_dyn()), first);
+    }
+  }
+
+  #[test]
+  fn every_run_optimises_and_evaluates_its_own_instance() {
+    let mut algo = optimise("cma_es");
+    assert_eq!(algo.evaluation_seed(), Some(1));
+    let mut instance_seeds = HashSet::new();
+    for _ in 0..3 {
+      algo.reset();
+      assert_eq!(algo.instance_seed, None);
+      algo.train_on_env(Box::new(SequenceEnv {targets: vec![0.5, -0.5, 0.9, 0.0], step: 0}), None, 100);
+      instance_seeds.insert(algo.evaluation_seed().unwrap());
+    }
+    assert_eq!(instance_seeds.len(), 3);
+    let mut config = HashMap::new();
+    config.insert("instance_seed".to_owned(), "7".to_owned());
+    algo.load_hyperparams(&config);
+    algo.train_on_env(Box::new(SequenceEnv {targets: vec![0.5], step: 0}), None, 100);
+    assert_eq!(algo.evaluation_seed(), Some(7));
+    algo.params.target = EvolutionTarget::OpenLoop;
+    assert_eq!(algo.evaluation_seed(), None);
+  }
+
+  #[test]
+  fn discrete_genes_cover_every_action() {
+    let algo = EvolutionaryAlgorithm::new(Space::Discrete(3), EvolutionHyperparams {sequence_length: 4, ..Default::default()});
+    let genome = vec![0.0, 0.34, 0.99, 1.5];
+    let actions: Vec<f64> = (0..5).map(|step| algo.decode_action(&genome, step)[0]).collect();
+    assert_eq!(actions, vec![0.0, 1.0, 2.0, 2.0, 0.0]);
+  }
+}
@@ -1,5 +1,6 @@
 pub use crate::space::Space;
 use ndarray::ArrayD;
+use std::collections::HashMap;
 
 pub type Observation = ArrayD<f64>;
 pub type Action = ArrayD<f64>;
@@ -7,12 +8,63 @@ pub type Reward = f64;
 pub type RewardVector = Vec<Reward>;
 pub type EpisodeRewards = Vec<RewardVector>;
 
+/** Additional information of a step, passed on to python as the info dict */
+pub type StepInfo = HashMap<String, InfoValue>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfoValue {
+  Bool(bool),
+  Int(i64),
+  Float(f64),
+  Text(String),
+  Map(StepInfo)
+}
+
+impl From<bool> for InfoValue {
+  fn from(value: bool) -> InfoValue {InfoValue::Bool(value)}
+}
+impl From<i64> for InfoValue {
+  fn from(value: i64) -> InfoValue {InfoValue::Int(value)}
+}
+impl From<u64> for InfoValue {
+  fn from(value: u64) -> InfoValue {InfoValue::Int(value as i64)}
+}
+impl From<usize> for InfoValue {
+  fn from(value: usize) -> InfoValue {InfoValue::Int(value as i64)}
+}
+impl From<f64> for InfoValue {
+  fn from(value: f64) -> InfoValue {InfoValue::Float(value)}
+}
+impl From<f32> for InfoValue {
+  fn from(value: f32) -> InfoValue {InfoValue::Float(value as f64)}
+}
+impl From<String> for InfoValue {
+  fn from(value: String) -> InfoValue {InfoValue::Text(value)}
+}
+impl From<&str> for InfoValue {
+  fn from(value: &str) -> InfoValue {InfoValue::Text(value.to_owned())}
+}
+impl From<StepInfo> for InfoValue {
+  fn from(value: StepInfo) -> InfoValue {InfoValue::Map(value)}
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Step {
   pub obs: Observation,
   pub action: Action,
   pub reward: f64,
+  /** The episode is over, either because it terminated or because it was truncated */
   pub is_done: bool,
+  /** The episode was cut off by a limit (e.g. a step limit) instead of reaching a terminal state */
+  pub is_truncated: bool,
+  pub info: StepInfo,
+}
+
+impl Step {
+  /** Whether a terminal state was reached, in which case the value of the next observation must not be bootstrapped */
+  pub fn is_terminated(&self) -> bool {
+    self.is_done && !self.is_truncated
+  }
 }
 
 pub trait GymEnvironment {
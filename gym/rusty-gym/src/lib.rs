@@ -13,6 +13,15 @@ pub use algo::*;
 pub mod replay;
 #[cfg(feature = "replay")]
 pub use replay::*;
+#[cfg(feature = "replay")]
+pub mod vec_env;
+#[cfg(feature = "replay")]
+pub use vec_env::*;
+
+#[cfg(feature = "evolution")]
+pub mod evolution;
+#[cfg(feature = "evolution")]
+pub use evolution::*;
 
 #[cfg(feature = "reset")]
 pub mod reset;
@@ -52,6 +52,26 @@ pub trait ReplayableGymEnvironment: GymEnvironment {
   fn get_used_seed(&mut self) -> u64;
   fn get_config(&mut self) -> HashMap<String, String>;
   fn load_config(&mut self, config: &HashMap<String, String>);
+  /**
+   * Applies only the given keys on top of the current config, e.g. to change it between episodes.
+   * Environments that can validate their config should report problems instead of panicking.
+   */
+  fn update_config(&mut self, config: &HashMap<String, String>) -> Result<(), String> {
+    self.load_config(config);
+    Ok(())
+  }
+  /** Which discrete actions are currently valid, None if the environment does not know or the action space is continuous */
+  fn action_masks(&mut self) -> Option<Vec<bool>> {
+    None
+  }
+  /** Action of an expert with access to the true state of the environment, e.g. for imitation learning, None if there is none */
+  fn expert_action(&mut self) -> Option<Action> {
+    None
+  }
+  /** True state of the environment the expert acts on, e.g. for heuristics, None if there is none */
+  fn get_expert_state(&mut self) -> Option<HashMap<String, String>> {
+    None
+  }
   fn get_name(&self) -> String;
   fn finalize(&mut self, algo_name: &str, eval_run_id: &str);
 }
@@ -65,9 +85,16 @@ pub struct GymRecorder {
   env_seed: u64,
   start: Instant,
   pub timed: bool,
+  /** Stores the config of every episode, which is done automatically once update_config changed it */
   pub track_env_config: bool,
+  /** Set by update_config, the config of the run no longer describes every episode afterwards */
+  config_updated: bool,
+  /** Config the current episode was started with */
+  episode_env_params: Option<HashMap<String, String>>,
   pub was_done: bool,
   pub manage_seed: bool,
+  /** Seed passed to use_seed, takes precedence over the managed random seed for the next reset */
+  requested_seed: Option<u64>,
   pub run_id: String
 }
 
@@ -84,9 +111,12 @@ impl GymRecorder {
       start: Instant::now(),
       timed: false,
       track_env_config: false,
+      config_updated: false,
+      episode_env_params: None,
       was_done: false,
       run_id,
-      manage_seed
+      manage_seed,
+      requested_seed: None
     }
   }
 }
@@ -113,16 +143,19 @@ impl GymEnvironment for GymRecorder {
       let env_seed = self.get_used_seed();
       let mut data = self.data.lock().unwrap();
       let mut new_episode = EpisodeData::new(env_seed, self.episode_actions.clone());
-      if self.track_env_config {
-        new_episode.env_params = Some(self.original_env.get_config());
-      }
+      new_episode.env_params = self.episode_env_params.take();
       data.push(new_episode);
       self.episode_actions.clear();
     }
+    // Replaying loads this config and then resets with the seed, so it is taken before the reset
+    self.episode_env_params = if self.track_env_config || self.config_updated {Some(self.original_env.get_config())} else {None};
     let obs = if self.manage_seed {
-      self.use_seed(rng_with_random_seed().next_u64());
+      let seed = self.requested_seed.take().unwrap_or_else(|| rng_with_random_seed().next_u64());
+      self.env_seed = seed;
+      self.original_env.use_seed(seed);
       self.original_env.reset()
     } else {
+      self.requested_seed = None;
       let o = self.original_env.reset();
       self.env_seed = self.original_env.get_used_seed();
       o
@@ -135,6 +168,7 @@ impl GymEnvironment for GymRecorder {
   }
   fn use_seed(&mut self, seed: u64) {
     self.env_seed = seed;
+    self.requested_seed = Some(seed);
     self.original_env.use_seed(seed)
   }
 }
@@ -153,6 +187,14 @@ impl ReplayableGymEnvironment for GymRecorder {
     conf
   }
   fn load_config(&mut self, config: &HashMap<String, String>) {self.original_env.load_config(config)}
+  fn update_config(&mut self, config: &HashMap<String, String>) -> Result<(), String> {
+    self.original_env.update_config(config)?;
+    self.config_updated = true;
+    Ok(())
+  }
+  fn action_masks(&mut self) -> Option<Vec<bool>> {self.original_env.action_masks()}
+  fn expert_action(&mut self) -> Option<Action> {self.original_env.expert_action()}
+  fn get_expert_state(&mut self) -> Option<HashMap<String, String>> {self.original_env.get_expert_state()}
   fn get_name(&self) -> String {self.original_env.get_name()}
   fn finalize(&mut self, algo_name: &str, eval_run_id: &str) {
     self.original_env.finalize(algo_name, eval_run_id);
@@ -232,4 +274,33 @@ pub mod test {
       assert_eq!(orig.1[0], other.1[0]);
     }
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn managed_seed_honours_use_seed() {
+    let rust_cart = CartpoleEnv::new(None, Some(RngType::Mt19937));
+    let mut recorder = GymRecorder::new(Box::new(rust_cart), None);
+    recorder.use_seed(1234);
+    let first_obs = recorder.reset();
+    assert_eq!(recorder.get_used_seed(), 1234);
+    recorder.reset();
+    assert_ne!(recorder.get_used_seed(), 1234);
+    recorder.use_seed(1234);
+    assert_eq!(recorder.reset(), first_obs);
+  }
+
+  #[test]
+  fn updated_config_is_recorded_per_episode() {
+    let rust_cart = CartpoleEnv::new(None, Some(RngType::Mt19937));
+    let mut recorder = GymRecorder::new(Box::new(rust_cart), None);
+    collect_episode(&mut recorder, None);
+    let mut heavy_config = recorder.get_config();
+    heavy_config.insert("gravity".to_owned(), "20".to_owned());
+    recorder.update_config(&heavy_config).unwrap();
+    collect_episode(&mut recorder, None);
+    recorder.reset();
+    let episodes = recorder.data.lock().unwrap().clone_to_vec();
+    assert!(episodes[0].env_params.is_none());
+    let recorded = episodes[1].env_params.as_ref().expect("episode after update_config has its config");
+    assert_eq!(recorded.get("gravity"), Some(&"20".to_owned()));
+  }
+}
@@ -0,0 +1,410 @@
+use crate::{space::Space,
+  gym::{Action, Observation, Reward, StepInfo},
+  replay::ReplayableGymEnvironment};
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, mpsc::{channel, Sender, Receiver}};
+use std::thread::JoinHandle;
+
+/**
+ * Creates the environment with the given index inside of a VecGymEnvironment.
+ * Environments are created on the worker thread that owns them, so they do not need to be Send.
+ */
+pub type VecEnvironmentMaker = Arc<dyn Fn(usize) -> Box<dyn ReplayableGymEnvironment> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VecStep {
+  pub obs: Vec<Observation>,
+  pub rewards: Vec<Reward>,
+  pub dones: Vec<bool>,
+  /** Subset of dones where the episode was cut off by a limit instead of reaching a terminal state */
+  pub truncateds: Vec<bool>,
+  /** Observation before the automatic reset, only set for environments that finished in this step */
+  pub terminal_obs: Vec<Option<Observation>>,
+  pub infos: Vec<StepInfo>
+}
+
+enum VecCommand {
+  Reset,
+  Step(Vec<Action>),
+  Seed(u64),
+  GetConfig,
+  LoadConfig(HashMap<String, String>),
+  Finalize(String, String),
+  ActionMasks,
+  Close
+}
+
+enum VecResponse {
+  Spaces(Space, Space),
+  Observations(Vec<Observation>),
+  Steps(Vec<(Observation, Reward, bool, bool, Option<Observation>, StepInfo)>),
+  Configs(Vec<HashMap<String, String>>),
+  ActionMasks(Vec<Option<Vec<bool>>>),
+  Ack,
+  /** An environment of the worker panicked while being created or used, the worker stops afterwards */
+  Failed(String)
+}
+
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+  match payload.downcast::<String>() {
+    Ok(message) => *message,
+    Err(payload) => payload.downcast_ref::<&str>().map(|message| message.to_string()).unwrap_or_else(|| "unknown panic".to_owned())
+  }
+}
+
+struct VecWorker {
+  env_indices: Vec<usize>,
+  sender: Sender<VecCommand>,
+  receiver: Receiver<VecResponse>,
+  handle: Option<JoinHandle<()>>
+}
+
+fn close_workers(workers: &mut [VecWorker]) {
+  for worker in workers.iter() {
+    let _ = worker.sender.send(VecCommand::Close);
+  }
+  for worker in workers.iter_mut() {
+    let _ = worker.receiver.recv();
+    if let Some(handle) = worker.handle.take() {
+      let _ = handle.join();
+    }
+  }
+}
+
+fn run_worker(env_indices: Vec<usize>, maker: VecEnvironmentMaker, commands: Receiver<VecCommand>, responses: Sender<VecResponse>) {
+  let created = catch_unwind(AssertUnwindSafe(|| {
+    let envs: Vec<Box<dyn ReplayableGymEnvironment>> = env_indices.iter().map(|i| maker(*i)).collect();
+    let spaces = VecResponse::Spaces(envs[0].action_space(), envs[0].observation_space());
+    (envs, spaces)
+  }));
+  let mut envs = match created {
+    Ok((envs, spaces)) => {
+      if responses.send(spaces).is_err() {
+        return;
+      }
+      envs
+    },
+    Err(payload) => {
+      let _ = responses.send(VecResponse::Failed(panic_message(payload)));
+      return;
+    }
+  };
+  while let Ok(command) = commands.recv() {
+    if let VecCommand::Close = command {
+      let _ = responses.send(VecResponse::Ack);
+      break;
+    }
+    let handled = catch_unwind(AssertUnwindSafe(|| match command {
+      VecCommand::Reset => {
+        VecResponse::Observations(envs.iter_mut().map(|env| env.reset()).collect())
+      },
+      VecCommand::Step(actions) => {
+        VecResponse::Steps(envs.iter_mut().zip(actions.iter()).map(|(env, action)| {
+          let step = env.step(action);
+          if step.is_done {
+            let next_obs = env.reset();
+            (next_obs, step.reward, true, step.is_truncated, Some(step.obs), step.info)
+          } else {
+            (step.obs, step.reward, false, false, None, step.info)
+          }
+        }).collect())
+      },
+      VecCommand::Seed(seed) => {
+        for (env, index) in envs.iter_mut().zip(env_indices.iter()) {
+          env.use_seed(seed.wrapping_add(*index as u64));
+        }
+        VecResponse::Ack
+      },
+      VecCommand::GetConfig => {
+        VecResponse::Configs(envs.iter_mut().map(|env| env.get_config()).collect())
+      },
+      VecCommand::LoadConfig(config) => {
+        for env in envs.iter_mut() {
+          env.load_config(&config);
+        }
+        VecResponse::Ack
+      },
+      VecCommand::Finalize(algo_name, eval_run_id) => {
+        for env in envs.iter_mut() {
+          env.finalize(&algo_name, &eval_run_id);
+        }
+        VecResponse::Ack
+      },
+      VecCommand::ActionMasks => {
+        VecResponse::ActionMasks(envs.iter_mut().map(|env| env.action_masks()).collect())
+      },
+      VecCommand::Close => {VecResponse::Ack}
+    }));
+    let failed = handled.is_err();
+    let response = handled.unwrap_or_else(|payload| VecResponse::Failed(panic_message(payload)));
+    if responses.send(response).is_err() || failed {
+      break;
+    }
+  }
+}
+
+/**
+ * Owns a batch of environments which are distributed over a fixed amount of worker threads.
+ * All calls are broadcast to the workers and stepped in parallel, results are returned in environment order.
+ * Environments that are done are reset automatically, mirroring the VecEnv contract of stable-baselines3.
+ */
+pub struct VecGymEnvironment {
+  workers: Vec<VecWorker>,
+  num_envs: usize,
+  action_space: Space,
+  observation_space: Space
+}
+
+impl VecGymEnvironment {
+  /** Fails if the maker panicked for any of the environments, e.g. because of an invalid config */
+  pub fn new(num_envs: usize, num_threads_opt: Option<usize>, maker: VecEnvironmentMaker) -> Result<VecGymEnvironment, String> {
+    assert!(num_envs > 0, "VecGymEnvironment needs at least one environment");
+    let available_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let num_threads = num_threads_opt.unwrap_or(available_threads).clamp(1, num_envs);
+    let mut workers: Vec<VecWorker> = Vec::with_capacity(num_threads);
+    for thread_nr in 0..num_threads {
+      let env_indices: Vec<usize> = (thread_nr..num_envs).step_by(num_threads).collect();
+      let (command_sender, command_receiver) = channel();
+      let (response_sender, response_receiver) = channel();
+      let worker_maker = maker.clone();
+      let worker_indices = env_indices.clone();
+      let handle = std::thread::Builder::new()
+        .name(format!("vec-env-{}", thread_nr))
+        .spawn(move || run_worker(worker_indices, worker_maker, command_receiver, response_sender))
+        .expect("Able to spawn worker thread for VecGymEnvironment");
+      workers.push(VecWorker {env_indices, sender: command_sender, receiver: response_receiver, handle: Some(handle)});
+    }
+    let mut spaces: Option<(Space, Space)> = None;
+    let mut error: Option<String> = None;
+    for worker in workers.iter() {
+      match worker.receiver.recv() {
+        Ok(VecResponse::Spaces(action_space, observation_space)) => {
+          if spaces.is_none() {
+            spaces = Some((action_space, observation_space));
+          }
+        },
+        Ok(VecResponse::Failed(message)) => {error.get_or_insert(format!("Environment of VecGymEnvironment panicked on creation: {}", message));},
+        Ok(_) => {panic!("Worker of VecGymEnvironment did not report its spaces")},
+        Err(_) => {error.get_or_insert("Worker of VecGymEnvironment stopped before creating its environments".to_owned());}
+      }
+    }
+    let (action_space, observation_space) = match (spaces, error) {
+      (Some(spaces), None) => spaces,
+      (_, error) => {
+        close_workers(&mut workers);
+        return Err(error.unwrap_or_default());
+      }
+    };
+    Ok(VecGymEnvironment {workers, num_envs, action_space, observation_space})
+  }
+
+  pub fn num_envs(&self) -> usize {self.num_envs}
+  pub fn action_space(&self) -> Space {self.action_space.clone()}
+  pub fn observation_space(&self) -> Space {self.observation_space.clone()}
+
+  /** Fails once an environment panicked, the VecGymEnvironment can't be used afterwards */
+  fn broadcast(&self, make_command: impl Fn(&VecWorker) -> VecCommand) -> Result<Vec<VecResponse>, String> {
+    let mut sent = Vec::with_capacity(self.workers.len());
+    for worker in self.workers.iter() {
+      sent.push(worker.sender.send(make_command(worker)).is_ok());
+    }
+    let mut responses = Vec::with_capacity(self.workers.len());
+    let mut error: Option<String> = None;
+    for (worker, was_sent) in self.workers.iter().zip(sent) {
+      let response = if was_sent {worker.receiver.recv().ok()} else {None};
+      match response {
+        Some(VecResponse::Failed(message)) => {error.get_or_insert(format!("Environment of VecGymEnvironment panicked: {}", message));},
+        Some(response) => {responses.push(response);},
+        None => {error.get_or_insert("Worker of VecGymEnvironment stopped after an earlier failure".to_owned());}
+      }
+    }
+    match error {
+      Some(message) => Err(message),
+      None => Ok(responses)
+    }
+  }
+
+  pub fn reset(&mut self) -> Result<Vec<Observation>, String> {
+    let mut observations: Vec<Option<Observation>> = vec![None; self.num_envs];
+    let responses = self.broadcast(|_| VecCommand::Reset)?;
+    for (worker, response) in self.workers.iter().zip(responses) {
+      match response {
+        VecResponse::Observations(worker_obs) => {
+          for (index, obs) in worker.env_indices.iter().zip(worker_obs) {
+            observations[*index] = Some(obs);
+          }
+        },
+        _ => {panic!("Unexpected answer to reset of VecGymEnvironment")}
+      }
+    }
+    Ok(observations.into_iter().map(|obs| obs.unwrap()).collect())
+  }
+
+  pub fn step(&mut self, actions: &[Action]) -> Result<VecStep, String> {
+    assert_eq!(actions.len(), self.num_envs, "VecGymEnvironment needs exactly one action per environment");
+    let mut obs: Vec<Option<Observation>> = vec![None; self.num_envs];
+    let mut rewards: Vec<Reward> = vec![0.0; self.num_envs];
+    let mut dones: Vec<bool> = vec![false; self.num_envs];
+    let mut truncateds: Vec<bool> = vec![false; self.num_envs];
+    let mut terminal_obs: Vec<Option<Observation>> = vec![None; self.num_envs];
+    let mut infos: Vec<StepInfo> = vec![StepInfo::new(); self.num_envs];
+    let responses = self.broadcast(|worker| {
+      VecCommand::Step(worker.env_indices.iter().map(|i| actions[*i].clone()).collect())
+    })?;
+    for (worker, response) in self.workers.iter().zip(responses) {
+      match response {
+        VecResponse::Steps(worker_steps) => {
+          for (index, (step_obs, reward, done, truncated, terminal, info)) in worker.env_indices.iter().zip(worker_steps) {
+            obs[*index] = Some(step_obs);
+            rewards[*index] = reward;
+            dones[*index] = done;
+            truncateds[*index] = truncated;
+            terminal_obs[*index] = terminal;
+            infos[*index] = info;
+          }
+        },
+        _ => {panic!("Unexpected answer to step of VecGymEnvironment")}
+      }
+    }
+    Ok(VecStep {obs: obs.into_iter().map(|o| o.unwrap()).collect(), rewards, dones, truncateds, terminal_obs, infos})
+  }
+
+  /** Environment i receives seed + i so that the batch does not produce identical episodes */
+  pub fn use_seed(&mut self, seed: u64) -> Result<Vec<u64>, String> {
+    self.broadcast(|_| VecCommand::Seed(seed))?;
+    Ok((0..self.num_envs).map(|i| seed.wrapping_add(i as u64)).collect())
+  }
+
+  pub fn get_config(&mut self) -> Result<Vec<HashMap<String, String>>, String> {
+    let mut configs: Vec<HashMap<String, String>> = vec![HashMap::new(); self.num_envs];
+    let responses = self.broadcast(|_| VecCommand::GetConfig)?;
+    for (worker, response) in self.workers.iter().zip(responses) {
+      match response {
+        VecResponse::Configs(worker_configs) => {
+          for (index, config) in worker.env_indices.iter().zip(worker_configs) {
+            configs[*index] = config;
+          }
+        },
+        _ => {panic!("Unexpected answer to get_config of VecGymEnvironment")}
+      }
+    }
+    Ok(configs)
+  }
+
+  pub fn action_masks(&mut self) -> Result<Vec<Option<Vec<bool>>>, String> {
+    let mut masks: Vec<Option<Vec<bool>>> = vec![None; self.num_envs];
+    let responses = self.broadcast(|_| VecCommand::ActionMasks)?;
+    for (worker, response) in self.workers.iter().zip(responses) {
+      match response {
+        VecResponse::ActionMasks(worker_masks) => {
+          for (index, mask) in worker.env_indices.iter().zip(worker_masks) {
+            masks[*index] = mask;
+          }
+        },
+        _ => {panic!("Unexpected answer to action_masks of VecGymEnvironment")}
+      }
+    }
+    Ok(masks)
+  }
+
+  pub fn load_config(&mut self, config: &HashMap<String, String>) -> Result<(), String> {
+    self.broadcast(|_| VecCommand::LoadConfig(config.clone())).map(|_| ())
+  }
+
+  pub fn finalize(&mut self, algo_name: &str, eval_run_id: &str) -> Result<(), String> {
+    self.broadcast(|_| VecCommand::Finalize(algo_name.to_owned(), eval_run_id.to_owned())).map(|_| ())
+  }
+
+  pub fn close(&mut self) {
+    close_workers(&mut self.workers);
+  }
+}
+
+impl Drop for VecGymEnvironment {
+  fn drop(&mut self) {
+    if self.workers.iter().any(|worker| worker.handle.is_some()) {
+      self.close();
+    }
+  }
+}
+
+#[cfg(test)]
+pub mod test {
+  use crate::{GymEnvironment, ReplayableGymEnvironment, env::control::CartpoleEnv, util::rng::RngType};
+  use super::{VecGymEnvironment, VecEnvironmentMaker};
+  use ndarray::ArrayBase;
+  use std::sync::Arc;
+
+  #[test]
+  fn vec_env_matches_single_envs() {
+    let maker: VecEnvironmentMaker = Arc::new(|_| {
+      let env: Box<dyn ReplayableGymEnvironment> = Box::new(CartpoleEnv::new(None, Some(RngType::Mt19937)));
+      env
+    });
+    let num_envs = 5;
+    let mut vec_env = VecGymEnvironment::new(num_envs, Some(2), maker).unwrap();
+    assert_eq!(vec_env.num_envs(), num_envs);
+    let seeds = vec_env.use_seed(42).unwrap();
+    let mut obs = vec_env.reset().unwrap();
+    let mut single_envs: Vec<CartpoleEnv> = seeds.iter().map(|seed| {
+      let mut env = CartpoleEnv::new(None, Some(RngType::Mt19937));
+      env.use_seed(*seed);
+      env
+    }).collect();
+    for (env, vec_obs) in single_envs.iter_mut().zip(obs.iter()) {
+      assert_eq!(&env.reset(), vec_obs);
+    }
+    let action = ArrayBase::from(vec![1.0]).into_dyn();
+    let actions = vec![action.clone(); num_envs];
+    let mut episodes_done = 0;
+    for _ in 0..100 {
+      let vec_step = vec_env.step(&actions).unwrap();
+      for (i, env) in single_envs.iter_mut().enumerate() {
+        let step = env.step(&action);
+        assert_eq!(step.reward, vec_step.rewards[i]);
+        assert_eq!(step.is_done, vec_step.dones[i]);
+        assert_eq!(step.is_truncated, vec_step.truncateds[i]);
+        assert_eq!(step.info, vec_step.infos[i]);
+        if step.is_done {
+          episodes_done += 1;
+          assert_eq!(Some(step.obs), vec_step.terminal_obs[i]);
+          assert_eq!(env.reset(), vec_step.obs[i]);
+        } else {
+          assert_eq!(step.obs, vec_step.obs[i]);
+          assert!(vec_step.terminal_obs[i].is_none());
+        }
+      }
+      obs = vec_step.obs;
+    }
+    assert_eq!(obs.len(), num_envs);
+    assert!(episodes_done > 0);
+    vec_env.close();
+  }
+
+  #[test]
+  fn vec_env_reports_panicking_environment() {
+    let maker: VecEnvironmentMaker = Arc::new(|_| {
+      let env: Box<dyn ReplayableGymEnvironment> = Box::new(CartpoleEnv::new(None, Some(RngType::Mt19937)));
+      env
+    });
+    let mut vec_env = VecGymEnvironment::new(2, Some(2), maker).unwrap();
+    vec_env.reset().unwrap();
+    let empty_action = ArrayBase::from(Vec::<f64>::new()).into_dyn();
+    let error = vec_env.step(&[empty_action.clone(), empty_action]).unwrap_err();
+    assert!(error.contains("panicked"), "{}", error);
+    assert!(vec_env.reset().is_err());
+    vec_env.close();
+  }
+
+  #[test]
+  fn vec_env_reports_panicking_maker() {
+    let maker: VecEnvironmentMaker = Arc::new(|env_index| {
+      assert!(env_index != 2, "invalid config");
+      let env: Box<dyn ReplayableGymEnvironment> = Box::new(CartpoleEnv::new(None, Some(RngType::Mt19937)));
+      env
+    });
+    let error = VecGymEnvironment::new(4, Some(2), maker).err().unwrap();
+    assert!(error.contains("invalid config"), "{}", error);
+  }
+}